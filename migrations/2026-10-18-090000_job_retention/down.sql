@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS repo_jobs_point_idx;
+ALTER TABLE repo_jobs DROP CONSTRAINT IF EXISTS repo_jobs_point_fkey;
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+DELETE FROM repo_jobs j
+WHERE NOT EXISTS (
+    SELECT 1 FROM mounts m
+    WHERE m.owned_by = j.point_owned_by AND m.mount_name = j.point_name
+);
+
+ALTER TABLE repo_jobs
+    ADD CONSTRAINT repo_jobs_point_fkey
+    FOREIGN KEY (point_owned_by, point_name)
+    REFERENCES mounts(owned_by, mount_name)
+    ON DELETE CASCADE
+    ON UPDATE CASCADE;
+
+CREATE INDEX repo_jobs_point_idx ON repo_jobs(point_owned_by, point_name, create_date);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE repo_jobs DROP COLUMN IF EXISTS launch_tags;
+ALTER TABLE repo_jobs DROP COLUMN IF EXISTS launch_read_data;
+ALTER TABLE repo_jobs DROP COLUMN IF EXISTS launch_dry_run;
+ALTER TABLE repo_jobs DROP COLUMN IF EXISTS target_path;
+ALTER TABLE repo_jobs DROP COLUMN IF EXISTS acted_by;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE repo_jobs ADD COLUMN acted_by TEXT;
+ALTER TABLE repo_jobs ADD COLUMN target_path TEXT;
+ALTER TABLE repo_jobs ADD COLUMN launch_dry_run BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE repo_jobs ADD COLUMN launch_read_data BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE repo_jobs ADD COLUMN launch_tags TEXT[] NOT NULL DEFAULT '{}';
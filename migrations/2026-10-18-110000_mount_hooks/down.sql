@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE IF EXISTS mount_hooks;
+ALTER TABLE repo_jobs DROP COLUMN IF EXISTS logs;
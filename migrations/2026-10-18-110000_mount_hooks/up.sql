@@ -0,0 +1,17 @@
+-- Your SQL goes here
+ALTER TABLE repo_jobs ADD COLUMN logs TEXT[] NOT NULL DEFAULT '{}';
+
+CREATE TABLE mount_hooks (
+    id UUID PRIMARY KEY,
+    owned_by TEXT NOT NULL,
+    mount_name TEXT NOT NULL,
+    hook_type SMALLINT NOT NULL,
+    command TEXT[] NOT NULL,
+    timeout_secs INTEGER NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    create_date TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (owned_by, mount_name)
+        REFERENCES mounts(owned_by, mount_name)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE
+);
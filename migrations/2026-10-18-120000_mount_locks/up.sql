@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TABLE mount_locks (
+    id UUID PRIMARY KEY,
+    owned_by TEXT NOT NULL,
+    mount_name TEXT NOT NULL,
+    is_exclusive BOOLEAN NOT NULL,
+    reason TEXT NOT NULL,
+    acquired_date TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expire_date TIMESTAMP NOT NULL,
+    job_id UUID,
+    FOREIGN KEY (owned_by, mount_name)
+        REFERENCES mounts(owned_by, mount_name)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE
+);
+
+UPDATE mounts SET locked = FALSE;
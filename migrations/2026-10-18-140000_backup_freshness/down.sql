@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS repo_jobs_success_idx;
+ALTER TABLE mounts DROP COLUMN IF EXISTS is_stale;
+ALTER TABLE mounts DROP COLUMN IF EXISTS rpo_hours;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE mounts ADD COLUMN rpo_hours INTEGER;
+ALTER TABLE mounts ADD COLUMN is_stale BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX repo_jobs_success_idx ON repo_jobs (point_owned_by, point_name, end_date)
+    WHERE job_type = 0 AND job_status = 2;
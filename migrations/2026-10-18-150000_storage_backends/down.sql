@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE mounts DROP COLUMN IF EXISTS backend;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE mounts ADD COLUMN backend SMALLINT NOT NULL DEFAULT 0;
+
+-- Points created with the directory backend are recognised by their limit files.
+UPDATE mounts SET backend = 1 WHERE data_img_path LIKE '%.limit';
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE mounts DROP COLUMN IF EXISTS health_checked;
+ALTER TABLE mounts DROP COLUMN IF EXISTS health;
+ALTER TABLE mounts DROP COLUMN IF EXISTS fsck_hours;
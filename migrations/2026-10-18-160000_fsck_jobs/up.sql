@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE mounts ADD COLUMN fsck_hours INTEGER NULL;
+ALTER TABLE mounts ADD COLUMN health SMALLINT NULL;
+ALTER TABLE mounts ADD COLUMN health_checked TIMESTAMP NULL;
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE quota_reservations (
+    id UUID PRIMARY KEY,
+    user_name TEXT NOT NULL REFERENCES users(user_name) ON DELETE CASCADE ON UPDATE CASCADE,
+    mount_name TEXT NOT NULL,
+    data_bytes BIGINT NOT NULL,
+    repo_bytes BIGINT NOT NULL,
+    reason TEXT NOT NULL,
+    create_date TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expire_date TIMESTAMP NOT NULL
+);
+
+CREATE INDEX quota_reservations_user_idx ON quota_reservations (user_name);
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE capacity_samples (
+    id UUID PRIMARY KEY,
+    sample_date TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    data_used_bytes BIGINT NOT NULL,
+    repo_used_bytes BIGINT NOT NULL
+);
+
+CREATE INDEX capacity_samples_date_idx ON capacity_samples (sample_date);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users DROP COLUMN IF EXISTS default_pool;
+ALTER TABLE capacity_samples DROP COLUMN IF EXISTS pool_name;
+ALTER TABLE mounts DROP COLUMN IF EXISTS pool_name;
+DROP TABLE IF EXISTS storage_pools;
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+CREATE TABLE storage_pools (
+    pool_name TEXT PRIMARY KEY,
+    data_path TEXT NOT NULL,
+    repo_path TEXT NOT NULL,
+    capacity_bytes BIGINT NOT NULL DEFAULT 0,
+    tags TEXT[] NOT NULL DEFAULT '{}',
+    create_date TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Left unset for existing points and samples, which are assigned to the default pool
+-- on startup.
+ALTER TABLE mounts ADD COLUMN pool_name TEXT NULL
+    REFERENCES storage_pools(pool_name) ON UPDATE CASCADE;
+ALTER TABLE capacity_samples ADD COLUMN pool_name TEXT NULL
+    REFERENCES storage_pools(pool_name) ON UPDATE CASCADE ON DELETE CASCADE;
+ALTER TABLE users ADD COLUMN default_pool TEXT NULL
+    REFERENCES storage_pools(pool_name) ON UPDATE CASCADE ON DELETE SET NULL;
@@ -0,0 +1,241 @@
+use chrono::Duration;
+
+use crate::api::traits::CleanValidate;
+use crate::mounts::actions as mount_actions;
+use crate::mounts::dtos::*;
+use crate::mounts::freshness::{self, FreshnessDto};
+use crate::mounts::models::*;
+use crate::mounts::pools::{self, PostForMigrateApi, PutForPoolApi, StoragePoolDto};
+use crate::mounts::provisioning::{self, AllocationReportDto};
+use crate::mounts::reconcile::{self, PostForRepairApi, ReconcileFindingDto};
+use crate::mounts::retention::{self, JobRetention, JobTableDto};
+use crate::mounts::rustic_async::NonBlockingRustic;
+use crate::mounts::usage::{self, CapacityReportDto};
+use crate::prelude::action_prelude::*;
+use crate::webhooks::dtos::WebhookDto;
+use crate::webhooks::models::Webhook;
+
+#[admin_action]
+pub async fn get_job_table() -> Result<JobTableDto, NeptisError> {
+    retention::job_table_stats(conn).await
+}
+
+#[admin_action]
+pub async fn purge_jobs() -> Result<usize, NeptisError> {
+    retention::purge_jobs(conn, &JobRetention::from_env()).await
+}
+
+#[admin_action(Vec<RepoJob>)]
+pub async fn get_all_jobs(filter: GetForJobsApi) -> Result<Vec<RepoJobDto>, NeptisError> {
+    use crate::schema::repo_jobs::dsl::*;
+
+    let mut query = repo_jobs.into_boxed();
+    if let Some(f_user) = filter.user {
+        query = query.filter(point_owned_by.eq(f_user));
+    }
+    if let Some(f_mount) = filter.mount {
+        query = query.filter(point_name.eq(f_mount));
+    }
+    if let Some(f_type) = filter.job_type {
+        query = query.filter(job_type.eq(f_type));
+    }
+    if let Some(f_status) = filter.status {
+        query = query.filter(job_status.eq(f_status));
+    }
+    if let Some(f_actor) = filter.acted_by {
+        query = query.filter(acted_by.eq(f_actor));
+    }
+    if let Some(f_days) = filter.days {
+        query = query.filter(create_date.gt(utc_now!() - Duration::days(f_days)));
+    }
+    Ok(query
+        .order(create_date.desc())
+        .limit(filter.limit.unwrap_or(100))
+        .get_results(conn)
+        .await?)
+}
+
+#[admin_action(RepoJob)]
+pub async fn backup_mount(
+    handler: &NonBlockingRustic,
+    p_user: &str,
+    p_name: &str,
+) -> Result<RepoJobDto, NeptisError> {
+    mount_actions::launch_backup(
+        conn,
+        auth_user,
+        handler,
+        PostForBackupApi {
+            point_user: p_user.to_string(),
+            point_name: p_name.to_string(),
+            tags: None,
+            dry_run: false,
+        },
+    )
+    .await
+}
+
+#[admin_action(RepoJob)]
+pub async fn check_mount(
+    handler: &NonBlockingRustic,
+    p_user: &str,
+    p_name: &str,
+    dto: PostForCheckApi,
+) -> Result<RepoJobDto, NeptisError> {
+    mount_actions::launch_check(conn, auth_user, handler, p_user, p_name, dto).await
+}
+
+#[admin_action(RepoJob)]
+pub async fn fsck_mount(
+    p_user: &str,
+    p_name: &str,
+    dto: PostForFsckApi,
+) -> Result<RepoJobDto, NeptisError> {
+    mount_actions::launch_fsck(conn, auth_user, p_user, p_name, dto).await
+}
+
+#[admin_action(RepoJob)]
+pub async fn restore_mount(
+    handler: &NonBlockingRustic,
+    p_user: &str,
+    p_name: &str,
+    dto: PostForRestoreApi,
+) -> Result<RepoJobDto, NeptisError> {
+    mount_actions::launch_restore(conn, auth_user, handler, p_user, p_name, dto).await
+}
+
+#[admin_action(RepoJob)]
+pub async fn mark_job_cancelled(job_id: Uuid) -> Result<RepoJobDto, NeptisError> {
+    mount_actions::mark_job_cancelled(conn, auth_user, job_id).await
+}
+
+#[admin_action(RepoJob)]
+pub async fn retry_job(
+    handler: &NonBlockingRustic,
+    job_id: Uuid,
+) -> Result<RepoJobDto, NeptisError> {
+    mount_actions::retry_job(conn, auth_user, handler, job_id).await
+}
+
+#[admin_action(Mount)]
+pub async fn transfer_mount(
+    p_user: &str,
+    p_name: &str,
+    dto: PostForTransferApi,
+) -> Result<MountDto, NeptisError> {
+    mount_actions::transfer_point(conn, p_user, p_name, dto).await
+}
+
+/// Moves a point into another storage pool.
+#[admin_action(Mount)]
+pub async fn migrate_mount(
+    p_user: &str,
+    p_name: &str,
+    dto: PostForMigrateApi,
+) -> Result<MountDto, NeptisError> {
+    use crate::schema::mounts::dsl::*;
+    let point: Mount = mounts
+        .find((p_user.to_string(), p_name.to_string()))
+        .get_result(conn)
+        .await?;
+    pools::migrate_point(conn, &point, dto).await
+}
+
+#[admin_action]
+pub async fn get_all_pools() -> Result<Vec<StoragePoolDto>, NeptisError> {
+    pools::list_pools(conn).await
+}
+
+#[admin_action]
+pub async fn put_pool(p_name: &str, dto: PutForPoolApi) -> Result<StoragePoolDto, NeptisError> {
+    pools::put_pool(conn, p_name, dto).await
+}
+
+#[admin_action]
+pub async fn delete_pool(p_name: &str) -> Result<usize, NeptisError> {
+    pools::delete_pool(conn, p_name).await
+}
+
+#[admin_action(MountHook)]
+pub async fn create_hook(
+    p_user: &str,
+    p_name: &str,
+    dto: PostForHookApi,
+) -> Result<MountHookDto, NeptisError> {
+    use crate::schema::mount_hooks::dsl::*;
+    use crate::schema::mounts::dsl::mounts;
+
+    // Make sure the point exists before attaching anything to it.
+    let _: Mount = mounts
+        .find((p_user.to_string(), p_name.to_string()))
+        .get_result(conn)
+        .await?;
+    let hook = MountHook {
+        id: Uuid::new_v4(),
+        owned_by: p_user.to_string(),
+        mount_name: p_name.to_string(),
+        hook_type: dto.hook_type,
+        command: dto.command,
+        timeout_secs: dto.timeout_secs.unwrap_or(300),
+        enabled: dto.enabled.unwrap_or(true),
+        create_date: utc_now!(),
+    };
+    Ok(diesel::insert_into(mount_hooks)
+        .values(hook.validate()?)
+        .get_result(conn)
+        .await?)
+}
+
+#[admin_action]
+pub async fn delete_hook(hook_id: Uuid) -> Result<usize, NeptisError> {
+    use crate::schema::mount_hooks::dsl::*;
+    match diesel::delete(mount_hooks.find(hook_id))
+        .execute(conn)
+        .await?
+    {
+        0 => Err(NeptisError::BadRequest("The hook does not exist!".into())),
+        n => Ok(n),
+    }
+}
+
+#[admin_action(Vec<Webhook>)]
+pub async fn get_all_webhooks() -> Result<Vec<WebhookDto>, NeptisError> {
+    use crate::schema::webhooks::dsl::*;
+    Ok(webhooks.order(create_date.desc()).get_results(conn).await?)
+}
+
+/// Every point currently past its recovery-point objective, most overdue first.
+#[admin_action]
+pub async fn get_stale_mounts() -> Result<Vec<FreshnessDto>, NeptisError> {
+    let mut report: Vec<FreshnessDto> = freshness::evaluate_freshness(conn)
+        .await?
+        .into_iter()
+        .filter(|x| x.stale)
+        .collect();
+    report.sort_by(|a, b| b.overdue_hours.total_cmp(&a.overdue_hours));
+    Ok(report)
+}
+
+/// Space handed out to points compared with what they actually use on the host.
+#[admin_action]
+pub async fn get_allocation() -> Result<AllocationReportDto, NeptisError> {
+    provisioning::allocation_report(conn).await
+}
+
+/// Allocation and usage of the storage paths, per point and per user, with a forecast
+/// of when they fill up.
+#[admin_action]
+pub async fn get_capacity() -> Result<CapacityReportDto, NeptisError> {
+    usage::capacity_report(conn).await
+}
+
+/// Differences between the points in the database and what is on disk.
+#[admin_action]
+pub async fn get_reconciliation() -> Result<Vec<ReconcileFindingDto>, NeptisError> {
+    reconcile::scan(conn).await
+}
+
+#[admin_action]
+pub async fn repair_finding(dto: PostForRepairApi) -> Result<Vec<ReconcileFindingDto>, NeptisError> {
+    reconcile::repair(conn, dto).await
+}
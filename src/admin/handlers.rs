@@ -0,0 +1,313 @@
+use super::actions;
+use crate::mounts::dtos::*;
+use crate::mounts::freshness::FreshnessDto;
+use crate::mounts::pools::{PostForMigrateApi, PutForPoolApi, StoragePoolDto};
+use crate::mounts::provisioning::AllocationReportDto;
+use crate::mounts::reconcile::{PostForRepairApi, ReconcileFindingDto};
+use crate::mounts::retention::JobTableDto;
+use crate::mounts::rustic_async::NonBlockingRustic;
+use crate::mounts::usage::CapacityReportDto;
+use crate::prelude::route_prelude::*;
+use crate::webhooks::dtos::WebhookDto;
+use rocket::State;
+use uuid::Uuid;
+
+#[get("/jobs/retention")]
+async fn get_job_table(
+    mut conn: Connection<Db>,
+    auth_user: User,
+) -> Result<Json<JobTableDto>, NeptisError> {
+    Ok(Json(
+        actions::priv_get_job_table_async(&mut conn, &auth_user).await?,
+    ))
+}
+
+#[post("/jobs/purge")]
+async fn purge_jobs(
+    mut conn: Connection<Db>,
+    auth_user: User,
+) -> Result<Json<usize>, NeptisError> {
+    Ok(Json(
+        actions::priv_purge_jobs_async(&mut conn, &auth_user).await?,
+    ))
+}
+
+#[get("/jobs?<filter..>")]
+async fn get_all_jobs(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    filter: GetForJobsApi,
+) -> Result<Json<Vec<RepoJobDto>>, NeptisError> {
+    Ok(Json(
+        actions::priv_get_all_jobs_async(&mut conn, &auth_user, filter).await?,
+    ))
+}
+
+/// Only marks the job cancelled; a running job is not stopped. See
+/// `mounts::actions::mark_job_cancelled`.
+#[post("/jobs/<id>/mark-cancelled")]
+async fn mark_job_cancelled(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    id: Uuid,
+) -> Result<Json<RepoJobDto>, NeptisError> {
+    Ok(Json(
+        actions::priv_mark_job_cancelled_async(&mut conn, &auth_user, id).await?,
+    ))
+}
+
+#[post("/jobs/<id>/retry")]
+async fn retry_job(
+    mut conn: Connection<Db>,
+    handler: &State<NonBlockingRustic>,
+    auth_user: User,
+    id: Uuid,
+) -> Result<Json<RepoJobDto>, NeptisError> {
+    Ok(Json(
+        actions::priv_retry_job_async(&mut conn, &auth_user, handler.inner(), id).await?,
+    ))
+}
+
+#[post("/mounts/<user>/<name>/backup")]
+async fn post_one_backup(
+    mut conn: Connection<Db>,
+    handler: &State<NonBlockingRustic>,
+    auth_user: User,
+    user: &str,
+    name: &str,
+) -> Result<Json<RepoJobDto>, NeptisError> {
+    Ok(Json(
+        actions::priv_backup_mount_async(&mut conn, &auth_user, handler.inner(), user, name)
+            .await?,
+    ))
+}
+
+#[post("/mounts/<user>/<name>/check", data = "<dto>")]
+async fn post_one_check(
+    mut conn: Connection<Db>,
+    handler: &State<NonBlockingRustic>,
+    auth_user: User,
+    user: &str,
+    name: &str,
+    dto: Json<PostForCheckApi>,
+) -> Result<Json<RepoJobDto>, NeptisError> {
+    Ok(Json(
+        actions::priv_check_mount_async(
+            &mut conn,
+            &auth_user,
+            handler.inner(),
+            user,
+            name,
+            dto.into_inner(),
+        )
+        .await?,
+    ))
+}
+
+#[post("/mounts/<user>/<name>/fsck", data = "<dto>")]
+async fn post_one_fsck(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    user: &str,
+    name: &str,
+    dto: Json<PostForFsckApi>,
+) -> Result<Json<RepoJobDto>, NeptisError> {
+    Ok(Json(
+        actions::priv_fsck_mount_async(&mut conn, &auth_user, user, name, dto.into_inner())
+            .await?,
+    ))
+}
+
+#[post("/mounts/<user>/<name>/restore", data = "<dto>")]
+async fn post_one_restore(
+    mut conn: Connection<Db>,
+    handler: &State<NonBlockingRustic>,
+    auth_user: User,
+    user: &str,
+    name: &str,
+    dto: Json<PostForRestoreApi>,
+) -> Result<Json<RepoJobDto>, NeptisError> {
+    Ok(Json(
+        actions::priv_restore_mount_async(
+            &mut conn,
+            &auth_user,
+            handler.inner(),
+            user,
+            name,
+            dto.into_inner(),
+        )
+        .await?,
+    ))
+}
+
+#[post("/mounts/<user>/<name>/transfer", data = "<dto>")]
+async fn transfer_one_mount(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    user: &str,
+    name: &str,
+    dto: Json<PostForTransferApi>,
+) -> Result<Json<MountDto>, NeptisError> {
+    Ok(Json(
+        actions::priv_transfer_mount_async(&mut conn, &auth_user, user, name, dto.into_inner())
+            .await?,
+    ))
+}
+
+#[post("/mounts/<user>/<name>/migrate", data = "<dto>")]
+async fn migrate_one_mount(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    user: &str,
+    name: &str,
+    dto: Json<PostForMigrateApi>,
+) -> Result<Json<MountDto>, NeptisError> {
+    Ok(Json(
+        actions::priv_migrate_mount_async(&mut conn, &auth_user, user, name, dto.into_inner())
+            .await?,
+    ))
+}
+
+#[get("/pools")]
+async fn get_all_pools(
+    mut conn: Connection<Db>,
+    auth_user: User,
+) -> Result<Json<Vec<StoragePoolDto>>, NeptisError> {
+    Ok(Json(
+        actions::priv_get_all_pools_async(&mut conn, &auth_user).await?,
+    ))
+}
+
+#[put("/pools/<name>", data = "<dto>")]
+async fn put_one_pool(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    name: &str,
+    dto: Json<PutForPoolApi>,
+) -> Result<Json<StoragePoolDto>, NeptisError> {
+    Ok(Json(
+        actions::priv_put_pool_async(&mut conn, &auth_user, name, dto.into_inner()).await?,
+    ))
+}
+
+#[delete("/pools/<name>")]
+async fn delete_one_pool(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    name: &str,
+) -> Result<(), NeptisError> {
+    actions::priv_delete_pool_async(&mut conn, &auth_user, name).await?;
+    Ok(())
+}
+
+#[post("/mounts/<user>/<name>/hooks", data = "<dto>")]
+async fn post_one_hook(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    user: &str,
+    name: &str,
+    dto: Json<PostForHookApi>,
+) -> Result<Json<MountHookDto>, NeptisError> {
+    Ok(Json(
+        actions::priv_create_hook_async(&mut conn, &auth_user, user, name, dto.into_inner())
+            .await?,
+    ))
+}
+
+#[delete("/hooks/<id>")]
+async fn delete_one_hook(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    id: Uuid,
+) -> Result<(), NeptisError> {
+    actions::priv_delete_hook_async(&mut conn, &auth_user, id).await?;
+    Ok(())
+}
+
+#[get("/webhooks")]
+async fn get_all_webhooks(
+    mut conn: Connection<Db>,
+    auth_user: User,
+) -> Result<Json<Vec<WebhookDto>>, NeptisError> {
+    Ok(Json(
+        actions::priv_get_all_webhooks_async(&mut conn, &auth_user).await?,
+    ))
+}
+
+#[get("/stale")]
+async fn get_stale_mounts(
+    mut conn: Connection<Db>,
+    auth_user: User,
+) -> Result<Json<Vec<FreshnessDto>>, NeptisError> {
+    Ok(Json(
+        actions::priv_get_stale_mounts_async(&mut conn, &auth_user).await?,
+    ))
+}
+
+#[get("/allocation")]
+async fn get_allocation(
+    mut conn: Connection<Db>,
+    auth_user: User,
+) -> Result<Json<AllocationReportDto>, NeptisError> {
+    Ok(Json(
+        actions::priv_get_allocation_async(&mut conn, &auth_user).await?,
+    ))
+}
+
+#[get("/capacity")]
+async fn get_capacity(
+    mut conn: Connection<Db>,
+    auth_user: User,
+) -> Result<Json<CapacityReportDto>, NeptisError> {
+    Ok(Json(
+        actions::priv_get_capacity_async(&mut conn, &auth_user).await?,
+    ))
+}
+
+#[get("/reconcile")]
+async fn get_reconciliation(
+    mut conn: Connection<Db>,
+    auth_user: User,
+) -> Result<Json<Vec<ReconcileFindingDto>>, NeptisError> {
+    Ok(Json(
+        actions::priv_get_reconciliation_async(&mut conn, &auth_user).await?,
+    ))
+}
+
+#[post("/reconcile", data = "<dto>")]
+async fn repair_finding(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    dto: Json<PostForRepairApi>,
+) -> Result<Json<Vec<ReconcileFindingDto>>, NeptisError> {
+    Ok(Json(
+        actions::priv_repair_finding_async(&mut conn, &auth_user, dto.into_inner()).await?,
+    ))
+}
+
+pub fn get_routes() -> Vec<Route> {
+    routes![
+        get_job_table,
+        purge_jobs,
+        get_all_jobs,
+        mark_job_cancelled,
+        retry_job,
+        post_one_backup,
+        post_one_check,
+        post_one_fsck,
+        post_one_restore,
+        transfer_one_mount,
+        migrate_one_mount,
+        get_all_pools,
+        put_one_pool,
+        delete_one_pool,
+        post_one_hook,
+        delete_one_hook,
+        get_all_webhooks,
+        get_stale_mounts,
+        get_allocation,
+        get_capacity,
+        get_reconciliation,
+        repair_finding
+    ]
+}
@@ -0,0 +1,303 @@
+use std::ffi::OsString;
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::sys::signal::{Signal, killpg};
+use nix::unistd::Pid;
+
+use crate::api::errors::NeptisError;
+use crate::get_env_or;
+
+/// What a finished command left behind. `status` is `None` when it was killed,
+/// such as after running past its timeout.
+#[derive(Debug, Clone)]
+pub struct CommandOutput {
+    pub status: Option<i32>,
+    pub timed_out: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CommandOutput {
+    pub fn success(&self) -> bool {
+        self.status == Some(0)
+    }
+}
+
+/// A command built from an argument vector. Nothing is ever passed through a shell,
+/// so arguments such as mount names cannot be interpreted as shell syntax.
+#[derive(Debug, Clone)]
+pub struct Cmd {
+    program: OsString,
+    args: Vec<OsString>,
+    envs: Vec<(String, String)>,
+    privileged: bool,
+    timeout: Option<Duration>,
+    stdin: Option<Vec<u8>>,
+}
+
+/// How long a command gets to exit between being asked to and being killed, and how
+/// long its pipes are drained for once it is gone.
+const GRACE: Duration = Duration::from_secs(5);
+
+// A leftover grandchild can hold a pipe open forever, so the reader is only ever
+// waited on for a while.
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> mpsc::Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut output = String::new();
+        if let Some(mut p) = pipe {
+            let _ = p.read_to_string(&mut output);
+        }
+        let _ = tx.send(output);
+    });
+    rx
+}
+
+// Stops the whole process group. A root child of `sudo` cannot be signalled by us, but
+// `sudo` relays SIGTERM to it, while SIGKILL would only orphan it.
+fn stop_group(child: &mut Child) {
+    let group = Pid::from_raw(child.id() as i32);
+    let _ = killpg(group, Signal::SIGTERM);
+    let deadline = Instant::now() + GRACE;
+    while Instant::now() < deadline {
+        if let Ok(Some(_)) = child.try_wait() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    let _ = killpg(group, Signal::SIGKILL);
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+impl Cmd {
+    /// A command run as the server user.
+    pub fn new(program: &str) -> Self {
+        Cmd {
+            program: program.into(),
+            args: vec![],
+            envs: vec![],
+            privileged: false,
+            timeout: Some(Duration::from_secs(get_env_or!("COMMAND_TIMEOUT", 3600))),
+            stdin: None,
+        }
+    }
+
+    /// A command run as root through `sudo`. Every invocation is logged.
+    pub fn privileged(program: &str) -> Self {
+        Cmd {
+            privileged: true,
+            ..Cmd::new(program)
+        }
+    }
+
+    pub fn arg<S: Into<OsString>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.envs.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn envs<'a, K, I>(mut self, envs: I) -> Self
+    where
+        K: AsRef<str> + 'a,
+        I: IntoIterator<Item = &'a (K, String)>,
+    {
+        self.envs.extend(
+            envs.into_iter()
+                .map(|(k, v)| (k.as_ref().to_string(), v.clone())),
+        );
+        self
+    }
+
+    /// Feeds `data` to the command's standard input. Use this for keys and other secrets,
+    /// which would otherwise show up in the process list.
+    pub fn stdin(mut self, data: Vec<u8>) -> Self {
+        self.stdin = Some(data);
+        self
+    }
+
+    /// Kill the command once it runs longer than this. `None` waits forever.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The command line for logs and errors. Environment values are left out, since
+    /// they carry secrets such as repository passwords.
+    pub fn display(&self) -> String {
+        let mut parts = vec![];
+        if self.privileged {
+            parts.push("sudo".to_string());
+        }
+        parts.extend(self.envs.iter().map(|(k, _)| format!("{}=***", k)));
+        parts.push(self.program.to_string_lossy().to_string());
+        parts.extend(self.args.iter().map(|x| x.to_string_lossy().to_string()));
+        parts.join(" ")
+    }
+
+    fn build(&self) -> Command {
+        if !self.privileged {
+            let mut c = Command::new(&self.program);
+            c.args(&self.args)
+                .envs(self.envs.iter().map(|(k, v)| (k, v)))
+                .process_group(0);
+            return c;
+        }
+        // sudo scrubs the environment, so variables are named in `--preserve-env` rather
+        // than put on the command line, where any user could read them through `ps`.
+        // The sudoers rule for the program needs `SETENV:` for this to be allowed.
+        // Its own process group lets a timeout stop everything the command started.
+        let mut c = Command::new("sudo");
+        c.arg("-n").process_group(0);
+        if !self.envs.is_empty() {
+            let keys = self.envs.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>();
+            c.arg(format!("--preserve-env={}", keys.join(",")))
+                .envs(self.envs.iter().map(|(k, v)| (k, v)));
+        }
+        c.arg("--").arg(&self.program).args(&self.args);
+        c
+    }
+
+    /// Runs the command to completion, whatever its exit status.
+    pub fn output(&self) -> Result<CommandOutput, NeptisError> {
+        if self.privileged {
+            println!("[command] {}", self.display());
+        }
+        let mut child = self
+            .build()
+            .stdin(match self.stdin {
+                Some(_) => Stdio::piped(),
+                None => Stdio::null(),
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| NeptisError::Command {
+                command: self.display(),
+                status: None,
+                stdout: String::new(),
+                stderr: format!("failed to start: {}", e),
+            })?;
+
+        if let (Some(data), Some(mut pipe)) = (self.stdin.clone(), child.stdin.take()) {
+            // Dropping the pipe once written closes it, so the command sees the end of input.
+            thread::spawn(move || pipe.write_all(&data));
+        }
+
+        // Drain both pipes on their own threads so a chatty command cannot block on a full pipe.
+        let out_t = read_pipe(child.stdout.take());
+        let err_t = read_pipe(child.stderr.take());
+
+        let deadline = self.timeout.map(|x| Instant::now() + x);
+        let mut timed_out = false;
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(s)) => break s.code(),
+                Ok(None) if deadline.is_none_or(|x| Instant::now() < x) => {
+                    thread::sleep(Duration::from_millis(50))
+                }
+                other => {
+                    timed_out = other.is_ok();
+                    stop_group(&mut child);
+                    break None;
+                }
+            }
+        };
+
+        let output = CommandOutput {
+            status,
+            timed_out,
+            stdout: out_t.recv_timeout(GRACE).unwrap_or_default(),
+            stderr: err_t.recv_timeout(GRACE).unwrap_or_default(),
+        };
+        if self.privileged && !output.success() {
+            println!(
+                "[command] {} failed ({}): {}",
+                self.display(),
+                Self::describe(&output),
+                output.stderr.trim_end()
+            );
+        }
+        Ok(output)
+    }
+
+    /// Runs the command, failing with `NeptisError::Command` unless it exits with zero.
+    /// Returns the trimmed stdout.
+    pub fn run(&self) -> Result<String, NeptisError> {
+        let output = self.output()?;
+        if output.success() {
+            return Ok(output.stdout.trim().to_string());
+        }
+        Err(NeptisError::Command {
+            command: self.display(),
+            status: output.status,
+            stdout: output.stdout.trim().to_string(),
+            stderr: match output.timed_out {
+                true => format!("timed out; {}", output.stderr.trim()),
+                false => output.stderr.trim().to_string(),
+            },
+        })
+    }
+
+    pub fn describe(output: &CommandOutput) -> String {
+        match (output.status, output.timed_out) {
+            (_, true) => "timed out".into(),
+            (Some(c), _) => format!("exit status {}", c),
+            (None, _) => "killed by a signal".into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn privileged_env_stays_off_the_command_line() {
+        let c = Cmd::privileged("restic")
+            .env("RESTIC_PASSWORD", "hunter2")
+            .args(["mount", "/repo"])
+            .build();
+        let args = c.get_args().map(|x| x.to_string_lossy().to_string()).collect::<Vec<_>>();
+        assert_eq!(args, ["-n", "--preserve-env=RESTIC_PASSWORD", "--", "restic", "mount", "/repo"]);
+        assert!(c.get_envs().any(|(k, v)| k == "RESTIC_PASSWORD" && v == Some("hunter2".as_ref())));
+    }
+
+    #[test]
+    fn display_hides_env_values() {
+        let c = Cmd::privileged("restic").env("RESTIC_PASSWORD", "hunter2").arg("check");
+        assert_eq!(c.display(), "sudo RESTIC_PASSWORD=*** restic check");
+    }
+
+    #[test]
+    fn timeout_stops_the_whole_group() {
+        // The background `sleep` keeps stdout open after the shell itself is gone.
+        let started = Instant::now();
+        let output = Cmd::new("sh")
+            .args(["-c", "sleep 30 & sleep 30"])
+            .timeout(Some(Duration::from_millis(200)))
+            .output()
+            .unwrap();
+        assert!(output.timed_out);
+        assert_eq!(output.status, None);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
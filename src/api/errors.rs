@@ -38,6 +38,29 @@ pub enum NeptisError {
     #[error("Unauthorized: {0}")]
     Unauthorized(String),
 
+    #[error("Locked: {0}")]
+    Locked(String),
+
+    #[error("Insufficient Storage: {0}")]
+    InsufficientStorage(String),
+
+    #[error("Insufficient Storage: writing {requested} more bytes would exceed the capacity of {mount_name} ({used} of {limit} bytes used)")]
+    CapacityExceeded {
+        mount_name: String,
+        requested: u64,
+        used: u64,
+        limit: u64,
+    },
+
+    #[error("Length Required: {0}")]
+    LengthRequired(String),
+
+    #[error("Payload Too Large: {0}")]
+    PayloadTooLarge(String),
+
+    #[error("Precondition Failed: {0}")]
+    PreconditionFailed(String),
+
     #[error(transparent)]
     Validation(#[from] ValidateError),
 
@@ -46,6 +69,14 @@ pub enum NeptisError {
 
     #[error("A timeout error has occurred.")]
     Timeout,
+
+    #[error("Command `{command}` failed (status {status:?}): {stderr}")]
+    Command {
+        command: String,
+        status: Option<i32>,
+        stdout: String,
+        stderr: String,
+    },
 }
 
 impl NeptisError {
@@ -62,11 +93,27 @@ impl<'r> Responder<'r, 'static> for NeptisError {
             NeptisError::InternalError(_) => Status::InternalServerError,
             NeptisError::BadRequest(_) => Status::BadRequest,
             NeptisError::Unauthorized(_) => Status::Unauthorized,
+            NeptisError::Locked(_) => Status::Locked,
+            NeptisError::InsufficientStorage(_) => Status::InsufficientStorage,
+            NeptisError::CapacityExceeded { .. } => Status::InsufficientStorage,
+            NeptisError::LengthRequired(_) => Status::LengthRequired,
+            NeptisError::PayloadTooLarge(_) => Status::PayloadTooLarge,
+            NeptisError::PreconditionFailed(_) => Status::PreconditionFailed,
             NeptisError::Validation(_) => Status::BadRequest,
             NeptisError::Timeout => Status::RequestTimeout,
             NeptisError::IoError(_) => Status::InternalServerError,
             NeptisError::RusticJob(_) => Status::InternalServerError,
+            NeptisError::Command { .. } => Status::InternalServerError,
+        };
+        let body = match self {
+            NeptisError::CapacityExceeded { requested, used, limit, .. } => json!({
+                "error": self.to_string(),
+                "requested_bytes": requested,
+                "used_bytes": used,
+                "limit_bytes": limit,
+            }),
+            _ => json!({"error": self.to_string()}),
         };
-        status::Custom(status, json!({"error": self.to_string()})).respond_to(request)
+        status::Custom(status, body).respond_to(request)
     }
 }
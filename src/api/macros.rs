@@ -23,6 +23,16 @@ macro_rules! get_env {
     };
 }
 
+#[macro_export]
+macro_rules! get_env_or {
+    ($value:expr, $default:expr) => {
+        std::env::var($value)
+            .ok()
+            .and_then(|x| x.trim().parse().ok())
+            .unwrap_or($default)
+    };
+}
+
 #[macro_export]
 macro_rules! trim {
     ($($field:expr),*) => {
@@ -65,34 +75,6 @@ macro_rules! vmax {
         }
     }};
 }
-#[macro_export]
-macro_rules! cmd {
-    ($arg:expr) => {{
-        // Delegate to the formatted version
-        cmd!("{}", $arg)
-    }};
-    ($fmt:expr, $($arg:tt)*) => {{
-        use std::process::Command;
-        let command_str = format!($fmt, $($arg)*);
-        let output = Command::new("sudo")
-            .arg("su")
-            .arg("-s")
-            .arg("/bin/bash")
-            .arg("root")
-            .arg("-c")
-            .arg(&command_str)
-            .output()
-            .ok();
-        output.and_then(|o| {
-            if o.status.success() {
-                Some(String::from_utf8_lossy(&o.stdout).trim().to_string())
-            } else {
-                None
-            }
-        })
-    }};
-}
-
 #[macro_export]
 macro_rules! utc_now {
     () => {
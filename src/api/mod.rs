@@ -1,3 +1,4 @@
+pub mod command;
 pub mod errors;
 pub mod hash;
 pub mod rolling_secret;
@@ -1,7 +1,15 @@
 use rocket::serde::json::Value;
 use serde::Serialize;
 use crate::mounts::dtos::{NodeDto, PutForXattrApi};
+use crate::mounts::freshness::FreshnessDto;
+use crate::mounts::pools::StoragePoolDto;
+use crate::mounts::provisioning::AllocationReportDto;
+use crate::mounts::reconcile::ReconcileFindingDto;
+use crate::mounts::repo_stats::RepoStatsDto;
+use crate::mounts::retention::JobTableDto;
+use crate::mounts::usage::CapacityReportDto;
 use crate::users::models::User;
+use crate::users::quota::QuotaDto;
 use crate::api::errors::*;
 
 macro_rules! setup {
@@ -30,7 +38,9 @@ macro_rules! setup {
 
 // Setup all primitive types for implementations.
 setup!(
-    u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64, bool, char, String, NodeDto, Value, (), PutForXattrApi
+    u8, u16, u32, u64, usize, i8, i16, i32, i64, isize, f32, f64, bool, char, String, NodeDto, Value, (), PutForXattrApi,
+    JobTableDto, RepoStatsDto, FreshnessDto, AllocationReportDto, ReconcileFindingDto, QuotaDto,
+    CapacityReportDto, StoragePoolDto
 );
 
 pub trait WebDtoFrom<TBase> {
@@ -22,6 +22,8 @@ mod prelude;
 mod schema;
 mod users;
 mod mounts;
+mod admin;
+mod webhooks;
 
 #[derive(Database)]
 #[database("neptis_db")]
@@ -36,8 +38,23 @@ fn rocket() -> _ {
         .attach(Db::init())
         .mount("/api/users", users::handlers::get_routes())
         .mount("/api/mounts", mounts::handlers::get_routes())
+        .mount("/api/files", mounts::handlers::get_file_routes())
+        .mount("/api/admin", admin::handlers::get_routes())
+        .mount("/api/webhooks", webhooks::handlers::get_routes())
         .manage(nb)
         .register("/", catchers![not_found, unauthorized])
+        .attach(rocket::fairing::AdHoc::try_on_ignite("Storage Backend", |rocket| {
+            Box::pin(async move {
+                // Refuse to start rather than fail on the first request using storage.
+                match mounts::storage::init_backend() {
+                    Ok(_) => Ok(rocket),
+                    Err(e) => {
+                        println!("{}", e);
+                        Err(rocket)
+                    }
+                }
+            })
+        }))
         .attach(rocket::fairing::AdHoc::on_liftoff("Database Init", |rocket| {
             Box::pin(async move {
                 if let Some(mut conn) = async {
@@ -52,7 +69,8 @@ fn rocket() -> _ {
                         last_name: "Admin".into(),
                         is_admin: true,
                         max_data_bytes: None,
-                        max_snapshot_bytes: None
+                        max_snapshot_bytes: None,
+                        default_pool: None
                     }.into();
                     let r = diesel::insert_into(users).values(c_user).execute(&mut conn).await;
                     if r.is_err() {
@@ -62,6 +80,87 @@ fn rocket() -> _ {
                 }
             })
         }))
+        .attach(rocket::fairing::AdHoc::on_liftoff("Storage Pools", |rocket| {
+            Box::pin(async move {
+                let Some(mut conn) = async {
+                    rocket.state::<Db>()?.0.get().await.ok()
+                }.await else {
+                    return;
+                };
+                if let Err(e) = mounts::pools::ensure_default_pool(&mut conn).await {
+                    println!("Failed to set up the default storage pool: {}", e);
+                }
+            })
+        }))
+        .attach(rocket::fairing::AdHoc::on_liftoff("Mount Locks", |rocket| {
+            Box::pin(async move {
+                // No job threads survive a restart, so every lock left over is stale, and
+                // cancelled jobs still waiting on their thread never finish.
+                let Some(mut conn) = async {
+                    rocket.state::<Db>()?.0.get().await.ok()
+                }.await else {
+                    return;
+                };
+                if let Err(e) = mounts::locks::clear_all_locks(&mut conn).await {
+                    println!("Failed to clear mount locks: {}", e);
+                }
+                if let Err(e) = mounts::retention::close_interrupted_jobs(&mut conn).await {
+                    println!("Failed to close interrupted jobs: {}", e);
+                }
+            })
+        }))
+        .attach(rocket::fairing::AdHoc::on_liftoff("Reconciliation", |rocket| {
+            Box::pin(async move {
+                if let Some(mut conn) = async {
+                    rocket.state::<Db>()?.0.get().await.ok()
+                }.await {
+                    mounts::reconcile::log_startup_findings(&mut conn).await;
+                }
+            })
+        }))
+        .attach(rocket::fairing::AdHoc::on_liftoff("Job Retention", |rocket| {
+            Box::pin(async move {
+                if let Some(db) = rocket.state::<Db>() {
+                    mounts::retention::spawn_job_cleanup(db.0.clone());
+                }
+            })
+        }))
+        .attach(rocket::fairing::AdHoc::on_liftoff("Backup Freshness", |rocket| {
+            Box::pin(async move {
+                if let Some(db) = rocket.state::<Db>() {
+                    mounts::freshness::spawn_freshness_monitor(db.0.clone());
+                }
+            })
+        }))
+        .attach(rocket::fairing::AdHoc::on_liftoff("Filesystem Checks", |rocket| {
+            Box::pin(async move {
+                if let Some(db) = rocket.state::<Db>() {
+                    mounts::fsck::spawn_fsck_scheduler(db.0.clone());
+                }
+            })
+        }))
+        .attach(rocket::fairing::AdHoc::on_liftoff("Capacity Samples", |rocket| {
+            Box::pin(async move {
+                if let Some(db) = rocket.state::<Db>() {
+                    mounts::usage::spawn_capacity_sampler(db.0.clone());
+                }
+            })
+        }))
+        .attach(rocket::fairing::AdHoc::on_liftoff("Idle Unmount", |rocket| {
+            Box::pin(async move {
+                if let Some(db) = rocket.state::<Db>() {
+                    mounts::idle::spawn_idle_reaper(db.0.clone());
+                }
+            })
+        }))
+        .attach(rocket::fairing::AdHoc::on_liftoff("Webhooks", |rocket| {
+            Box::pin(async move {
+                if let Some(db) = rocket.state::<Db>() {
+                    webhooks::dispatch::spawn_dispatcher(db.0.clone());
+                    webhooks::thresholds::spawn_quota_watch(db.0.clone());
+                }
+            })
+        }))
 }
 
 #[catch(404)]
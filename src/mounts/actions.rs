@@ -1,17 +1,36 @@
+use super::capacity;
 use super::dtos::*;
+use super::extract::ExtractTarget;
+use super::files::{self, FileConditions, UploadTarget};
+use super::freshness;
+use super::fsck;
+use super::idle;
+use super::locks;
+use super::luks;
 use super::models::*;
+use super::pools;
+use super::provisioning;
+use crate::users::quota;
+use super::relocate;
+use super::repo_stats::{self, RepoStatsDto};
+use super::storage::{self, MountStats};
 use super::rustic_async::NonBlockingRustic;
 use crate::api::traits::WebDtoFrom;
 use crate::mounts::rustic_async::JobLaunchInfo;
 use crate::prelude::action_prelude::*;
+use crate::webhooks::dispatch;
+use crate::webhooks::models::WebhookEvent;
 use base64::prelude::*;
 use chrono::TimeZone;
 use diesel::result;
 use nix::sys::time::TimeSpec;
 use passwords::PasswordGenerator;
+use rocket::serde::json::serde_json::json;
 use rustic_backend::BackendOptions;
 use rustic_core::BackupOptions;
+use rustic_core::CheckOptions;
 use rustic_core::PathList;
+use rustic_core::RestoreOptions;
 use rustic_core::SnapshotOptions;
 use rustic_core::{ConfigOptions, KeyOptions, Repository, RepositoryOptions};
 use serde::Serialize;
@@ -26,96 +45,18 @@ use std::path::Path;
 use std::thread;
 use std::time::Duration;
 
-pub struct MountStats {
-    pub path: String,
-    pub b_total: usize,
-    pub b_used: usize,
-    pub b_avail: usize,
-}
-
-fn get_img_block_size(img_path: &str) -> Result<usize, NeptisError> {
-    (|| {
-        cmd!("tune2fs -l {} | grep 'Block size'", img_path)?
-            .replace("Block size:", "")
-            .trim()
-            .parse::<usize>()
-            .ok()
-    })()
-    .ok_or(NeptisError::InternalError(
-        "Failed to determine block size of FS".into(),
-    ))
-}
-
-fn get_system_info(mount_path: &str) -> Result<MountStats, NeptisError> {
-    (|| {
-        if mount_path.is_empty() {
-            return None;
-        }
-        let res = cmd!("df {} -B1", mount_path)?;
-        /*
-           Filesystem        1B-blocks         Used    Available Use% Mounted on
-           /dev/nvme0n1p5 433992540160 255224807424 156646924288  62% /
-        */
-        let spl = res
-            .split_terminator("\n")
-            .map(|x| x.to_string())
-            .collect::<Vec<_>>()
-            .get(1)?
-            .split_whitespace()
-            .map(|x| x.trim().to_string())
-            .collect::<Vec<_>>();
-
-        let b_total = spl.get(1)?.parse::<usize>().ok()?;
-        let b_used = spl.get(2)?.parse::<usize>().ok()?;
-        let b_avail = spl.get(3)?.parse::<usize>().ok()?;
-
-        // Pull the block size as well.
-        Some(MountStats {
-            path: mount_path.to_string(),
-            b_total,
-            b_used,
-            b_avail,
-        })
-    })()
-    .ok_or(NeptisError::InternalError(
-        "Failed to get system info!".into(),
-    ))
-}
-
-fn ensure_single_limit(auth_user: &User, bytes: usize, is_data: bool) -> Result<(), NeptisError> {
-    if is_data {
-        if auth_user.max_data_bytes as usize <= bytes {
-            return Ok(());
-        }
-    } else {
-        if auth_user.max_snapshot_bytes as usize <= bytes {
-            return Ok(());
-        }
-    }
-    Err(NeptisError::BadRequest(
-        "Not enough space on your account!".into(),
-    ))
-}
-
-fn ensure_user_limit(auth_user: &User, d_total: usize, r_total: usize) -> Result<(), NeptisError> {
-    match d_total <= auth_user.max_data_bytes as usize
-        && r_total <= auth_user.max_snapshot_bytes as usize
-    {
-        true => Ok(()),
-        false => Err(NeptisError::BadRequest(
-            "Not enough space on your account!".into(),
-        )),
-    }
-}
-
-// Check to see if the point is mounted or not.
-fn raw_mnt_check(img_path: &str, mnt_path: &str) -> Option<()> {
-    for line in cmd!("mount -l")?.split_terminator("\n").into_iter() {
-        if line.contains(format!("{} on {} type ext4", img_path, mnt_path).as_str()) {
-            return Some(());
-        }
-    }
-    None
+fn new_repo_password() -> String {
+    PasswordGenerator::new()
+        .length(8)
+        .numbers(true)
+        .lowercase_letters(true)
+        .uppercase_letters(true)
+        .symbols(false)
+        .spaces(false)
+        .exclude_similar_characters(true)
+        .strict(true)
+        .generate_one()
+        .unwrap()
 }
 
 fn has_any_entries<P: AsRef<Path>>(dir: P) -> Option<()> {
@@ -131,7 +72,7 @@ fn has_any_entries<P: AsRef<Path>>(dir: P) -> Option<()> {
     }
 }
 
-fn ensure_point_mounted(point: &Mount, use_repo: bool) -> Result<(), NeptisError> {
+pub fn ensure_point_mounted(point: &Mount, use_repo: bool) -> Result<(), NeptisError> {
     if point.data_img_path.is_empty()
         || point.data_mnt_path.is_empty()
         || point.repo_img_path.is_empty()
@@ -150,25 +91,13 @@ fn ensure_point_mounted(point: &Mount, use_repo: bool) -> Result<(), NeptisError
     let repo_dir = format!("{}/repo", point.repo_mnt_path.as_str());
     let repo_dir_mnt = format!("{}/repo-mnt", point.repo_mnt_path.as_str());
 
-    for path in [point.data_mnt_path.as_str(), point.repo_mnt_path.as_str()] {
-        if !fs::exists(path)? {
-            fs::create_dir_all(path)?;
-        }
-    }
-
+    let storage = storage::for_point(point);
     for (img_path, mnt_path) in [
         (point.data_img_path.as_str(), point.data_mnt_path.as_str()),
         (point.repo_img_path.as_str(), point.repo_mnt_path.as_str()),
     ] {
-        if raw_mnt_check(img_path, mnt_path).is_none() {
-            // The point is not mounted - we need to mount it!
-            (|| {
-                cmd!("mount -o rw,sync,loop {0} {1}", img_path, mnt_path)?;
-                cmd!("chmod 777 {}", mnt_path)?;
-                cmd!("chmod 777 {}", img_path)?;
-                raw_mnt_check(img_path, mnt_path)
-            })()
-            .ok_or(NeptisError::InternalError("Failed to mount point!".into()))?;
+        if !storage.is_mounted(img_path, mnt_path) {
+            storage.mount(img_path, mnt_path)?;
         }
     }
 
@@ -187,13 +116,14 @@ fn ensure_point_mounted(point: &Mount, use_repo: bool) -> Result<(), NeptisError
             None => {
                 let repo_pass = point.repo_password.to_string();
                 let rd_mnt = repo_dir_mnt.clone();
+                // `restic mount` stays in the foreground for as long as the mount is up.
                 thread::spawn(move || {
-                    cmd!(
-                        "export RESTIC_PASSWORD='{0}' && restic mount -r {1} {2} --allow-other",
-                        repo_pass.as_str(),
-                        repo_dir.as_str(),
-                        rd_mnt
-                    )
+                    Cmd::privileged("restic")
+                        .env("RESTIC_PASSWORD", repo_pass.as_str())
+                        .args(["mount", "--allow-other", "-r"])
+                        .args([repo_dir, rd_mnt])
+                        .timeout(None)
+                        .run()
                 });
                 thread::sleep(Duration::from_secs(2));
                 has_any_entries(repo_dir_mnt.as_str())
@@ -223,6 +153,32 @@ impl WebDtoFrom<RepoJob> for RepoJobDto {
             errors: item.errors.clone(),
             create_date: item.create_date.clone(),
             end_date: item.end_date.clone(),
+            acted_by: item.acted_by.clone(),
+            target_path: item.target_path.clone(),
+            launch_dry_run: item.launch_dry_run,
+            launch_read_data: item.launch_read_data,
+            launch_tags: item.launch_tags.clone(),
+            logs: item.logs.clone(),
+        })
+    }
+}
+impl WebDtoFrom<MountHook> for MountHookDto {
+    fn try_to_dto(auth_user: &User, item: MountHook) -> Result<Self, NeptisError>
+    where
+        Self: Serialize + Sized,
+    {
+        if !auth_user.is_admin && auth_user.user_name != item.owned_by {
+            return Err(NeptisError::Unauthorized("You do not have access!".into()));
+        }
+        Ok(Self {
+            id: item.id,
+            owned_by: item.owned_by,
+            mount_name: item.mount_name,
+            hook_type: item.hook_type,
+            command: item.command,
+            timeout_secs: item.timeout_secs,
+            enabled: item.enabled,
+            create_date: item.create_date,
         })
     }
 }
@@ -239,9 +195,13 @@ impl WebDtoFrom<Mount> for MountDto {
         }
         let mut d_used: Option<i64> = None;
         let mut r_used: Option<i64> = None;
-        if ensure_point_mounted(&item, true).is_ok() {
-            d_used = Some(get_system_info(item.data_mnt_path.as_str())?.b_used as i64);
-            r_used = Some(get_system_info(item.repo_mnt_path.as_str())?.b_used as i64);
+        // Listing points must not remount the ones unmounted for being idle.
+        let storage = storage::for_point(&item);
+        if !item.locked && storage.is_mounted(&item.data_img_path, &item.data_mnt_path) {
+            d_used = Some(storage.usage(&item.data_img_path, &item.data_mnt_path)?.b_used as i64);
+        }
+        if !item.locked && storage.is_mounted(&item.repo_img_path, &item.repo_mnt_path) {
+            r_used = Some(storage.usage(&item.repo_img_path, &item.repo_mnt_path)?.b_used as i64);
         }
         // Attempt to pull the information - only if it is mounted.
         Ok(MountDto {
@@ -254,6 +214,14 @@ impl WebDtoFrom<Mount> for MountDto {
             date_created: item.date_created,
             repo_accessed: item.repo_accessed,
             data_accessed: item.data_accessed,
+            locked: item.locked,
+            rpo_hours: item.rpo_hours,
+            stale: item.is_stale,
+            backend: item.backend,
+            fsck_hours: item.fsck_hours,
+            health: item.health,
+            health_checked: item.health_checked,
+            pool_name: item.pool_name,
         })
     }
 }
@@ -284,12 +252,10 @@ pub async fn delete_one_mount(p_name: &str) -> Result<usize, NeptisError> {
         .find((auth_user.user_name.clone(), p_name.to_string()))
         .get_result(conn)
         .await?;
-    if p_mount.locked {
-        return Err(NeptisError::InternalError(
-            "The point is currently locked".into(),
-        ));
-    }
+    // The lock row goes away with the point itself, so it is only released on failure.
+    let lock_id = locks::acquire_lock(conn, &p_mount, true, "deletion").await?;
 
+    // Any jobs for the point are removed by the `repo_jobs_point_fkey` cascade.
     if diesel::delete(mounts)
         .filter(
             owned_by
@@ -300,6 +266,7 @@ pub async fn delete_one_mount(p_name: &str) -> Result<usize, NeptisError> {
         .await?
         <= 0
     {
+        locks::release_lock(conn, lock_id).await?;
         return Err(NeptisError::BadRequest("The point does not exist!".into()));
     }
     // Delete the files and make it work!
@@ -313,15 +280,14 @@ pub async fn delete_one_mount(p_name: &str) -> Result<usize, NeptisError> {
             p_mount.repo_mnt_path.as_str(),
         ),
     ] {
-        if raw_mnt_check(i_path, m_path).is_some() {
-            cmd!("umount {}", m_path).ok_or(NeptisError::InternalError(
-                "Failed to unmount point!".into(),
-            ))?;
-        }
-        fs::remove_dir(m_path)?;
-        fs::remove_file(i_path)?;
+        storage::for_point(&p_mount).destroy(i_path, m_path)?;
     }
 
+    dispatch::emit(
+        p_mount.owned_by.as_str(),
+        WebhookEvent::MountDeleted,
+        json!({ "mount": p_mount.mount_name }),
+    );
     Ok(1)
 }
 
@@ -391,7 +357,10 @@ async fn stage_user_s2d(
         .await
         .ok()
         .ok_or(NeptisError::InternalError("Failed to pull DB".into()))?;
-    return stage_user_s2(path, user_mounts).await;
+    let (point, s2) = stage_user_s2(path, user_mounts).await?;
+    locks::ensure_unlocked(conn, &point).await?;
+    idle::touch_point(conn, &point, !s2.starts_with("repo")).await?;
+    Ok((point, s2))
 }
 
 async fn stage_user_s2(
@@ -456,6 +425,160 @@ pub async fn delete_file(path: &str) -> Result<usize, NeptisError> {
     Ok(1)
 }
 
+/// Resolves a directory to archive, which may be in the data area or inside a snapshot.
+#[action]
+pub async fn stage_archive(path: &str) -> Result<String, NeptisError> {
+    if path.split('/').any(|x| x == "..") {
+        return Err(NeptisError::BadRequest(format!("Cannot resolve {}", path)));
+    }
+    let (point, mut s2) = stage_user_s2d(path, auth_user, conn).await?;
+    if !s2.contains('/') {
+        s2 = format!("{}/.", s2); // the whole data area or repository mount
+    }
+    ensure_point_mounted(&point, s2.starts_with("repo"))?;
+    let (abs_path, _) = from_rel_s2(s2.as_str(), &point)?;
+    // The walk below the root never follows links, so neither may the root itself.
+    let meta = fs::symlink_metadata(abs_path.as_str())?;
+    if meta.is_symlink() {
+        return Err(NeptisError::BadRequest(
+            "Symbolic links cannot be archived!".into(),
+        ));
+    }
+    if !meta.is_dir() {
+        return Err(NeptisError::BadRequest(
+            "Only directories can be archived!".into(),
+        ));
+    }
+    Ok(abs_path)
+}
+
+/// The short ID `restic mount` names a snapshot's directory by, from any unique prefix
+/// of its ID (or `latest`).
+#[action]
+pub async fn get_snapshot_dir_name(p_name: &str, snap: &str) -> Result<String, NeptisError> {
+    use crate::schema::mounts::dsl::*;
+    let f_point: Mount = mounts
+        .find((auth_user.user_name.clone(), p_name.to_string()))
+        .get_result(conn)
+        .await?;
+    ensure_point_mounted(&f_point, false)?;
+
+    let repo_dir = format!("{}/repo", f_point.repo_mnt_path.as_str());
+    let repo_pass = f_point.repo_password.clone();
+    let snap = snap.to_string();
+    rocket::tokio::task::spawn_blocking(move || -> Result<String, NeptisError> {
+        let backends = BackendOptions::default()
+            .repository(repo_dir.as_str())
+            .to_backends()?;
+        let repo_opts = RepositoryOptions::default().password(repo_pass.as_str());
+        let s_file = Repository::new(&repo_opts, &backends)?
+            .open()?
+            .get_snapshot_from_str(snap.as_str(), |_| true)?;
+        // `Id` displays as the same eight hex digits restic shortens IDs to.
+        Ok(s_file.id.to_string())
+    })
+    .await
+    .map_err(|e| NeptisError::InternalError(e.to_string()))?
+}
+
+/// Resolves the directory an archive upload is extracted into, creating it if needed.
+/// A shared lock is held on the point until the caller releases it.
+pub async fn stage_extract(
+    conn: &mut AsyncPgConnection,
+    auth_user: &User,
+    path: &str,
+) -> Result<ExtractTarget, NeptisError> {
+    if path.split('/').any(|x| x == "..") {
+        return Err(NeptisError::BadRequest(format!("Cannot resolve {}", path)));
+    }
+    let (point, mut s2) = stage_user_s2d(path, auth_user, conn).await?;
+    if s2 == "data" {
+        s2 = "data/.".into();
+    }
+    if !s2.starts_with("data/") {
+        return Err(NeptisError::BadRequest(
+            "Archives can only be extracted into the data area!".into(),
+        ));
+    }
+    ensure_point_mounted(&point, false)?;
+    provisioning::ensure_host_headroom(&point, true)?;
+    let (abs_path, _) = from_rel_s2(s2.as_str(), &point)?;
+    fs::create_dir_all(abs_path.as_str())?;
+
+    let capacity = capacity::data_capacity(&point)?;
+    let lock_id = locks::acquire_lock(conn, &point, false, "extraction").await?;
+    Ok(ExtractTarget {
+        root: abs_path.into(),
+        capacity,
+        // Leave room for archive headers and padding over the extracted size.
+        limit: (point.data_max_bytes.max(0) as u64).saturating_mul(2),
+        lock_id,
+    })
+}
+
+/// Resolves a file to download, which may be in the data area or inside a snapshot.
+#[action]
+pub async fn stage_download(path: &str) -> Result<String, NeptisError> {
+    if path.split('/').any(|x| x == "..") {
+        return Err(NeptisError::BadRequest(format!("Cannot resolve {}", path)));
+    }
+    let (point, s2) = stage_user_s2d(path, auth_user, conn).await?;
+    ensure_point_mounted(&point, s2.starts_with("repo"))?;
+    let (abs_path, _) = from_rel_s2(s2.as_str(), &point)?;
+    if !fs::metadata(abs_path.as_str())?.is_file() {
+        return Err(NeptisError::BadRequest(
+            "Only files can be downloaded!".into(),
+        ));
+    }
+    Ok(abs_path)
+}
+
+/// Resolves the file a streamed upload of `len` bytes at `offset` is written to, and
+/// checks that it fits in the data area. A shared lock is held on the point until the
+/// caller releases it.
+pub async fn stage_upload(
+    conn: &mut AsyncPgConnection,
+    auth_user: &User,
+    path: &str,
+    offset: Option<u64>,
+    len: u64,
+    cond: &FileConditions,
+) -> Result<UploadTarget, NeptisError> {
+    if path.split('/').any(|x| x == "..") {
+        return Err(NeptisError::BadRequest(format!("Cannot resolve {}", path)));
+    }
+    let (point, s2) = stage_user_s2d(path, auth_user, conn).await?;
+    if !s2.starts_with("data/") {
+        return Err(NeptisError::BadRequest(
+            "Files can only be uploaded into the data area!".into(),
+        ));
+    }
+    provisioning::ensure_host_headroom(&point, true)?;
+    let (abs_path, _) = from_rel_s2(s2.as_str(), &point)?;
+    ensure_point_mounted(&point, false)?;
+
+    let meta = files::existing_file(abs_path.as_str())?;
+    files::ensure_matches(cond, meta.as_ref())?;
+    // A whole file is staged next to the one it replaces, so it counts in full.
+    let growth = match offset {
+        Some(n) => capacity::write_growth(
+            meta.as_ref().map_or(0, |x| x.len()),
+            Some(SeekFrom::Start(n)),
+            len,
+        ),
+        None => len,
+    };
+    let cap = capacity::data_capacity(&point)?;
+    cap.ensure_fits(growth)?;
+
+    let lock_id = locks::acquire_lock(conn, &point, false, "upload").await?;
+    Ok(UploadTarget {
+        path: abs_path.into(),
+        capacity: cap,
+        lock_id,
+    })
+}
+
 #[action]
 pub async fn dump_file(dto: GetForDumpApi) -> Result<String, NeptisError> {
     // Convert to a relative path.
@@ -477,14 +600,15 @@ pub async fn dump_file(dto: GetForDumpApi) -> Result<String, NeptisError> {
 #[action]
 pub async fn post_file(dto: PostForFileApi) -> Result<(), NeptisError> {
     // First, attempt to run the main function - then rename if required.
-    let (abs_path, is_rw) = stage_user_s2d(dto.path.as_str(), auth_user, conn)
-        .await
-        .and_then(|(a, b)| from_rel_s2(b.as_str(), &a))?;
+    let (point, s2) = stage_user_s2d(dto.path.as_str(), auth_user, conn).await?;
+    provisioning::ensure_host_headroom(&point, true)?;
+    let (abs_path, is_rw) = from_rel_s2(s2.as_str(), &point)?;
     if !is_rw {
         return Err(NeptisError::BadRequest(
             "This requested file is read-only!".into(),
         ));
     }
+    ensure_point_mounted(&point, false)?;
 
     let a_path = abs_path.as_str();
     if fs::exists(a_path)? {
@@ -507,16 +631,20 @@ pub async fn post_file(dto: PostForFileApi) -> Result<(), NeptisError> {
             .map(|x| BASE64_STANDARD.decode(x).ok())
             .and_then(|x| x)
             .unwrap_or_else(Vec::new);
+        let offset: Option<SeekFrom> = dto.offset.map(Into::into);
+        let cap = capacity::data_capacity(&point)?;
+        cap.ensure_fits(capacity::write_growth(0, offset, decoded_data.len() as u64))?;
         let mut file = OpenOptions::new()
             .write(true)
             .create_new(true)
             .open(a_path)?;
 
-        if let Some(offset) = dto.offset {
-            file.seek(offset.into())?;
+        if let Some(offset) = offset {
+            file.seek(offset)?;
         }
 
-        file.write_all(decoded_data.as_slice())?;
+        file.write_all(decoded_data.as_slice())
+            .map_err(|e| cap.map_write_error(e))?;
         return Ok(());
     }
 }
@@ -526,7 +654,10 @@ pub async fn put_xattr(dto: PutForXattrApi) -> Result<(), NeptisError> {
     // First, attempt to run the main function - then rename if required.
     let (abs_path, is_rw) = stage_user_s2d(dto.path.as_str(), auth_user, conn)
         .await
-        .and_then(|(a, b)| from_rel_s2(b.as_str(), &a))?;
+        .and_then(|(a, b)| {
+            provisioning::ensure_host_headroom(&a, true)?;
+            from_rel_s2(b.as_str(), &a)
+        })?;
     if !is_rw {
         return Err(NeptisError::BadRequest(
             "This requested file is read-only!".into(),
@@ -606,14 +737,15 @@ pub async fn put_file(dto: PutForFileApi) -> Result<(), NeptisError> {
         }
     }
     // First, attempt to run the main function - then rename if required.
-    let (abs_path, is_rw) = stage_user_s2d(dto.path.as_str(), auth_user, conn)
-        .await
-        .and_then(|(a, b)| from_rel_s2(b.as_str(), &a))?;
+    let (point, s2) = stage_user_s2d(dto.path.as_str(), auth_user, conn).await?;
+    provisioning::ensure_host_headroom(&point, true)?;
+    let (abs_path, is_rw) = from_rel_s2(s2.as_str(), &point)?;
     if !is_rw {
         return Err(NeptisError::BadRequest(
             "This requested file is read-only!".into(),
         ));
     }
+    ensure_point_mounted(&point, false)?;
     let a_path = abs_path.as_str();
 
     if !fs::exists(a_path)? {
@@ -626,17 +758,31 @@ pub async fn put_file(dto: PutForFileApi) -> Result<(), NeptisError> {
         ));
     }
 
-    if let Some(b_vec) = dto
+    let b_vec = dto
         .base64
         .map(|x| BASE64_STANDARD.decode(x).ok())
-        .and_then(|x| x)
-    {
+        .and_then(|x| x);
+    let offset: Option<SeekFrom> = dto.offset.map(Into::into);
+    let n_size = dto.attr.as_ref().and_then(|x| x.size);
+
+    // Check the furthest the file can end up, from either the write or a truncation.
+    let cap = capacity::data_capacity(&point)?;
+    let cur_len = fs::metadata(a_path)?.len();
+    let w_growth = b_vec
+        .as_ref()
+        .map(|x| capacity::write_growth(cur_len, offset, x.len() as u64))
+        .unwrap_or(0);
+    let t_growth = n_size.map(|x| x.saturating_sub(cur_len)).unwrap_or(0);
+    cap.ensure_fits(w_growth.max(t_growth))?;
+
+    if let Some(b_vec) = b_vec {
         let mut file = OpenOptions::new().write(true).open(a_path)?;
-        if let Some(offset) = dto.offset {
-            file.seek(offset.into())?;
+        if let Some(offset) = offset {
+            file.seek(offset)?;
         }
-        file.write_all(b_vec.as_slice())?;
-        file.flush()?;
+        file.write_all(b_vec.as_slice())
+            .and_then(|_| file.flush())
+            .map_err(|e| cap.map_write_error(e))?;
     }
 
     let mut use_path = a_path.to_string();
@@ -681,7 +827,7 @@ pub async fn put_file(dto: PutForFileApi) -> Result<(), NeptisError> {
         // Finally, set the size of the file if necessary.
         if let Some(size) = r_attr.size {
             let file = OpenOptions::new().write(true).open(&use_path)?;
-            file.set_len(size)?;
+            file.set_len(size).map_err(|e| cap.map_write_error(e))?;
         }
     }
     Ok(())
@@ -766,6 +912,10 @@ pub async fn browse_file(t_path: &str, depth: u16) -> Result<Vec<NodeDto>, Nepti
     let allowed_depth = starting_depth + depth as usize;
 
     for mount in user_mounts {
+        // A point under an exclusive lock may be unmounted on purpose; leave it alone.
+        if locks::ensure_unlocked(conn, &mount).await.is_err() {
+            continue;
+        }
         ensure_point_mounted(&mount, true)?;
 
         let mount_root = format!("/{}", mount.mount_name);
@@ -858,39 +1008,685 @@ pub async fn get_all_jobs(p_name: &str) -> Result<Vec<RepoJobDto>, NeptisError>
         .await?)
 }
 
+// Pull a point for a job, making sure the caller is either the owner or an admin.
+async fn find_job_point(
+    conn: &mut AsyncPgConnection,
+    auth_user: &User,
+    p_user: &str,
+    p_name: &str,
+) -> Result<Mount, NeptisError> {
+    use crate::schema::mounts::dsl::*;
+    if !auth_user.is_admin && auth_user.user_name != p_user {
+        return Err(NeptisError::Unauthorized("You do not have access!".into()));
+    }
+    Ok(mounts
+        .find((p_user.to_string(), p_name.to_string()))
+        .get_result(conn)
+        .await?)
+}
+
+fn to_launch_info(auth_user: &User, point: &Mount) -> JobLaunchInfo {
+    JobLaunchInfo {
+        point_owned_by: point.owned_by.clone(),
+        point_name: point.mount_name.clone(),
+        data_path: point.data_mnt_path.clone(),
+        repo_path: format!("{}/repo", point.repo_mnt_path.as_str()),
+        repo_pass: point.repo_password.clone(),
+        acted_by: if auth_user.user_name != point.owned_by {
+            Some(auth_user.user_name.clone())
+        } else {
+            None
+        },
+        lock_id: None,
+    }
+}
+
+pub async fn launch_backup(
+    conn: &mut AsyncPgConnection,
+    auth_user: &User,
+    handler: &NonBlockingRustic,
+    dto: PostForBackupApi,
+) -> Result<RepoJob, NeptisError> {
+    use crate::schema::repo_jobs::dsl::*;
+
+    let f_point = find_job_point(conn, auth_user, &dto.point_user, &dto.point_name).await?;
+    let p_hooks: Vec<MountHook> = {
+        use crate::schema::mount_hooks::dsl::*;
+        mount_hooks
+            .filter(
+                owned_by
+                    .eq(f_point.owned_by.as_str())
+                    .and(mount_name.eq(f_point.mount_name.as_str())),
+            )
+            .order(create_date.asc())
+            .get_results(conn)
+            .await?
+    };
+
+    provisioning::ensure_host_headroom(&f_point, false)?;
+    idle::touch_point(conn, &f_point, true).await?;
+    idle::touch_point(conn, &f_point, false).await?;
+
+    // Backups only read the data area, so they can share the point with each other.
+    let lock_id = locks::acquire_lock(conn, &f_point, false, "backup").await?;
+    let mut options = to_launch_info(auth_user, &f_point);
+    options.lock_id = Some(lock_id);
+
+    let ret = (|| {
+        ensure_point_mounted(&f_point, false)?;
+        let b_opts = BackupOptions::default().dry_run(dto.dry_run);
+        let source = PathList::from_string(f_point.data_mnt_path.as_str())?
+            .sanitize()
+            .unwrap();
+        let mut s_opts = SnapshotOptions::default();
+        if let Some(ref tags) = dto.tags {
+            s_opts = s_opts.add_tags(tags.join(",").as_str())?;
+        }
+        handler.start_backup(&options, source, s_opts, b_opts, p_hooks)
+    })();
+    if ret.is_err() {
+        locks::release_lock(conn, lock_id).await?;
+    }
+
+    // Finally, return the job information.
+    Ok(repo_jobs.find(ret?).get_result(conn).await?)
+}
+
+pub async fn launch_check(
+    conn: &mut AsyncPgConnection,
+    auth_user: &User,
+    handler: &NonBlockingRustic,
+    p_user: &str,
+    p_name: &str,
+    dto: PostForCheckApi,
+) -> Result<RepoJob, NeptisError> {
+    use crate::schema::repo_jobs::dsl::*;
+
+    let f_point = find_job_point(conn, auth_user, p_user, p_name).await?;
+    idle::touch_point(conn, &f_point, false).await?;
+    let lock_id = locks::acquire_lock(conn, &f_point, false, "check").await?;
+    let mut options = to_launch_info(auth_user, &f_point);
+    options.lock_id = Some(lock_id);
+
+    let ret = ensure_point_mounted(&f_point, false).and_then(|_| {
+        handler.start_check(&options, CheckOptions::default().read_data(dto.read_data))
+    });
+    if ret.is_err() {
+        locks::release_lock(conn, lock_id).await?;
+    }
+    Ok(repo_jobs.find(ret?).get_result(conn).await?)
+}
+
+/// Checks the filesystems of both areas of a point, repairing them when asked to.
+pub async fn launch_fsck(
+    conn: &mut AsyncPgConnection,
+    auth_user: &User,
+    p_user: &str,
+    p_name: &str,
+    dto: PostForFsckApi,
+) -> Result<RepoJob, NeptisError> {
+    use crate::schema::repo_jobs::dsl::*;
+
+    let f_point = find_job_point(conn, auth_user, p_user, p_name).await?;
+    fsck::ensure_checkable(&f_point)?;
+
+    // Both areas are unmounted for the check, so nothing else may use the point.
+    let lock_id = locks::acquire_lock(conn, &f_point, true, "fsck").await?;
+    let mut options = to_launch_info(auth_user, &f_point);
+    options.lock_id = Some(lock_id);
+
+    let ret = fsck::start_fsck(&options, &f_point, dto.repair);
+    if ret.is_err() {
+        locks::release_lock(conn, lock_id).await?;
+    }
+    Ok(repo_jobs.find(ret?).get_result(conn).await?)
+}
+
+pub async fn launch_restore(
+    conn: &mut AsyncPgConnection,
+    auth_user: &User,
+    handler: &NonBlockingRustic,
+    p_user: &str,
+    p_name: &str,
+    dto: PostForRestoreApi,
+) -> Result<RepoJob, NeptisError> {
+    use crate::schema::repo_jobs::dsl::*;
+
+    let f_point = find_job_point(conn, auth_user, p_user, p_name).await?;
+    let rel_path = dto.target_path.unwrap_or_default();
+    if rel_path.split('/').any(|x| x == "..") {
+        return Err(NeptisError::BadRequest(
+            "The restore target must stay inside the data area!".into(),
+        ));
+    }
+
+    provisioning::ensure_host_headroom(&f_point, true)?;
+    idle::touch_point(conn, &f_point, true).await?;
+    idle::touch_point(conn, &f_point, false).await?;
+
+    // Restores write into the data area, so nothing else may touch the point meanwhile.
+    let lock_id = locks::acquire_lock(conn, &f_point, true, "restore").await?;
+    let mut options = to_launch_info(auth_user, &f_point);
+    options.lock_id = Some(lock_id);
+
+    let ret = ensure_point_mounted(&f_point, false).and_then(|_| {
+        handler.start_full_restore(
+            &options,
+            dto.snapshot_path.as_str(),
+            rel_path.as_str(),
+            RestoreOptions::default(),
+            dto.dry_run,
+        )
+    });
+    if ret.is_err() {
+        locks::release_lock(conn, lock_id).await?;
+    }
+    Ok(repo_jobs.find(ret?).get_result(conn).await?)
+}
+
+/// Marks a job as cancelled without stopping it. rustic offers no way to interrupt an
+/// operation, so a job that is already running carries on in the background and only
+/// has its outcome noted; this is for clearing jobs which are stuck or no longer wanted.
+/// The point stays locked, and `end_date` unset, until the job's thread actually finishes.
+pub async fn mark_job_cancelled(
+    conn: &mut AsyncPgConnection,
+    auth_user: &User,
+    job_id: Uuid,
+) -> Result<RepoJob, NeptisError> {
+    use crate::schema::repo_jobs::dsl::*;
+
+    let mut f_job: RepoJob = repo_jobs.find(job_id).get_result(conn).await?;
+    find_job_point(conn, auth_user, &f_job.point_owned_by, &f_job.point_name).await?;
+    if f_job.job_status != JobStatus::Running && f_job.job_status != JobStatus::NotStarted {
+        return Err(NeptisError::BadRequest("The job is not running!".into()));
+    }
+    f_job.job_status = JobStatus::Cancelled;
+    f_job.acted_by = Some(auth_user.user_name.clone());
+    f_job
+        .errors
+        .push(format!("Cancelled by {}", auth_user.user_name));
+    Ok(diesel::update(repo_jobs.find(job_id))
+        .set(&f_job)
+        .get_result(conn)
+        .await?)
+}
+
+/// Launches a new job with the same type, target and options as a finished one.
+pub async fn retry_job(
+    conn: &mut AsyncPgConnection,
+    auth_user: &User,
+    handler: &NonBlockingRustic,
+    job_id: Uuid,
+) -> Result<RepoJob, NeptisError> {
+    use crate::schema::repo_jobs::dsl::*;
+
+    let f_job: RepoJob = repo_jobs.find(job_id).get_result(conn).await?;
+    if f_job.job_status == JobStatus::Running || f_job.job_status == JobStatus::NotStarted {
+        return Err(NeptisError::BadRequest("The job is still running!".into()));
+    }
+    // Cancelling does not stop the work, so a second run could overlap the first.
+    if f_job.job_status == JobStatus::Cancelled && f_job.end_date.is_none() {
+        return Err(NeptisError::BadRequest(
+            "The job was cancelled but is still running in the background!".into(),
+        ));
+    }
+    match f_job.job_type {
+        JobType::Backup => {
+            launch_backup(
+                conn,
+                auth_user,
+                handler,
+                PostForBackupApi {
+                    point_user: f_job.point_owned_by,
+                    point_name: f_job.point_name,
+                    tags: Some(f_job.launch_tags).filter(|x| !x.is_empty()),
+                    dry_run: f_job.launch_dry_run,
+                },
+            )
+            .await
+        }
+        JobType::Check => {
+            launch_check(
+                conn,
+                auth_user,
+                handler,
+                &f_job.point_owned_by,
+                &f_job.point_name,
+                PostForCheckApi {
+                    read_data: f_job.launch_read_data,
+                },
+            )
+            .await
+        }
+        JobType::Restore => {
+            launch_restore(
+                conn,
+                auth_user,
+                handler,
+                &f_job.point_owned_by,
+                &f_job.point_name,
+                PostForRestoreApi {
+                    snapshot_path: f_job.snapshot_id.ok_or(NeptisError::BadRequest(
+                        "The job has no snapshot to restore!".into(),
+                    ))?,
+                    target_path: f_job.target_path,
+                    dry_run: f_job.launch_dry_run,
+                },
+            )
+            .await
+        }
+        JobType::Fsck => {
+            launch_fsck(
+                conn,
+                auth_user,
+                &f_job.point_owned_by,
+                &f_job.point_name,
+                PostForFsckApi { repair: false },
+            )
+            .await
+        }
+    }
+}
+
+#[action(Vec<MountHook>)]
+pub async fn get_all_hooks(p_name: &str) -> Result<Vec<MountHookDto>, NeptisError> {
+    use crate::schema::mount_hooks::dsl::*;
+    Ok(mount_hooks
+        .filter(
+            owned_by
+                .eq(auth_user.user_name.as_str())
+                .and(mount_name.eq(p_name)),
+        )
+        .order(create_date.asc())
+        .get_results(conn)
+        .await?)
+}
+
+#[action]
+pub async fn get_repo_stats(p_name: &str) -> Result<RepoStatsDto, NeptisError> {
+    use crate::schema::mounts::dsl::*;
+    let f_point: Mount = mounts
+        .find((auth_user.user_name.clone(), p_name.to_string()))
+        .get_result(conn)
+        .await?;
+    locks::ensure_unlocked(conn, &f_point).await?;
+    ensure_point_mounted(&f_point, false)?;
+
+    let repo_dir = format!("{}/repo", f_point.repo_mnt_path.as_str());
+    let repo_pass = f_point.repo_password.clone();
+    rocket::tokio::task::spawn_blocking(move || {
+        repo_stats::collect_repo_stats(repo_dir.as_str(), repo_pass.as_str())
+    })
+    .await
+    .map_err(|e| NeptisError::InternalError(e.to_string()))?
+}
+
 #[action(RepoJob)]
 pub async fn backup_mount(
     handler: &NonBlockingRustic,
     dto: PostForBackupApi,
 ) -> Result<RepoJobDto, NeptisError> {
-    // Attempt to validate to ensure the DTO is valid.
+    launch_backup(conn, auth_user, handler, dto).await
+}
+
+fn resize_point(
+    f_point: &Mount,
+    server_mounts: &[Mount],
+    d_max_bytes: i64,
+    r_max_bytes: i64,
+    d_sys_info: &MountStats,
+    r_sys_info: &MountStats,
+) -> Result<(), NeptisError> {
+    if d_max_bytes == f_point.data_max_bytes && r_max_bytes == f_point.repo_max_bytes {
+        return Err(NeptisError::BadRequest(
+            "No modification is necessary".into(),
+        ));
+    }
+
+    // Begin performing the re-size, and update the DB.
+    ensure_point_mounted(f_point, true)?;
+    let storage = storage::for_point(f_point);
+
+    for (n_bytes, o_bytes, is_data, mount_path, image_path) in [
+        (
+            d_max_bytes,
+            f_point.data_max_bytes,
+            true,
+            f_point.data_mnt_path.as_str(),
+            f_point.data_img_path.as_str(),
+        ),
+        (
+            r_max_bytes,
+            f_point.repo_max_bytes,
+            false,
+            f_point.repo_mnt_path.as_str(),
+            f_point.repo_img_path.as_str(),
+        ),
+    ] {
+        let b_inc = n_bytes - o_bytes;
+        if b_inc == 0 {
+            continue;
+        }
+        let s_info = storage.usage(image_path, mount_path)?;
+        if (n_bytes as usize) < s_info.b_used {
+            // Not allowed - need more free space to shrink partition.
+            return Err(NeptisError::BadRequest(
+                "Not enough free space to shrink. Please delete files and try again".into(),
+            ));
+        }
+        provisioning::ensure_can_allocate(
+            if is_data { d_sys_info } else { r_sys_info },
+            provisioning::allocated_bytes(server_mounts, is_data),
+            b_inc.max(0) as u64,
+            if is_data { "data" } else { "repo" },
+        )?;
+
+        if !is_data {
+            // Unmount the repository first to prevent busy requests.
+            let _ = Cmd::privileged("umount")
+                .arg(format!("{}/repo-mnt", mount_path))
+                .run();
+        }
+        if b_inc > 0 {
+            storage.grow(image_path, mount_path, n_bytes)?;
+        } else {
+            storage.shrink(image_path, mount_path, n_bytes)?;
+        }
+    }
+
+    // Remount straight away so a resize which left the point unusable is reported.
+    ensure_point_mounted(f_point, false)
+}
+
+/// Hands a point over to another user, moving its paths to match. Limits are checked
+/// against the recipient, and the job history follows the point.
+pub async fn transfer_point(
+    conn: &mut AsyncPgConnection,
+    p_user: &str,
+    p_name: &str,
+    dto: PostForTransferApi,
+) -> Result<Mount, NeptisError> {
     use crate::schema::mounts::dsl::*;
-    use crate::schema::repo_jobs::dsl::*;
 
-    let f_point: Mount = mounts
-        .find((dto.point_user.clone(), dto.point_name.clone()))
+    let point: Mount = mounts
+        .find((p_user.to_string(), p_name.to_string()))
+        .get_result(conn)
+        .await?;
+    if dto.new_owner == point.owned_by {
+        return Err(NeptisError::BadRequest("No modification is necessary".into()));
+    }
+    let recipient: User = {
+        use crate::schema::users::dsl::*;
+        users
+            .find(dto.new_owner.as_str())
+            .get_result(conn)
+            .await
+            .ok()
+            .ok_or(NeptisError::BadRequest("The recipient does not exist!".into()))?
+    };
+    let r_mounts: Vec<Mount> = mounts
+        .filter(owned_by.eq(recipient.user_name.as_str()))
+        .get_results(conn)
+        .await?;
+    if r_mounts.iter().any(|x| x.mount_name == point.mount_name) {
+        return Err(NeptisError::BadRequest(
+            "The recipient already has a point with that name!".into(),
+        ));
+    }
+    // The recipient's limits hold the point until it is recorded under their name.
+    let r_id = quota::reserve(
+        conn,
+        recipient.user_name.as_str(),
+        point.mount_name.as_str(),
+        point.data_max_bytes,
+        point.repo_max_bytes,
+        "transfer",
+    )
+    .await?;
+
+    let n_password = dto.new_password.unwrap_or(false).then(new_repo_password);
+    let ret = relocate::relocate_point(
+        conn,
+        &point,
+        recipient.user_name.as_str(),
+        point.mount_name.as_str(),
+        n_password,
+        "transfer",
+    )
+    .await;
+    quota::release(conn, r_id).await?;
+    ret
+}
+
+/// Gives the server the passphrase of an encrypted point, so it can be mounted until
+/// the server restarts.
+#[action(Mount)]
+pub async fn unlock_mount(p_name: &str, dto: PostForUnlockApi) -> Result<MountDto, NeptisError> {
+    use crate::schema::mounts::dsl::*;
+
+    let point: Mount = mounts
+        .find((auth_user.user_name.clone(), p_name.to_string()))
         .get_result(conn)
         .await?;
+    if point.backend != StorageBackend::LuksExt4 {
+        return Err(NeptisError::BadRequest("The point is not encrypted!".into()));
+    }
+    for img_path in [point.data_img_path.as_str(), point.repo_img_path.as_str()] {
+        luks::unlock(img_path, dto.passphrase.as_str())?;
+    }
+    Ok(point)
+}
+
+/// Renames a point, moving its images and mount directories to match the new name.
+#[action(Mount)]
+pub async fn rename_mount(p_name: &str, dto: PatchForMountApi) -> Result<MountDto, NeptisError> {
+    use crate::schema::mounts::dsl::*;
+
+    let n_name = dto.name.trim().to_string();
+    if n_name.is_empty() || n_name.contains('/') || n_name.starts_with('.') {
+        return Err(NeptisError::BadRequest("The new name is not valid!".into()));
+    }
+    if n_name == p_name {
+        return Err(NeptisError::BadRequest("No modification is necessary".into()));
+    }
+    let point: Mount = mounts
+        .find((auth_user.user_name.clone(), p_name.to_string()))
+        .get_result(conn)
+        .await?;
+    if mounts
+        .find((auth_user.user_name.clone(), n_name.clone()))
+        .get_result::<Mount>(conn)
+        .await
+        .is_ok()
+    {
+        return Err(NeptisError::BadRequest(
+            "A point with that name already exists!".into(),
+        ));
+    }
+    let owner = point.owned_by.clone();
+    relocate::relocate_point(conn, &point, owner.as_str(), n_name.as_str(), None, "rename").await
+}
+
+/// Room left on top of the snapshot size when a clone is not given a data size.
+const CLONE_HEADROOM: f64 = 1.25;
+
+/// Creates the point `dto.name` and restores a snapshot of `dto.source` into it as a job.
+#[action(RepoJob)]
+pub async fn clone_mount(
+    handler: &NonBlockingRustic,
+    dto: PostForCloneApi,
+) -> Result<RepoJobDto, NeptisError> {
+    use crate::schema::repo_jobs::dsl::*;
+
+    let n_name = dto.name.trim().to_string();
+    if n_name.is_empty() || n_name.contains('/') || n_name.starts_with('.') {
+        return Err(NeptisError::BadRequest("The new name is not valid!".into()));
+    }
+    let source: Mount = {
+        use crate::schema::mounts::dsl::*;
+        if mounts
+            .find((auth_user.user_name.clone(), n_name.clone()))
+            .get_result::<Mount>(conn)
+            .await
+            .is_ok()
+        {
+            return Err(NeptisError::BadRequest(
+                "A point with that name already exists!".into(),
+            ));
+        }
+        mounts
+            .find((auth_user.user_name.clone(), dto.source.clone()))
+            .get_result(conn)
+            .await?
+    };
+    // A clone holds the same data, so it must not be less protected than its source.
+    let is_encrypted = source.backend == StorageBackend::LuksExt4;
+    if is_encrypted && dto.passphrase.is_none() {
+        return Err(NeptisError::BadRequest(
+            "A passphrase is needed to clone an encrypted point!".into(),
+        ));
+    }
+
+    // Size the new data area from what the snapshot read when it was taken.
+    idle::touch_point(conn, &source, false).await?;
+    ensure_point_mounted(&source, false)?;
+    let s_bytes = {
+        let backends = BackendOptions::default()
+            .repository(format!("{}/repo", source.repo_mnt_path.as_str()))
+            .to_backends()?;
+        let repo_opts = RepositoryOptions::default().password(source.repo_password.as_str());
+        Repository::new(&repo_opts, &backends)?
+            .open()?
+            .get_snapshot_from_str(dto.snapshot_id.as_str(), |_| true)?
+            .summary
+            .map(|x| x.total_bytes_processed)
+            .unwrap_or(0)
+    };
+    let seed = dto.seed_repo.unwrap_or(false);
+    put_mount_async(
+        conn,
+        auth_user,
+        n_name.as_str(),
+        PutForMountApi {
+            data_bytes: dto
+                .data_bytes
+                .unwrap_or(((s_bytes as f64 * CLONE_HEADROOM) as i64).max(10_000_000)),
+            repo_bytes: dto.repo_bytes.unwrap_or(source.repo_max_bytes),
+            encrypted: Some(is_encrypted),
+            passphrase: dto.passphrase.clone(),
+            pool: None,
+            pool_tags: None,
+        },
+    )
+    .await?;
+    let n_point: Mount = {
+        use crate::schema::mounts::dsl::*;
+        mounts
+            .find((auth_user.user_name.clone(), n_name.clone()))
+            .get_result(conn)
+            .await?
+    };
 
-    let options = JobLaunchInfo {
-        point_owned_by: dto.point_user.clone(),
-        point_name: dto.point_name.clone(),
-        repo_path: format!("{}/repo", f_point.repo_mnt_path.as_str()),
-        repo_pass: f_point.repo_password.clone(),
+    // The source repository is only read; the clone is written to throughout.
+    let s_lock = locks::acquire_lock(conn, &source, false, "clone").await?;
+    let n_lock = match locks::acquire_lock(conn, &n_point, true, "clone").await {
+        Ok(x) => x,
+        Err(e) => {
+            locks::release_lock(conn, s_lock).await?;
+            return Err(e);
+        }
     };
+    let mut options = to_launch_info(auth_user, &n_point);
+    options.lock_id = Some(n_lock);
+
+    let ret = ensure_point_mounted(&n_point, false).and_then(|_| {
+        handler.start_clone(
+            &options,
+            format!("{}/repo", source.repo_mnt_path.as_str()).as_str(),
+            source.repo_password.as_str(),
+            dto.snapshot_id.as_str(),
+            seed,
+            Some(s_lock),
+        )
+    });
+    if ret.is_err() {
+        locks::release_lock(conn, n_lock).await?;
+        locks::release_lock(conn, s_lock).await?;
+    }
+    Ok(repo_jobs.find(ret?).get_result(conn).await?)
+}
+
+#[action(Mount)]
+pub async fn put_rpo(p_name: &str, dto: PutForRpoApi) -> Result<MountDto, NeptisError> {
+    use crate::schema::mounts::dsl::*;
 
-    let b_opts = BackupOptions::default().dry_run(dto.dry_run);
-    let source = PathList::from_string(f_point.data_mnt_path.as_str())?
-        .sanitize()
-        .unwrap();
-    let mut s_opts = SnapshotOptions::default();
-    if let Some(ref tags) = dto.tags {
-        s_opts = s_opts.add_tags(tags.join(",").as_str())?;
+    if dto.rpo_hours.is_some_and(|x| x < 1) {
+        return Err(NeptisError::BadRequest(
+            "The objective must be at least one hour!".into(),
+        ));
     }
-    let ret_id = handler.start_backup(&options, source, s_opts, b_opts)?;
+    diesel::update(mounts.find((auth_user.user_name.clone(), p_name.to_string())))
+        .set(rpo_hours.eq(dto.rpo_hours))
+        .execute(conn)
+        .await?;
+    freshness::refresh_stale_flags(conn).await?;
+    Ok(mounts
+        .find((auth_user.user_name.clone(), p_name.to_string()))
+        .get_result(conn)
+        .await?)
+}
 
-    // Finally, return the job information.
-    Ok(repo_jobs.find(ret_id).get_result(conn).await?)
+#[action(Mount)]
+pub async fn put_fsck(p_name: &str, dto: PutForFsckApi) -> Result<MountDto, NeptisError> {
+    use crate::schema::mounts::dsl::*;
+
+    if dto.fsck_hours.is_some_and(|x| x < 1) {
+        return Err(NeptisError::BadRequest(
+            "Checks must be at least one hour apart!".into(),
+        ));
+    }
+    let point: Mount = mounts
+        .find((auth_user.user_name.clone(), p_name.to_string()))
+        .get_result(conn)
+        .await?;
+    if dto.fsck_hours.is_some() {
+        fsck::ensure_checkable(&point)?;
+    }
+    Ok(diesel::update(mounts.find((auth_user.user_name.clone(), p_name.to_string())))
+        .set(fsck_hours.eq(dto.fsck_hours))
+        .get_result(conn)
+        .await?)
+}
+
+// Creates both areas of a new point and initialises its repository.
+fn provision_point(p_mount: &Mount) -> Result<(), NeptisError> {
+    let storage = storage::for_point(p_mount);
+    for (m_path, i_path, m_bytes) in [
+        (
+            p_mount.data_mnt_path.as_str(),
+            p_mount.data_img_path.as_str(),
+            p_mount.data_max_bytes,
+        ),
+        (
+            p_mount.repo_mnt_path.as_str(),
+            p_mount.repo_img_path.as_str(),
+            p_mount.repo_max_bytes,
+        ),
+    ] {
+        storage.create(i_path, m_path, m_bytes)?;
+    }
+
+    // We need to actually create the repository via rustic.
+    ensure_point_mounted(p_mount, false)?;
+    let repo_dir = format!("{}/repo", p_mount.repo_mnt_path.as_str());
+    let backends = BackendOptions::default()
+        .repository(repo_dir)
+        .to_backends()?;
+    let repo_opts = RepositoryOptions::default().password(p_mount.repo_password.as_str());
+    let key_opts = KeyOptions::default();
+    let config_opts = ConfigOptions::default();
+    Repository::new(&repo_opts, &backends)?.init(&key_opts, &config_opts)?;
+    Ok(())
 }
 
 #[action(Mount)]
@@ -911,17 +1707,12 @@ pub async fn put_mount(m_name: &str, dto: PutForMountApi) -> Result<MountDto, Ne
         ));
     }
 
-    // Get the free space to ensure we don't go over.
-    let data_path = get_env!("DATA_PATH");
-    let repo_path = get_env!("REPO_PATH");
-
-    let d_sys_info = get_system_info(data_path.as_str())?;
-    let r_sys_info = get_system_info(repo_path.as_str())?;
-
-    let all_mounts: Vec<Mount> = mounts
-        .filter(owned_by.eq(auth_user.user_name.clone()))
-        .get_results(conn)
-        .await?;
+    let server_mounts: Vec<Mount> = mounts.get_results(conn).await?;
+    let all_mounts: Vec<Mount> = server_mounts
+        .iter()
+        .filter(|x| x.owned_by == auth_user.user_name)
+        .cloned()
+        .collect();
 
     // If the mount exists, we will attempt to resize it - otherwise, just
     // create a new one instead.
@@ -929,145 +1720,117 @@ pub async fn put_mount(m_name: &str, dto: PutForMountApi) -> Result<MountDto, Ne
         .iter()
         .find(|x| x.mount_name == m_name && x.owned_by == auth_user.user_name.clone())
     {
-        // Begin performing the re-size, and update the DB.
-        ensure_point_mounted(f_point, true)?;
-
-        // If the snapshot part is 0, just delete it.
-        for (b_inc, s_info, is_data, mount_path, image_path) in [
-            (
-                d_max_bytes - f_point.data_max_bytes,
-                get_system_info(f_point.data_mnt_path.as_str())?,
-                true,
-                f_point.data_mnt_path.as_str(),
-                f_point.data_img_path.as_str(),
-            ),
-            (
-                r_max_bytes - f_point.repo_max_bytes,
-                get_system_info(f_point.repo_mnt_path.as_str())?,
-                false,
-                f_point.repo_mnt_path.as_str(),
-                f_point.repo_img_path.as_str(),
-            ),
-        ] {
-            if b_inc == 0 {
-                return Err(NeptisError::BadRequest(
-                    "No modification is necessary".into(),
-                ));
-            }
-            if (d_max_bytes as usize) < s_info.b_used {
-                // Not allowed - need more free space to shrink partition.
-                return Err(NeptisError::BadRequest(
-                    "Not enough free space to shrink. Please delete files and try again".into(),
-                ));
-            }
-            if b_inc > 0 && d_sys_info.b_avail < b_inc as usize {
-                return Err(NeptisError::BadRequest(
-                    "Not enough free space on server".into(),
-                ));
-            }
-            ensure_single_limit(
-                auth_user,
-                (all_mounts
-                    .iter()
-                    .map(|x| {
-                        if is_data {
-                            x.data_max_bytes as usize
-                        } else {
-                            x.repo_max_bytes as usize
-                        }
-                    })
-                    .sum::<usize>() as i64
-                    + b_inc as i64) as usize,
-                is_data,
-            )?;
-
-            // At this point, the commands are different based
-            // on increase or decrease.
-            let b_str = d_max_bytes.to_string();
-            let sec_str = format!("{}", d_max_bytes / get_img_block_size(image_path)? as i64);
-            let temp_dir = format!("{}/{}", data_path, Uuid::new_v4().to_string());
-            if b_inc > 0 {
-                (|| {
-                    if !is_data {
-                        cmd!("umount {}/repo-mnt", mount_path)?; // unmount the repository to prevent busy requests.
-                    }
-                    fs::create_dir_all(temp_dir.as_str()).ok()?;
-                    cmd!("umount {}", mount_path)?;
-                    cmd!("e2fsck -f -y {}", image_path)?;
-                    cmd!("fallocate -l {} {}", b_str.as_str(), image_path)?;
-                    cmd!("e2fsck -f -y {}", image_path)?;
-                    cmd!("resize2fs {}", image_path)?;
-                    cmd!("mount -o rw,sync,loop {} {}", image_path, temp_dir.as_str())?;
-                    cmd!("umount {}", temp_dir.as_str())?;
-                    fs::remove_dir(temp_dir.as_str()).ok()?;
-                    Some(())
-                })()
-                .ok_or(NeptisError::InternalError("Failed to perform grow".into()))?;
-            } else {
-                (|| {
-                    if !is_data {
-                        cmd!("umount {}/repo-mnt", mount_path)?; // unmount the repository to prevent busy requests.
-                    }
-                    fs::create_dir_all(temp_dir.as_str()).ok()?;
-                    cmd!("umount {}", mount_path)?;
-                    cmd!("e2fsck -f -y {}", image_path)?;
-                    cmd!("resize2fs {} {}", image_path, sec_str.as_str())?;
-                    cmd!("e2fsck -f -y {}", image_path)?;
-                    cmd!("mount -o rw,sync,loop {} {}", image_path, temp_dir.as_str())?;
-                    cmd!("umount {}", temp_dir.as_str())?;
-                    fs::remove_dir(temp_dir.as_str()).ok()?;
-                    Some(())
-                })()
-                .ok_or(NeptisError::InternalError(
-                    "Failed to perform shrink".into(),
-                ))?;
+        // Growth has to fit in the pool the point already lives in.
+        let pool = pools::pool_of(conn, f_point).await?;
+        pools::ensure_room(
+            &pool,
+            &server_mounts,
+            d_max_bytes + r_max_bytes - f_point.data_max_bytes - f_point.repo_max_bytes,
+        )?;
+        let d_sys_info = storage::disk_usage(pool.data_path.as_str())?;
+        let r_sys_info = storage::disk_usage(pool.repo_path.as_str())?;
+
+        // Only growth needs to be reserved; shrinking frees space once recorded.
+        let r_id = quota::reserve(
+            conn,
+            auth_user.user_name.as_str(),
+            m_name,
+            d_max_bytes - f_point.data_max_bytes,
+            r_max_bytes - f_point.repo_max_bytes,
+            "resize",
+        )
+        .await?;
+
+        // Resizing unmounts both images, so the point must be idle for the duration.
+        let ret = match locks::acquire_lock(conn, f_point, true, "resize").await {
+            Ok(lock_id) => {
+                let ret = resize_point(
+                    f_point,
+                    &pools::points_in(&pool, &server_mounts),
+                    d_max_bytes,
+                    r_max_bytes,
+                    &d_sys_info,
+                    &r_sys_info,
+                );
+                locks::release_lock(conn, lock_id).await.and(ret)
             }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = ret {
+            quota::release(conn, r_id).await?;
+            return Err(e);
         }
 
-        // We are done! Simply update the DB to continue.
-        let mut u_point = f_point.clone();
-        u_point.data_max_bytes = d_max_bytes;
-        u_point.repo_max_bytes = r_max_bytes;
-        u_point.repo_accessed = utc_now!();
-        u_point.data_accessed = utc_now!();
-        Ok(diesel::update(mounts)
-            .set(&u_point)
-            .get_result(conn)
-            .await?)
+        // We are done! Only the sizes are written back, as `f_point` was read before the
+        // lock and the rest of the row may have changed since.
+        let now = utc_now!();
+        let u_point: Result<Mount, NeptisError> =
+            diesel::update(mounts.find((f_point.owned_by.clone(), f_point.mount_name.clone())))
+                .set((
+                    data_max_bytes.eq(d_max_bytes),
+                    repo_max_bytes.eq(r_max_bytes),
+                    data_accessed.eq(now),
+                    repo_accessed.eq(now),
+                ))
+                .get_result(conn)
+                .await
+                .map_err(Into::into);
+        quota::release(conn, r_id).await?;
+        let u_point = u_point?;
+        dispatch::emit(
+            u_point.owned_by.as_str(),
+            WebhookEvent::MountResized,
+            json!({
+                "mount": u_point.mount_name,
+                "data_max_bytes": u_point.data_max_bytes,
+                "repo_max_bytes": u_point.repo_max_bytes,
+            }),
+        );
+        Ok(u_point)
     } else {
-        if d_sys_info.b_avail < d_max_bytes as usize {
+        if dto.passphrase.is_some() && !dto.encrypted.unwrap_or(false) {
             return Err(NeptisError::BadRequest(
-                "Not enough data free space on server".into(),
+                "A passphrase can only be set on an encrypted point!".into(),
             ));
         }
-        if r_sys_info.b_avail < r_max_bytes as usize {
-            return Err(NeptisError::BadRequest(
-                "Not enough repo free space on server".into(),
-            ));
-        }
-        ensure_user_limit(
+        let pool = pools::choose_pool(
+            conn,
             auth_user,
-            all_mounts
-                .iter()
-                .map(|x| x.data_max_bytes as usize)
-                .sum::<usize>()
-                + d_max_bytes as usize,
-            all_mounts
-                .iter()
-                .map(|x| x.repo_max_bytes as usize)
-                .sum::<usize>()
-                + r_max_bytes as usize,
+            dto.pool.as_deref(),
+            &dto.pool_tags.clone().unwrap_or_default(),
+            d_max_bytes + r_max_bytes,
+        )
+        .await?;
+        let data_path = pool.data_path.clone();
+        let repo_path = pool.repo_path.clone();
+        let pool_mounts = pools::points_in(&pool, &server_mounts);
+        provisioning::ensure_can_allocate(
+            &storage::disk_usage(data_path.as_str())?,
+            provisioning::allocated_bytes(&pool_mounts, true),
+            d_max_bytes as u64,
+            "data",
+        )?;
+        provisioning::ensure_can_allocate(
+            &storage::disk_usage(repo_path.as_str())?,
+            provisioning::allocated_bytes(&pool_mounts, false),
+            r_max_bytes as u64,
+            "repo",
         )?;
+        let p_backend = match dto.encrypted.unwrap_or(false) {
+            true => StorageBackend::LuksExt4,
+            false => storage::configured_backend(),
+        };
 
         // Attempt to create the directory and run the allocating commands.
         let p_mount = Mount {
             mount_name: m_name.to_string(),
             owned_by: auth_user.user_name.clone(),
             data_img_path: format!(
-                "{}/{}-{}-DATA.img",
+                "{}/{}-{}-DATA.{}",
                 data_path.clone(),
                 m_name,
-                auth_user.user_name.clone()
+                auth_user.user_name.clone(),
+                storage::provisioner_for(p_backend).image_suffix()
             ),
             data_mnt_path: format!(
                 "{}/{}-{}-DATA",
@@ -1076,10 +1839,11 @@ pub async fn put_mount(m_name: &str, dto: PutForMountApi) -> Result<MountDto, Ne
                 auth_user.user_name.clone()
             ),
             repo_img_path: format!(
-                "{}/{}-{}-REPO.img",
+                "{}/{}-{}-REPO.{}",
                 repo_path.clone(),
                 m_name,
-                auth_user.user_name.clone()
+                auth_user.user_name.clone(),
+                storage::provisioner_for(p_backend).image_suffix()
             ),
             repo_mnt_path: format!(
                 "{}/{}-{}-REPO",
@@ -1087,65 +1851,56 @@ pub async fn put_mount(m_name: &str, dto: PutForMountApi) -> Result<MountDto, Ne
                 m_name,
                 auth_user.user_name.clone()
             ),
-            repo_password: PasswordGenerator::new()
-                .length(8)
-                .numbers(true)
-                .lowercase_letters(true)
-                .uppercase_letters(true)
-                .symbols(false)
-                .spaces(false)
-                .exclude_similar_characters(true)
-                .strict(true)
-                .generate_one()
-                .unwrap(),
+            repo_password: new_repo_password(),
             data_max_bytes: d_max_bytes as i64,
             repo_max_bytes: r_max_bytes as i64,
             date_created: utc_now!(),
             data_accessed: utc_now!(),
             repo_accessed: utc_now!(),
             locked: false, // will not be inserted until very end
+            rpo_hours: None,
+            is_stale: false,
+            backend: p_backend,
+            fsck_hours: None,
+            health: None,
+            health_checked: None,
+            pool_name: Some(pool.pool_name.clone()),
         };
 
-        for (m_path, i_path, m_bytes) in [
-            (
-                p_mount.data_mnt_path.as_str(),
-                p_mount.data_img_path.as_str(),
-                p_mount.data_max_bytes,
-            ),
-            (
-                p_mount.repo_mnt_path.as_str(),
-                p_mount.repo_img_path.as_str(),
-                p_mount.repo_max_bytes,
-            ),
-        ] {
-            fs::create_dir_all(m_path)
-                .map_err(|_| NeptisError::InternalError("Failed to create directory".into()))?;
-            (|| {
-                cmd!("fallocate -l {0} {1}", m_bytes, i_path)?;
-                cmd!("mkfs.ext4 {0}", i_path)?;
-                cmd!("chmod 777 {0}", i_path)?;
-                Some(())
-            })()
-            .ok_or(NeptisError::InternalError(
-                "Failed to create/mount FS".into(),
-            ))?;
+        if let Some(ref pass) = dto.passphrase {
+            luks::remember_passphrase(p_mount.data_img_path.as_str(), pass.as_str());
+            luks::remember_passphrase(p_mount.repo_img_path.as_str(), pass.as_str());
         }
 
-        // We need to actually create the repository via rustic.
-        ensure_point_mounted(&p_mount, false)?;
-        let repo_dir = format!("{}/repo", p_mount.repo_mnt_path.as_str());
-        let backends = BackendOptions::default()
-            .repository(repo_dir)
-            .to_backends()?;
-        let repo_opts = RepositoryOptions::default().password(p_mount.repo_password.as_str());
-        let key_opts = KeyOptions::default();
-        let config_opts = ConfigOptions::default();
-        Repository::new(&repo_opts, &backends)?.init(&key_opts, &config_opts)?;
-
-        // Finally, insert to the DB and finish.
-        Ok(diesel::insert_into(mounts)
-            .values(&p_mount)
-            .get_result(conn)
-            .await?)
+        // Hold the space on the account before touching the disk.
+        let r_id = quota::reserve(
+            conn,
+            auth_user.user_name.as_str(),
+            m_name,
+            d_max_bytes,
+            r_max_bytes,
+            "create",
+        )
+        .await?;
+        let ret = match provision_point(&p_mount) {
+            Ok(_) => diesel::insert_into(mounts)
+                .values(&p_mount)
+                .get_result::<Mount>(conn)
+                .await
+                .map_err(Into::into),
+            Err(e) => Err(e),
+        };
+        quota::release(conn, r_id).await?;
+        let p_mount = ret?;
+        dispatch::emit(
+            p_mount.owned_by.as_str(),
+            WebhookEvent::MountCreated,
+            json!({
+                "mount": p_mount.mount_name,
+                "data_max_bytes": p_mount.data_max_bytes,
+                "repo_max_bytes": p_mount.repo_max_bytes,
+            }),
+        );
+        Ok(p_mount)
     }
 }
@@ -0,0 +1,214 @@
+use std::fs::{self, File, Metadata};
+use std::io::{self, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use rocket::FromFormField;
+use rocket::http::ContentType;
+use rocket::tokio::sync::mpsc;
+use serde::{Deserialize, Serialize};
+use zip::ZipWriter;
+use zip::write::SimpleFileOptions;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, FromFormField)]
+pub enum ArchiveFormat {
+    #[field(value = "tar")]
+    Tar,
+    #[field(value = "tar.gz")]
+    #[field(value = "tgz")]
+    TarGz,
+    #[field(value = "zip")]
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn content_type(&self) -> ContentType {
+        match self {
+            ArchiveFormat::Tar => ContentType::new("application", "x-tar"),
+            ArchiveFormat::TarGz => ContentType::GZIP,
+            ArchiveFormat::Zip => ContentType::ZIP,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::Zip => "zip",
+        }
+    }
+}
+
+/// Buffers archive output and hands it to the response stream in chunks.
+struct ChunkWriter {
+    tx: mpsc::Sender<Vec<u8>>,
+    buf: Vec<u8>,
+}
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+impl Write for ChunkWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buf, Vec::with_capacity(CHUNK_SIZE));
+        // The receiver is gone when the client hangs up, so stop building the archive.
+        self.tx
+            .blocking_send(chunk)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Client disconnected"))
+    }
+}
+
+// Match a path against a pattern where `*` is any run of characters and `?` is one.
+fn wildcard(pattern: &[u8], path: &[u8]) -> bool {
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            wildcard(&pattern[1..], path) || (!path.is_empty() && wildcard(pattern, &path[1..]))
+        }
+        (Some(b'?'), Some(_)) => wildcard(&pattern[1..], &path[1..]),
+        (Some(a), Some(b)) if a == b => wildcard(&pattern[1..], &path[1..]),
+        _ => false,
+    }
+}
+
+/// Whether a relative path is selected by the filters. A filter selects an exact path,
+/// anything below it, or anything matching it as a wildcard pattern.
+pub fn path_matches(filters: &[String], rel_path: &str) -> bool {
+    filters.is_empty()
+        || filters.iter().any(|f| {
+            let f = f.trim_matches('/');
+            rel_path == f
+                || rel_path.starts_with(format!("{}/", f).as_str())
+                || wildcard(f.as_bytes(), rel_path.as_bytes())
+        })
+}
+
+// Collect every entry below `root` as (absolute path, relative path, metadata).
+fn walk(root: &Path, rel: &str, output: &mut Vec<(PathBuf, String, Metadata)>) -> io::Result<()> {
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let rel_path = if rel.is_empty() { name } else { format!("{}/{}", rel, name) };
+        if rel_path == "lost+found" {
+            continue; // R/O on every ext4 image
+        }
+        let meta = entry.path().symlink_metadata()?;
+        let is_dir = meta.is_dir();
+        output.push((entry.path(), rel_path.clone(), meta));
+        if is_dir {
+            walk(entry.path().as_path(), rel_path.as_str(), output)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_tar<W: Write>(
+    entries: &[(PathBuf, String, Metadata)],
+    filters: &[String],
+    w: W,
+) -> io::Result<W> {
+    let mut builder = tar::Builder::new(w);
+    builder.follow_symlinks(false);
+    for (abs_path, rel_path, meta) in entries {
+        if meta.is_dir() {
+            // Directory entries only carry metadata, so they are kept when unfiltered.
+            if filters.is_empty() {
+                builder.append_dir(rel_path, abs_path)?;
+            }
+        } else if path_matches(filters, rel_path) {
+            builder.append_path_with_name(abs_path, rel_path)?;
+        }
+    }
+    builder.into_inner()
+}
+
+fn to_zip_time(meta: &Metadata) -> zip::DateTime {
+    let t: DateTime<Utc> = meta.modified().unwrap_or(UNIX_EPOCH).into();
+    zip::DateTime::from_date_and_time(
+        t.year().clamp(1980, 2107) as u16,
+        t.month() as u8,
+        t.day() as u8,
+        t.hour() as u8,
+        t.minute() as u8,
+        t.second() as u8,
+    )
+    .unwrap_or_default()
+}
+
+fn write_zip<W: Write>(
+    entries: &[(PathBuf, String, Metadata)],
+    filters: &[String],
+    w: W,
+) -> io::Result<()> {
+    let mut zip = ZipWriter::new_stream(w);
+    for (abs_path, rel_path, meta) in entries {
+        let options = SimpleFileOptions::default()
+            .unix_permissions(meta.permissions().mode())
+            .last_modified_time(to_zip_time(meta))
+            .large_file(meta.len() >= u32::MAX as u64);
+        if meta.is_dir() {
+            if filters.is_empty() {
+                zip.add_directory(rel_path.as_str(), options)?;
+            }
+        } else if !path_matches(filters, rel_path) {
+            continue;
+        } else if meta.is_symlink() {
+            let target = fs::read_link(abs_path)?;
+            zip.add_symlink(rel_path.as_str(), target.to_string_lossy(), options)?;
+        } else {
+            zip.start_file(rel_path.as_str(), options)?;
+            io::copy(&mut File::open(abs_path)?, &mut zip)?;
+        }
+    }
+    zip.finish()?.into_inner().flush()
+}
+
+fn write_archive<W: Write>(
+    format: ArchiveFormat,
+    root: &Path,
+    filters: &[String],
+    w: W,
+) -> io::Result<()> {
+    let mut entries = vec![];
+    walk(root, "", &mut entries)?;
+    match format {
+        ArchiveFormat::Tar => write_tar(&entries, filters, w)?.flush(),
+        ArchiveFormat::TarGz => write_tar(&entries, filters, GzEncoder::new(w, Compression::default()))?
+            .finish()?
+            .flush(),
+        ArchiveFormat::Zip => write_zip(&entries, filters, w),
+    }
+}
+
+/// Builds an archive of `root` on a blocking thread, returning the chunks as they are produced.
+pub fn stream_archive(
+    format: ArchiveFormat,
+    root: PathBuf,
+    filters: Vec<String>,
+) -> mpsc::Receiver<Vec<u8>> {
+    let (tx, rx) = mpsc::channel(4);
+    rocket::tokio::task::spawn_blocking(move || {
+        let writer = ChunkWriter {
+            tx,
+            buf: Vec::with_capacity(CHUNK_SIZE),
+        };
+        if let Err(e) = write_archive(format, root.as_path(), &filters, writer) {
+            println!("Failed to stream archive of {}: {}", root.display(), e);
+        }
+    });
+    rx
+}
@@ -0,0 +1,126 @@
+use std::io::{self, SeekFrom};
+
+use super::models::Mount;
+use super::storage;
+use crate::prelude::action_prelude::*;
+
+const ENOSPC: i32 = 28;
+const EDQUOT: i32 = 122;
+
+/// How full the data area of a point is. Every write into it is checked against this
+/// before it starts.
+pub struct Capacity {
+    pub mount_name: String,
+    pub used: u64,
+    pub limit: u64,
+    /// Free space reported by the filesystem, which may run out before the limit does.
+    pub available: u64,
+}
+
+impl Capacity {
+    /// Bytes which may still be written.
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.used).min(self.available)
+    }
+
+    fn exceeded(&self, requested: u64) -> NeptisError {
+        self.exceeded_after(0, requested)
+    }
+
+    /// Refuses a write of `requested` bytes once `written` more have gone in since the
+    /// usage was read, as happens partway through an extraction.
+    pub fn exceeded_after(&self, written: u64, requested: u64) -> NeptisError {
+        NeptisError::CapacityExceeded {
+            mount_name: self.mount_name.clone(),
+            requested,
+            used: self.used.saturating_add(written),
+            limit: self.limit,
+        }
+    }
+
+    /// Refuses a write which would grow the area by `growth` bytes past what is left.
+    pub fn ensure_fits(&self, growth: u64) -> Result<(), NeptisError> {
+        match growth <= self.remaining() {
+            true => Ok(()),
+            false => Err(self.exceeded(growth)),
+        }
+    }
+
+    /// Reports a write which still ran out of space, such as one racing another, as
+    /// over capacity rather than as a generic I/O error.
+    pub fn map_write_error(&self, e: io::Error) -> NeptisError {
+        match is_out_of_space(&e) {
+            true => self.exceeded(0),
+            false => e.into(),
+        }
+    }
+}
+
+/// Whether a write failed because the filesystem or its quota is full.
+pub fn is_out_of_space(e: &io::Error) -> bool {
+    matches!(e.raw_os_error(), Some(ENOSPC) | Some(EDQUOT))
+}
+
+/// Reads the usage of the data area of `point`, which must be mounted.
+pub fn data_capacity(point: &Mount) -> Result<Capacity, NeptisError> {
+    let stats = storage::for_point(point).usage(&point.data_img_path, &point.data_mnt_path)?;
+    Ok(Capacity {
+        mount_name: point.mount_name.clone(),
+        used: stats.b_used as u64,
+        limit: point.data_max_bytes.max(0) as u64,
+        available: stats.b_avail as u64,
+    })
+}
+
+/// How far writing `len` bytes at `offset` grows a file currently `cur_len` bytes long.
+/// The file is assumed to be freshly opened, so `Current` counts from its start.
+pub fn write_growth(cur_len: u64, offset: Option<SeekFrom>, len: u64) -> u64 {
+    let start = match offset.unwrap_or(SeekFrom::Start(0)) {
+        SeekFrom::Start(n) => n,
+        SeekFrom::End(n) => cur_len.saturating_add_signed(n),
+        SeekFrom::Current(n) => 0u64.saturating_add_signed(n),
+    };
+    start.saturating_add(len).saturating_sub(cur_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn growth_from_the_start() {
+        assert_eq!(write_growth(0, None, 100), 100);
+        assert_eq!(write_growth(100, None, 40), 0);
+        assert_eq!(write_growth(100, Some(SeekFrom::Start(80)), 40), 20);
+        assert_eq!(write_growth(100, Some(SeekFrom::Start(200)), 10), 110);
+    }
+
+    #[test]
+    fn growth_from_the_end_and_current() {
+        assert_eq!(write_growth(100, Some(SeekFrom::End(0)), 10), 10);
+        assert_eq!(write_growth(100, Some(SeekFrom::End(-50)), 10), 0);
+        assert_eq!(write_growth(100, Some(SeekFrom::End(-200)), 10), 0);
+        assert_eq!(write_growth(100, Some(SeekFrom::Current(95)), 10), 5);
+        assert_eq!(write_growth(0, Some(SeekFrom::Current(-5)), 10), 10);
+    }
+
+    #[test]
+    fn remaining_is_bounded_by_the_filesystem() {
+        let cap = Capacity {
+            mount_name: "m".into(),
+            used: 600,
+            limit: 1000,
+            available: 100,
+        };
+        assert_eq!(cap.remaining(), 100);
+        assert!(cap.ensure_fits(100).is_ok());
+        assert!(matches!(
+            cap.ensure_fits(101),
+            Err(NeptisError::CapacityExceeded { requested: 101, used: 600, limit: 1000, .. })
+        ));
+        assert!(matches!(
+            cap.exceeded_after(50, 10),
+            NeptisError::CapacityExceeded { used: 650, .. }
+        ));
+    }
+}
@@ -1,7 +1,7 @@
 use std::{fs::Metadata, io::SeekFrom, time::{SystemTime, UNIX_EPOCH}};
 
 use crate::{prelude::model_prelude::*};
-use super::models::{JobStatus, JobType, Mount, RepoJob};
+use super::models::{FsHealth, HookType, JobStatus, JobType, Mount, MountHook, RepoJob, StorageBackend};
 
 #[derive(Serialize, Deserialize)]
 pub struct MountDto {
@@ -13,7 +13,17 @@ pub struct MountDto {
     pub repo_used_bytes: Option<i64>,
     pub date_created: NaiveDateTime,
     pub data_accessed: NaiveDateTime,
-    pub repo_accessed: NaiveDateTime
+    pub repo_accessed: NaiveDateTime,
+    pub locked: bool,
+    pub rpo_hours: Option<i32>,
+    /// No successful backup within `rpo_hours`, as of the last freshness pass.
+    pub stale: bool,
+    pub backend: StorageBackend,
+    pub fsck_hours: Option<i32>,
+    /// Outcome of the last filesystem check of either area.
+    pub health: Option<FsHealth>,
+    pub health_checked: Option<NaiveDateTime>,
+    pub pool_name: Option<String>
 }
 
 #[derive(Serialize, Deserialize)]
@@ -28,7 +38,35 @@ pub struct RepoJobDto {
     pub total_bytes: Option<i64>,
     pub errors: Vec<String>,
     pub create_date: NaiveDateTime,
-    pub end_date: Option<NaiveDateTime>
+    /// When the work stopped. A cancelled job has none while it is still running.
+    pub end_date: Option<NaiveDateTime>,
+    pub acted_by: Option<String>,
+    pub target_path: Option<String>,
+    pub launch_dry_run: bool,
+    pub launch_read_data: bool,
+    pub launch_tags: Vec<String>,
+    pub logs: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MountHookDto {
+    pub id: Uuid,
+    pub owned_by: String,
+    pub mount_name: String,
+    pub hook_type: HookType,
+    pub command: Vec<String>,
+    pub timeout_secs: i32,
+    pub enabled: bool,
+    pub create_date: NaiveDateTime
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PostForHookApi {
+    pub hook_type: HookType,
+    /// Program followed by its arguments; no shell is involved.
+    pub command: Vec<String>,
+    pub timeout_secs: Option<i32>,
+    pub enabled: Option<bool>
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -117,7 +155,56 @@ pub struct GetForDumpApi {
 #[derive(Serialize, Deserialize)]
 pub struct PutForMountApi {
     pub data_bytes: i64,
-    pub repo_bytes: i64
+    pub repo_bytes: i64,
+    /// Creates both areas as LUKS2 containers. Ignored when resizing.
+    pub encrypted: Option<bool>,
+    /// Needed, along with the server key, to open an encrypted point.
+    pub passphrase: Option<String>,
+    /// Storage pool to create the point in, instead of the default or the one with
+    /// the most space left. Ignored when resizing.
+    pub pool: Option<String>,
+    /// Only places the point in a pool carrying all of these tags.
+    pub pool_tags: Option<Vec<String>>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PostForUnlockApi {
+    pub passphrase: String
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PatchForMountApi {
+    pub name: String
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PostForTransferApi {
+    pub new_owner: String,
+    /// Gives the repository a fresh password, so the previous owner's copy stops working.
+    pub new_password: Option<bool>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PostForCloneApi {
+    /// Point to clone, owned by the caller.
+    pub source: String,
+    pub snapshot_id: String,
+    pub name: String,
+    /// Also copies the snapshot into the new repository, so it starts with that history.
+    pub seed_repo: Option<bool>,
+    /// Defaults to the size of the snapshot plus some headroom.
+    pub data_bytes: Option<i64>,
+    /// Defaults to the size of the source repository.
+    pub repo_bytes: Option<i64>,
+    /// Passphrase of the new point. Required when the source is encrypted, since the
+    /// clone is encrypted as well.
+    pub passphrase: Option<String>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PutForRpoApi {
+    /// Hours allowed between successful backups; clears the objective when unset.
+    pub rpo_hours: Option<i32>
 }
 
 #[derive(Serialize, Deserialize)]
@@ -128,6 +215,43 @@ pub struct PostForBackupApi {
     pub dry_run: bool
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct PostForCheckApi {
+    pub read_data: bool
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PostForFsckApi {
+    /// Lets `e2fsck` fix what it finds instead of only reporting it.
+    pub repair: bool
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PutForFsckApi {
+    /// Hours between filesystem checks; stops scheduling them when unset.
+    pub fsck_hours: Option<i32>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PostForRestoreApi {
+    /// Path inside the repository, such as `<snapshot id>:/some/dir`.
+    pub snapshot_path: String,
+    /// Destination relative to the data area; defaults to the root.
+    pub target_path: Option<String>,
+    pub dry_run: bool
+}
+
+#[derive(FromForm)]
+pub struct GetForJobsApi {
+    pub user: Option<String>,
+    pub mount: Option<String>,
+    pub job_type: Option<JobType>,
+    pub status: Option<JobStatus>,
+    pub acted_by: Option<String>,
+    pub days: Option<i64>,
+    pub limit: Option<i64>
+}
+
 impl NodeDto {
     fn safe_time(t: SystemTime) -> SystemTime {
         if t < UNIX_EPOCH {
@@ -150,4 +274,5 @@ impl NodeDto {
 }
 
 bind_dto!(Mount, MountDto);
-bind_dto!(RepoJob, RepoJobDto);
\ No newline at end of file
+bind_dto!(RepoJob, RepoJobDto);
+bind_dto!(MountHook, MountHookDto);
\ No newline at end of file
@@ -0,0 +1,526 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek};
+use std::os::unix::fs::{PermissionsExt, symlink};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+use rocket::FromFormField;
+use rocket::data::DataStream;
+use rocket::tokio::io::AsyncReadExt;
+use rocket::tokio::sync::mpsc;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use zip::ZipArchive;
+
+use super::archive::ArchiveFormat;
+use super::capacity::{self, Capacity};
+use crate::api::errors::NeptisError;
+
+/// What to do when an entry already exists in the target directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, FromFormField)]
+pub enum ConflictPolicy {
+    #[default]
+    #[field(value = "skip")]
+    Skip,
+    #[field(value = "overwrite")]
+    Overwrite,
+    #[field(value = "rename")]
+    Rename,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExtractAction {
+    Created,
+    Overwritten,
+    Renamed,
+    Skipped,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ExtractEntryDto {
+    /// The path as it appears in the archive.
+    pub path: String,
+    pub action: ExtractAction,
+    /// Where the entry was written, relative to the target, when it was renamed.
+    pub written_to: Option<String>,
+    pub bytes: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ExtractReportDto {
+    pub target: String,
+    pub total_bytes: u64,
+    /// False when the archive could not be read to the end. Running out of space fails
+    /// the whole request instead.
+    pub complete: bool,
+    /// Set when the archive itself could not be read to the end.
+    pub error: Option<String>,
+    pub entries: Vec<ExtractEntryDto>,
+}
+
+/// A resolved upload destination. The shared lock must be released once extraction ends.
+pub struct ExtractTarget {
+    pub root: PathBuf,
+    /// Usage of the data area when extraction started. What it has left is the budget.
+    pub capacity: Capacity,
+    /// Largest archive body accepted.
+    pub limit: u64,
+    pub lock_id: Uuid,
+}
+
+enum EntryKind {
+    Dir,
+    File,
+    Symlink(PathBuf),
+    Other,
+}
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Feeds a blocking reader from chunks sent by the request body task.
+struct ChunkReader {
+    rx: mpsc::Receiver<Vec<u8>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for ChunkReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos >= self.buf.len() {
+            match self.rx.blocking_recv() {
+                Some(chunk) => {
+                    self.buf = chunk;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Turns an archive path into one relative to the target, refusing anything which
+/// is absolute or climbs out with `..`.
+fn confine(raw: &str) -> Option<PathBuf> {
+    let mut output = PathBuf::new();
+    for c in Path::new(raw).components() {
+        match c {
+            Component::Normal(x) => output.push(x),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (!output.as_os_str().is_empty()).then_some(output)
+}
+
+// Whether a symlink placed at `rel` pointing at `target` stays inside the target directory.
+// Any `..` has to come first, where it only climbs the real directories above the link.
+// Once the target descends, a later `..` could back out of another symlink instead, such
+// as `a/up -> ..` followed by `esc -> a/up/../..`, so that is refused whatever the order.
+fn link_confined(rel: &Path, target: &Path) -> bool {
+    let mut depth = rel.components().count() as i64 - 1;
+    let mut descended = false;
+    for c in target.components() {
+        match c {
+            Component::Normal(_) => {
+                depth += 1;
+                descended = true;
+            }
+            Component::CurDir => {}
+            Component::ParentDir if descended => return false,
+            Component::ParentDir => {
+                depth -= 1;
+                if depth < 0 {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+    }
+    true
+}
+
+// Find a free sibling name such as `photo (1).jpg` for the rename policy.
+fn free_name(root: &Path, rel: &Path) -> PathBuf {
+    let stem = rel
+        .file_stem()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let ext = rel
+        .extension()
+        .map(|x| format!(".{}", x.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|i| rel.with_file_name(format!("{} ({}){}", stem, i, ext)))
+        .find(|x| root.join(x).symlink_metadata().is_err())
+        .unwrap()
+}
+
+struct Extractor {
+    root: PathBuf,
+    policy: ConflictPolicy,
+    budget: u64,
+    written: u64,
+    exhausted: bool,
+    /// Size of the write which did not fit, once `exhausted`.
+    shortfall: u64,
+    entries: Vec<ExtractEntryDto>,
+}
+
+impl Extractor {
+    // Create the parent directories of `rel`, refusing to descend through a symlink.
+    fn prepare_parents(&self, rel: &Path) -> Result<(), String> {
+        let mut cur = self.root.clone();
+        for c in rel.parent().into_iter().flat_map(|x| x.components()) {
+            cur.push(c);
+            match cur.symlink_metadata() {
+                Ok(m) if m.is_symlink() => return Err("a parent directory is a symlink".into()),
+                Ok(m) if !m.is_dir() => return Err("a parent path is not a directory".into()),
+                Ok(_) => {}
+                Err(_) => fs::create_dir(&cur).map_err(|e| e.to_string())?,
+            }
+        }
+        Ok(())
+    }
+
+    fn place<R: Read>(
+        &mut self,
+        raw_path: &str,
+        kind: EntryKind,
+        size: u64,
+        mode: Option<u32>,
+        mtime: Option<u64>,
+        reader: &mut R,
+    ) {
+        let mut entry = ExtractEntryDto {
+            path: raw_path.to_string(),
+            action: ExtractAction::Failed,
+            written_to: None,
+            bytes: 0,
+            error: None,
+        };
+        match self.place_inner(raw_path, kind, size, mode, mtime, reader, &mut entry) {
+            Ok(action) => entry.action = action,
+            Err(e) => entry.error = Some(e),
+        }
+        self.entries.push(entry);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn place_inner<R: Read>(
+        &mut self,
+        raw_path: &str,
+        kind: EntryKind,
+        size: u64,
+        mode: Option<u32>,
+        mtime: Option<u64>,
+        reader: &mut R,
+        entry: &mut ExtractEntryDto,
+    ) -> Result<ExtractAction, String> {
+        let mut rel = confine(raw_path).ok_or("path escapes the target directory")?;
+        if let EntryKind::Symlink(ref target) = kind
+            && !link_confined(&rel, target)
+        {
+            return Err("symlink points outside the target directory".into());
+        }
+        if let EntryKind::Other = kind {
+            return Err("unsupported entry type".into());
+        }
+        self.prepare_parents(&rel)?;
+
+        let mut action = ExtractAction::Created;
+        if let Ok(existing) = self.root.join(&rel).symlink_metadata() {
+            match (&kind, self.policy) {
+                (EntryKind::Dir, _) if existing.is_dir() => return Ok(ExtractAction::Skipped),
+                (_, ConflictPolicy::Skip) => return Ok(ExtractAction::Skipped),
+                (_, ConflictPolicy::Overwrite) => {
+                    if existing.is_dir() {
+                        return Err("a directory already exists at this path".into());
+                    }
+                    fs::remove_file(self.root.join(&rel)).map_err(|e| e.to_string())?;
+                    action = ExtractAction::Overwritten;
+                }
+                (_, ConflictPolicy::Rename) => {
+                    rel = free_name(&self.root, &rel);
+                    entry.written_to = Some(rel.to_string_lossy().to_string());
+                    action = ExtractAction::Renamed;
+                }
+            }
+        }
+
+        let abs_path = self.root.join(&rel);
+        match kind {
+            EntryKind::Dir => fs::create_dir(&abs_path).map_err(|e| e.to_string())?,
+            EntryKind::Symlink(target) => symlink(target, &abs_path).map_err(|e| e.to_string())?,
+            EntryKind::File => {
+                let remaining = self.budget.saturating_sub(self.written);
+                if size > remaining {
+                    self.exhausted = true;
+                    self.shortfall = size;
+                    return Err("mount quota exceeded".into());
+                }
+                let mut file = OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&abs_path)
+                    .map_err(|e| e.to_string())?;
+                // Headers can lie about the size, so cap what is actually copied as well.
+                let copied = io::copy(&mut reader.take(remaining + 1), &mut file);
+                let copied = match copied {
+                    Ok(n) if n <= remaining => n,
+                    other => {
+                        let _ = fs::remove_file(&abs_path);
+                        return match other {
+                            Ok(_) => {
+                                self.exhausted = true;
+                                self.shortfall = size.max(remaining + 1);
+                                Err("mount quota exceeded".into())
+                            }
+                            Err(e) if capacity::is_out_of_space(&e) => {
+                                self.exhausted = true;
+                                self.shortfall = size;
+                                Err("mount quota exceeded".into())
+                            }
+                            Err(e) => Err(e.to_string()),
+                        };
+                    }
+                };
+                if let Some(m) = mtime {
+                    let _ = file.set_modified(UNIX_EPOCH + Duration::from_secs(m));
+                }
+                self.written += copied;
+                entry.bytes = copied;
+            }
+            EntryKind::Other => unreachable!(),
+        }
+        if let Some(m) = mode {
+            // Only permission bits are kept; setuid and friends are dropped.
+            let _ = fs::set_permissions(&abs_path, fs::Permissions::from_mode(m & 0o777));
+        }
+        Ok(action)
+    }
+}
+
+fn extract_tar<R: Read>(ex: &mut Extractor, r: R) -> io::Result<()> {
+    let mut archive = tar::Archive::new(r);
+    for item in archive.entries()? {
+        if ex.exhausted {
+            break;
+        }
+        let mut item = item?;
+        let raw_path = item.path()?.to_string_lossy().to_string();
+        let header = item.header();
+        let e_type = header.entry_type();
+        let kind = if e_type.is_dir() {
+            EntryKind::Dir
+        } else if e_type.is_file() {
+            EntryKind::File
+        } else if e_type.is_symlink() {
+            match item.link_name()? {
+                Some(x) => EntryKind::Symlink(x.to_path_buf()),
+                None => EntryKind::Other,
+            }
+        } else if e_type.is_pax_global_extensions() || e_type.is_pax_local_extensions() {
+            continue;
+        } else {
+            EntryKind::Other // hard links, devices and fifos
+        };
+        let mode = header.mode().ok();
+        let mtime = header.mtime().ok();
+        let size = item.size();
+        ex.place(raw_path.as_str(), kind, size, mode, mtime, &mut item);
+    }
+    Ok(())
+}
+
+fn extract_zip<R: Read + Seek>(ex: &mut Extractor, r: R) -> io::Result<()> {
+    let mut archive = ZipArchive::new(r)?;
+    for i in 0..archive.len() {
+        if ex.exhausted {
+            break;
+        }
+        let mut item = archive.by_index(i)?;
+        let raw_path = item.name().to_string();
+        let kind = if item.is_dir() {
+            EntryKind::Dir
+        } else if item.is_symlink() {
+            let mut target = String::new();
+            item.read_to_string(&mut target)?;
+            EntryKind::Symlink(target.into())
+        } else {
+            EntryKind::File
+        };
+        let mode = item.unix_mode();
+        let size = item.size();
+        ex.place(raw_path.as_str(), kind, size, mode, None, &mut item);
+    }
+    Ok(())
+}
+
+fn run_extract(
+    format: ArchiveFormat,
+    ex: &mut Extractor,
+    reader: ChunkReader,
+) -> io::Result<()> {
+    match format {
+        ArchiveFormat::Tar => extract_tar(ex, reader),
+        ArchiveFormat::TarGz => extract_tar(ex, flate2::read::GzDecoder::new(reader)),
+        ArchiveFormat::Zip => {
+            // The central directory sits at the end of a zip, so it has to be spooled first.
+            // The spool is kept in the target, where it takes from the same budget as
+            // the entries extracted next to it.
+            let s_path = ex.root.join(format!(".neptis-{}.zip", Uuid::new_v4()));
+            let ret = (|| {
+                let mut spool = File::options()
+                    .read(true)
+                    .write(true)
+                    .create_new(true)
+                    .open(&s_path)?;
+                let spooled = io::copy(&mut reader.take(ex.budget + 1), &mut spool)?;
+                if spooled > ex.budget {
+                    ex.exhausted = true;
+                    ex.shortfall = spooled;
+                    return Ok(());
+                }
+                ex.budget -= spooled;
+                spool.rewind()?;
+                extract_zip(ex, spool)
+            })();
+            let _ = fs::remove_file(&s_path);
+            ret
+        }
+    }
+}
+
+/// Extracts an uploaded archive into `target.root`, reading the body as it arrives.
+pub async fn extract_stream(
+    format: ArchiveFormat,
+    policy: ConflictPolicy,
+    rel_target: &str,
+    target: &ExtractTarget,
+    mut body: DataStream<'_>,
+) -> Result<ExtractReportDto, NeptisError> {
+    let (tx, rx) = mpsc::channel::<Vec<u8>>(4);
+    let mut ex = Extractor {
+        root: target.root.clone(),
+        policy,
+        budget: target.capacity.remaining(),
+        written: 0,
+        exhausted: false,
+        shortfall: 0,
+        entries: vec![],
+    };
+    let task = rocket::tokio::task::spawn_blocking(move || {
+        let reader = ChunkReader {
+            rx,
+            buf: vec![],
+            pos: 0,
+        };
+        let ret = run_extract(format, &mut ex, reader);
+        (ex, ret)
+    });
+
+    loop {
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        let n = body.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        chunk.truncate(n);
+        if tx.send(chunk).await.is_err() {
+            break; // the extractor stopped early
+        }
+    }
+    drop(tx);
+
+    let (ex, ret) = task
+        .await
+        .map_err(|e| NeptisError::InternalError(format!("Extraction panicked: {}", e)))?;
+    if ex.exhausted {
+        return Err(target.capacity.exceeded_after(ex.written, ex.shortfall));
+    }
+    if let Err(e) = ret.as_ref()
+        && ex.entries.is_empty()
+    {
+        return Err(NeptisError::BadRequest(format!("Failed to read archive: {}", e)));
+    }
+    Ok(ExtractReportDto {
+        target: rel_target.to_string(),
+        total_bytes: ex.written,
+        complete: ret.is_ok(),
+        error: ret.err().map(|e| e.to_string()),
+        entries: ex.entries,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn confine_keeps_relative_paths() {
+        assert_eq!(confine("a/b.txt"), Some(PathBuf::from("a/b.txt")));
+        assert_eq!(confine("./a/./b"), Some(PathBuf::from("a/b")));
+        assert_eq!(confine("dir/"), Some(PathBuf::from("dir")));
+    }
+
+    #[test]
+    fn confine_refuses_escapes() {
+        assert_eq!(confine("/etc/passwd"), None);
+        assert_eq!(confine("../x"), None);
+        assert_eq!(confine("a/../../x"), None);
+        assert_eq!(confine("a/../b"), None);
+        assert_eq!(confine(""), None);
+        assert_eq!(confine("."), None);
+    }
+
+    #[test]
+    fn link_confined_follows_depth() {
+        assert!(link_confined(Path::new("link"), Path::new("file")));
+        assert!(link_confined(Path::new("a/link"), Path::new("../file")));
+        assert!(link_confined(Path::new("a/b/link"), Path::new("../../c/./file")));
+        assert!(!link_confined(Path::new("link"), Path::new("../file")));
+        assert!(!link_confined(Path::new("a/link"), Path::new("../../file")));
+        assert!(!link_confined(Path::new("a/link"), Path::new("/etc/passwd")));
+        // Climbing out and back in still passes outside the target on the way.
+        assert!(!link_confined(Path::new("link"), Path::new("../target/file")));
+        // A `..` after descending could back out of a symlink rather than a directory.
+        assert!(link_confined(Path::new("a/up"), Path::new("..")));
+        assert!(!link_confined(Path::new("esc"), Path::new("a/up/../..")));
+        assert!(!link_confined(Path::new("a/b/link"), Path::new("../c/../file")));
+    }
+
+    #[test]
+    fn chained_symlinks_cannot_escape() {
+        for order in [["a/up", "esc"], ["esc", "a/up"]] {
+            let root = std::env::temp_dir().join(format!("neptis-extract-{}", Uuid::new_v4()));
+            fs::create_dir(&root).unwrap();
+            let mut ex = Extractor {
+                root: root.clone(),
+                policy: ConflictPolicy::Skip,
+                budget: 0,
+                written: 0,
+                exhausted: false,
+                shortfall: 0,
+                entries: vec![],
+            };
+            for name in order {
+                let target = if name == "esc" { "a/up/../.." } else { ".." };
+                let kind = EntryKind::Symlink(PathBuf::from(target));
+                ex.place(name, kind, 0, None, None, &mut io::empty());
+            }
+
+            let up = ex.entries.iter().find(|x| x.path == "a/up").unwrap();
+            let esc = ex.entries.iter().find(|x| x.path == "esc").unwrap();
+            assert_eq!(up.action, ExtractAction::Created);
+            assert_eq!(esc.action, ExtractAction::Failed);
+            assert!(root.join("esc").symlink_metadata().is_err());
+            fs::remove_dir_all(&root).unwrap();
+        }
+    }
+}
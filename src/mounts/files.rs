@@ -0,0 +1,344 @@
+use std::fs::{self, Metadata};
+use std::io::{self, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use rocket::data::DataStream;
+use rocket::http::{ContentType, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::tokio::fs::{File, OpenOptions};
+use rocket::tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::capacity::Capacity;
+use crate::api::errors::NeptisError;
+
+/// Largest upload accepted when no `upload` limit is configured under `[limits]`.
+pub const DEFAULT_UPLOAD_LIMIT: u64 = 1 << 30;
+
+/// The headers a file request may carry to ask for part of a file, or for it only when
+/// it changed.
+pub struct FileConditions {
+    pub range: Option<String>,
+    pub if_range: Option<String>,
+    pub if_match: Option<String>,
+    pub if_none_match: Option<String>,
+    pub content_length: Option<u64>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for FileConditions {
+    type Error = ();
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let header = |name: &str| req.headers().get_one(name).map(|x| x.trim().to_string());
+        request::Outcome::Success(FileConditions {
+            range: header("Range"),
+            if_range: header("If-Range"),
+            if_match: header("If-Match"),
+            if_none_match: header("If-None-Match"),
+            content_length: header("Content-Length").and_then(|x| x.parse().ok()),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UploadDto {
+    pub bytes_written: u64,
+    /// Size of the whole file once written.
+    pub size: u64,
+    pub etag: String,
+}
+
+/// A resolved upload destination. The shared lock must be released once the body is
+/// written.
+pub struct UploadTarget {
+    pub path: PathBuf,
+    pub capacity: Capacity,
+    pub lock_id: Uuid,
+}
+
+/// Validator of the file behind `meta`, which changes whenever it is written to or
+/// replaced.
+pub fn etag_of(meta: &Metadata) -> String {
+    format!(
+        "\"{:x}-{:x}-{:x}.{:x}\"",
+        meta.ino(),
+        meta.size(),
+        meta.mtime(),
+        meta.mtime_nsec()
+    )
+}
+
+// Whether `etag` is listed in an `If-Match` or `If-None-Match` header.
+fn etag_listed(header: &str, etag: &str) -> bool {
+    header.split(',').map(|x| x.trim()).any(|x| {
+        x == "*" || x.strip_prefix("W/").unwrap_or(x) == etag
+    })
+}
+
+/// Refuses to write when `If-Match` names another version than the one on disk, or
+/// any version while the file does not exist yet.
+pub fn ensure_matches(cond: &FileConditions, meta: Option<&Metadata>) -> Result<(), NeptisError> {
+    let Some(ref header) = cond.if_match else {
+        return Ok(());
+    };
+    match meta.map(etag_of) {
+        Some(etag) if etag_listed(header, etag.as_str()) => Ok(()),
+        _ => Err(NeptisError::PreconditionFailed(
+            "The file has changed since it was read".into(),
+        )),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    Full,
+    /// First and last byte, inclusive.
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+// Only a single range is served; anything else gets the whole file, as the header
+// may be ignored.
+fn parse_range(header: &str, len: u64) -> ByteRange {
+    let Some(spec) = header.strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+    let Some((start, end)) = spec.trim().split_once('-').filter(|_| !spec.contains(',')) else {
+        return ByteRange::Full;
+    };
+    match (start.trim(), end.trim()) {
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if len == 0 => ByteRange::Unsatisfiable,
+            Ok(n) => ByteRange::Partial(len.saturating_sub(n), len - 1),
+            Err(_) => ByteRange::Full,
+        },
+        (start, end) => {
+            let Ok(start) = start.parse::<u64>() else {
+                return ByteRange::Full;
+            };
+            let end = match end {
+                "" => u64::MAX,
+                x => match x.parse::<u64>() {
+                    Ok(x) if x >= start => x,
+                    _ => return ByteRange::Full,
+                },
+            };
+            match start < len {
+                true => ByteRange::Partial(start, end.min(len - 1)),
+                false => ByteRange::Unsatisfiable,
+            }
+        }
+    }
+}
+
+/// A file, or the part of it asked for through `Range`, streamed straight from disk.
+pub struct FileResponse {
+    status: Status,
+    etag: String,
+    content_type: ContentType,
+    /// The file, positioned at the first byte to send, with the number of bytes to send.
+    body: Option<(File, u64)>,
+    content_range: Option<String>,
+}
+
+impl<'r> Responder<'r, 'static> for FileResponse {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let mut res = Response::build();
+        res.status(self.status)
+            .raw_header("Accept-Ranges", "bytes")
+            .raw_header("ETag", self.etag);
+        if let Some(range) = self.content_range {
+            res.raw_header("Content-Range", range);
+        }
+        if let Some((file, n)) = self.body {
+            // The length is known up front, so the body is not sent chunked.
+            res.header(self.content_type)
+                .raw_header("Content-Length", n.to_string())
+                .streamed_body(file.take(n));
+        } else if self.status == Status::RangeNotSatisfiable {
+            res.raw_header("Content-Length", "0");
+        }
+        res.ok()
+    }
+}
+
+/// Opens `path` to be sent back, honouring `If-None-Match`, `Range` and `If-Range`.
+pub async fn open_file(path: &str, cond: &FileConditions) -> Result<FileResponse, NeptisError> {
+    let mut file = File::open(path).await?;
+    let meta = file.metadata().await?;
+    let etag = etag_of(&meta);
+    let len = meta.len();
+    let content_type = Path::new(path)
+        .extension()
+        .and_then(|x| ContentType::from_extension(&x.to_string_lossy()))
+        .unwrap_or(ContentType::Binary);
+    let mut output = FileResponse {
+        status: Status::Ok,
+        etag: etag.clone(),
+        content_type,
+        body: None,
+        content_range: None,
+    };
+
+    if cond
+        .if_none_match
+        .as_deref()
+        .is_some_and(|x| etag_listed(x, etag.as_str()))
+    {
+        output.status = Status::NotModified;
+        return Ok(output);
+    }
+
+    // A range is only honoured against the version the client already holds part of.
+    let range = match (cond.range.as_deref(), cond.if_range.as_deref()) {
+        (Some(_), Some(x)) if x != etag => ByteRange::Full,
+        (Some(x), _) => parse_range(x, len),
+        (None, _) => ByteRange::Full,
+    };
+    match range {
+        ByteRange::Full => output.body = Some((file, len)),
+        ByteRange::Partial(start, end) => {
+            file.seek(SeekFrom::Start(start)).await?;
+            output.status = Status::PartialContent;
+            output.content_range = Some(format!("bytes {}-{}/{}", start, end, len));
+            output.body = Some((file, end - start + 1));
+        }
+        ByteRange::Unsatisfiable => {
+            output.status = Status::RangeNotSatisfiable;
+            output.content_range = Some(format!("bytes */{}", len));
+        }
+    }
+    Ok(output)
+}
+
+// Where a whole-file upload is written before it replaces the file.
+fn staging_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.upload-{}", name, Uuid::new_v4()))
+}
+
+async fn write_body(
+    file: &mut File,
+    body: DataStream<'_>,
+    expected: u64,
+    capacity: &Capacity,
+) -> Result<u64, NeptisError> {
+    let n = body
+        .stream_to(&mut *file)
+        .await
+        .map_err(|e| capacity.map_write_error(e))?;
+    file.flush().await.map_err(|e| capacity.map_write_error(e))?;
+    if !n.complete || n.written != expected {
+        return Err(NeptisError::BadRequest(format!(
+            "The body did not match its Content-Length of {} bytes",
+            expected
+        )));
+    }
+    Ok(n.written)
+}
+
+/// Writes exactly `expected` bytes of `body` into the target. Without `offset` the
+/// file is replaced once the whole body has arrived, so a broken upload leaves it as
+/// it was. With `offset`, the bytes are written in place from there, so a large file
+/// can be sent in parts and an interrupted part resent.
+pub async fn write_upload(
+    target: &UploadTarget,
+    offset: Option<u64>,
+    expected: u64,
+    body: DataStream<'_>,
+) -> Result<UploadDto, NeptisError> {
+    let written = match offset {
+        Some(offset) => {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&target.path)
+                .await?;
+            file.seek(SeekFrom::Start(offset)).await?;
+            write_body(&mut file, body, expected, &target.capacity).await?
+        }
+        None => {
+            let staging = staging_path(&target.path);
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&staging)
+                .await?;
+            let ret = async {
+                let n = write_body(&mut file, body, expected, &target.capacity).await?;
+                if let Ok(meta) = fs::metadata(&target.path) {
+                    fs::set_permissions(&staging, meta.permissions())?;
+                }
+                rocket::tokio::fs::rename(&staging, &target.path).await?;
+                Ok::<_, NeptisError>(n)
+            }
+            .await;
+            if ret.is_err() {
+                let _ = rocket::tokio::fs::remove_file(&staging).await;
+            }
+            ret?
+        }
+    };
+    let meta = fs::metadata(&target.path)?;
+    Ok(UploadDto {
+        bytes_written: written,
+        size: meta.len(),
+        etag: etag_of(&meta),
+    })
+}
+
+/// What is at `path` now, if anything. Directories cannot be uploaded over.
+pub fn existing_file(path: &str) -> Result<Option<Metadata>, NeptisError> {
+    match fs::metadata(path) {
+        Ok(x) if x.is_dir() => Err(NeptisError::BadRequest(
+            "A directory already exists at the path!".into(),
+        )),
+        Ok(x) => Ok(Some(x)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), ByteRange::Partial(0, 99));
+        assert_eq!(parse_range("bytes=500-", 1000), ByteRange::Partial(500, 999));
+        assert_eq!(parse_range("bytes=900-5000", 1000), ByteRange::Partial(900, 999));
+        assert_eq!(parse_range("bytes= 10 - 20 ", 1000), ByteRange::Partial(10, 20));
+    }
+
+    #[test]
+    fn suffix_ranges() {
+        assert_eq!(parse_range("bytes=-100", 1000), ByteRange::Partial(900, 999));
+        assert_eq!(parse_range("bytes=-5000", 1000), ByteRange::Partial(0, 999));
+        assert_eq!(parse_range("bytes=-0", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-10", 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn out_of_bounds_ranges_are_unsatisfiable() {
+        assert_eq!(parse_range("bytes=1000-", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn unsupported_headers_serve_the_whole_file() {
+        assert_eq!(parse_range("items=0-10", 1000), ByteRange::Full);
+        assert_eq!(parse_range("bytes=0-10,20-30", 1000), ByteRange::Full);
+        assert_eq!(parse_range("bytes=20-10", 1000), ByteRange::Full);
+        assert_eq!(parse_range("bytes=a-b", 1000), ByteRange::Full);
+        assert_eq!(parse_range("bytes=-x", 1000), ByteRange::Full);
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+use chrono::{Duration, NaiveDateTime};
+use rocket::serde::json::serde_json::json;
+use rocket_db_pools::diesel::PgPool;
+use serde::{Deserialize, Serialize};
+
+use super::models::*;
+use crate::prelude::action_prelude::*;
+use crate::webhooks::dispatch;
+use crate::webhooks::models::WebhookEvent;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FreshnessDto {
+    pub owned_by: String,
+    pub mount_name: String,
+    pub rpo_hours: i32,
+    pub last_success: Option<NaiveDateTime>,
+    pub stale: bool,
+    /// Hours past the objective; negative while the point is still fresh.
+    pub overdue_hours: f64,
+}
+
+/// Compares every point with an objective to its newest successful backup. A point
+/// which has never been backed up is measured from its creation.
+pub async fn evaluate_freshness(
+    conn: &mut AsyncPgConnection,
+) -> Result<Vec<FreshnessDto>, NeptisError> {
+    let points: Vec<Mount> = {
+        use crate::schema::mounts::dsl::*;
+        mounts.filter(rpo_hours.is_not_null()).get_results(conn).await?
+    };
+    let last: HashMap<(String, String), Option<NaiveDateTime>> = {
+        use crate::schema::repo_jobs::dsl::*;
+        repo_jobs
+            .filter(
+                job_type
+                    .eq(JobType::Backup)
+                    .and(job_status.eq(JobStatus::Successful))
+                    .and(launch_dry_run.eq(false))
+                    .and(snapshot_id.is_not_null()),
+            )
+            .group_by((point_owned_by, point_name))
+            .select((point_owned_by, point_name, diesel::dsl::max(end_date)))
+            .get_results::<(String, String, Option<NaiveDateTime>)>(conn)
+            .await?
+            .into_iter()
+            .map(|(o, n, d)| ((o, n), d))
+            .collect()
+    };
+
+    let now = utc_now!();
+    Ok(points
+        .into_iter()
+        .filter_map(|point| {
+            let rpo = point.rpo_hours?;
+            let last_success = last
+                .get(&(point.owned_by.clone(), point.mount_name.clone()))
+                .cloned()
+                .flatten();
+            let since = last_success.unwrap_or(point.date_created);
+            let overdue = now - (since + Duration::hours(rpo as i64));
+            Some(FreshnessDto {
+                owned_by: point.owned_by,
+                mount_name: point.mount_name,
+                rpo_hours: rpo,
+                last_success,
+                stale: overdue > Duration::zero(),
+                overdue_hours: overdue.num_seconds() as f64 / 3600.0,
+            })
+        })
+        .collect())
+}
+
+/// Stores the stale flag of every point, raising an event for each one which changed.
+pub async fn refresh_stale_flags(conn: &mut AsyncPgConnection) -> Result<usize, NeptisError> {
+    use crate::schema::mounts::dsl::*;
+
+    let report = evaluate_freshness(conn).await?;
+    let flagged: Vec<(String, String)> = mounts
+        .filter(is_stale.eq(true))
+        .select((owned_by, mount_name))
+        .get_results(conn)
+        .await?;
+
+    let mut changed = 0;
+    for item in report.iter() {
+        let key = (item.owned_by.clone(), item.mount_name.clone());
+        if flagged.contains(&key) == item.stale {
+            continue;
+        }
+        diesel::update(mounts.find(key))
+            .set(is_stale.eq(item.stale))
+            .execute(conn)
+            .await?;
+        dispatch::emit(
+            item.owned_by.as_str(),
+            if item.stale {
+                WebhookEvent::MountStale
+            } else {
+                WebhookEvent::MountRecovered
+            },
+            json!({
+                "mount": item.mount_name,
+                "rpo_hours": item.rpo_hours,
+                "last_success": item.last_success,
+                "overdue_hours": item.overdue_hours,
+            }),
+        );
+        changed += 1;
+    }
+
+    // Points whose objective was removed are no longer considered stale.
+    for key in flagged
+        .into_iter()
+        .filter(|x| !report.iter().any(|r| r.owned_by == x.0 && r.mount_name == x.1))
+    {
+        diesel::update(mounts.find(key))
+            .set(is_stale.eq(false))
+            .execute(conn)
+            .await?;
+        changed += 1;
+    }
+    Ok(changed)
+}
+
+/// Spawns the background task which periodically re-evaluates backup freshness.
+pub fn spawn_freshness_monitor(pool: PgPool) {
+    let interval_secs: u64 = get_env_or!("FRESHNESS_INTERVAL", 300);
+    if interval_secs == 0 {
+        return;
+    }
+    rocket::tokio::spawn(async move {
+        let mut timer =
+            rocket::tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+        loop {
+            timer.tick().await;
+            let Ok(mut conn) = pool.get().await else {
+                continue;
+            };
+            if let Err(e) = refresh_stale_flags(&mut conn).await {
+                println!("Failed to check backup freshness: {}", e);
+            }
+        }
+    });
+}
@@ -0,0 +1,232 @@
+use std::{env, thread};
+
+use chrono::Duration;
+use diesel::{Connection, PgConnection};
+use rocket_db_pools::diesel::PgPool;
+
+use super::idle;
+use super::locks;
+use super::models::*;
+use super::rustic_async::{JobLaunchInfo, NonBlockingRustic};
+use super::storage;
+use crate::api::command::CommandOutput;
+use crate::prelude::action_prelude::*;
+use crate::webhooks::dispatch;
+
+/// Only points with a filesystem image of their own can be checked.
+pub fn ensure_checkable(point: &Mount) -> Result<(), NeptisError> {
+    match point.backend {
+        StorageBackend::LoopExt4 | StorageBackend::LuksExt4 => Ok(()),
+        _ => Err(NeptisError::BadRequest(
+            "The point is not stored in a filesystem image of its own!".into(),
+        )),
+    }
+}
+
+// The exit status of e2fsck is a bit mask: 1 and 2 mean errors were fixed, 4 that some
+// were left alone, and anything higher that the check itself failed.
+fn health_of(output: &CommandOutput) -> Result<FsHealth, NeptisError> {
+    match output.status {
+        Some(c) if c & !7 != 0 => Err(NeptisError::InternalError(format!(
+            "e2fsck failed ({})",
+            Cmd::describe(output)
+        ))),
+        Some(c) if c & 4 != 0 => Ok(FsHealth::Corrupt),
+        Some(c) if c & 3 != 0 => Ok(FsHealth::Repaired),
+        Some(_) => Ok(FsHealth::Clean),
+        None => Err(NeptisError::InternalError(format!(
+            "e2fsck did not finish ({})",
+            Cmd::describe(output)
+        ))),
+    }
+}
+
+// Checks both areas of `point`, which are unmounted first. `ensure_point_mounted`
+// brings them back on the next request.
+fn check_point(point: &Mount, repair: bool, logs: &mut Vec<String>) -> Result<FsHealth, NeptisError> {
+    idle::detach_areas(point, true, true)?;
+    let storage = storage::for_point(point);
+    let mut health = FsHealth::Clean;
+    for (area, img_path, mnt_path) in [
+        ("data", point.data_img_path.as_str(), point.data_mnt_path.as_str()),
+        ("repo", point.repo_img_path.as_str(), point.repo_mnt_path.as_str()),
+    ] {
+        let output = storage.check_fs(img_path, mnt_path, repair)?;
+        logs.extend(
+            output
+                .stdout
+                .lines()
+                .chain(output.stderr.lines())
+                .filter(|x| !x.trim().is_empty())
+                .map(|x| format!("[{}] {}", area, x)),
+        );
+        health = health.max(health_of(&output)?);
+    }
+    Ok(health)
+}
+
+/// Starts a filesystem check of `point` as a job. The caller must hold an exclusive
+/// lock in `launch_info.lock_id`, which is released once the check finishes.
+pub fn start_fsck(
+    launch_info: &JobLaunchInfo,
+    point: &Mount,
+    repair: bool,
+) -> Result<Uuid, NeptisError> {
+    use crate::schema::repo_jobs::dsl::*;
+
+    let job_id = Uuid::new_v4();
+    let lock_id = launch_info.lock_id;
+    let s_job = RepoJob {
+        id: job_id,
+        snapshot_id: None,
+        point_owned_by: launch_info.point_owned_by.clone(),
+        point_name: launch_info.point_name.clone(),
+        job_type: JobType::Fsck,
+        job_status: JobStatus::Running,
+        used_bytes: 0,
+        total_bytes: None,
+        errors: vec![],
+        create_date: utc_now!(),
+        end_date: None,
+        acted_by: launch_info.acted_by.clone(),
+        target_path: None,
+        launch_dry_run: false,
+        launch_read_data: false,
+        launch_tags: vec![],
+        logs: vec![],
+    };
+    let mut conn =
+        PgConnection::establish(&env::var("DATABASE_URL").expect("DATABASE_URL must be set"))?;
+    diesel::RunQueryDsl::execute(diesel::insert_into(repo_jobs).values(&s_job), &mut conn)?;
+    NonBlockingRustic::attach_lock(&mut conn, lock_id, job_id)?;
+    dispatch::emit_job(&s_job);
+
+    let point = point.clone();
+    thread::spawn(move || {
+        let mut c_logs = vec![];
+        let ret = check_point(&point, repair, &mut c_logs);
+        finish_fsck(job_id, lock_id, &point, ret, c_logs, &mut conn);
+    });
+    Ok(job_id)
+}
+
+// The job threads use their own blocking connection, so the queries go through
+// `diesel::RunQueryDsl` by name rather than the async one from the prelude.
+fn finish_fsck(
+    job_id: Uuid,
+    lock_id: Option<Uuid>,
+    point: &Mount,
+    ret: Result<FsHealth, NeptisError>,
+    c_logs: Vec<String>,
+    conn: &mut PgConnection,
+) {
+    use crate::schema::repo_jobs::dsl::*;
+    NonBlockingRustic::release_lock(conn, lock_id);
+    let f_job: Result<RepoJob, _> = diesel::RunQueryDsl::get_result(repo_jobs.find(job_id), conn);
+    let Ok(mut f_job) = f_job else {
+        return;
+    };
+
+    if let Ok(p_health) = ret {
+        use crate::schema::mounts::dsl as m;
+        let target = m::mounts.find((point.owned_by.clone(), point.mount_name.clone()));
+        let _ = diesel::RunQueryDsl::execute(
+            diesel::update(target)
+                .set((m::health.eq(Some(p_health)), m::health_checked.eq(Some(utc_now!())))),
+            conn,
+        );
+    }
+
+    f_job.logs.extend(c_logs);
+    let outcome = match ret {
+        Ok(FsHealth::Corrupt) => Err("Filesystem errors were found and left unrepaired".into()),
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    };
+    // A cancelled job keeps its status; the outcome is only noted.
+    f_job.end_date = Some(utc_now!());
+    if f_job.job_status == JobStatus::Cancelled {
+        f_job.errors.push(match outcome {
+            Ok(_) => "Job completed after it was cancelled".into(),
+            Err(e) => e,
+        });
+    } else {
+        match outcome {
+            Ok(_) => f_job.job_status = JobStatus::Successful,
+            Err(e) => {
+                f_job.job_status = JobStatus::Failed;
+                f_job.errors.push(e);
+            }
+        }
+    }
+    let _ = diesel::RunQueryDsl::execute(diesel::update(repo_jobs.find(job_id)).set(&f_job), conn);
+    dispatch::emit_job(&f_job);
+}
+
+/// Starts a read-only check of every point whose `fsck_hours` have passed since its
+/// last check. Busy points are skipped until the next pass. Returns the number started.
+pub async fn run_due_checks(conn: &mut AsyncPgConnection) -> Result<usize, NeptisError> {
+    let points: Vec<Mount> = {
+        use crate::schema::mounts::dsl::*;
+        mounts
+            .filter(fsck_hours.is_not_null().and(locked.eq(false)))
+            .get_results(conn)
+            .await?
+    };
+
+    let now = utc_now!();
+    let mut started = 0;
+    for point in points {
+        let Some(hours) = point.fsck_hours else {
+            continue;
+        };
+        let since = point.health_checked.unwrap_or(point.date_created);
+        if since + Duration::hours(hours as i64) > now || ensure_checkable(&point).is_err() {
+            continue;
+        }
+        let Ok(lock_id) = locks::acquire_lock(conn, &point, true, "fsck").await else {
+            continue;
+        };
+        let options = JobLaunchInfo {
+            point_owned_by: point.owned_by.clone(),
+            point_name: point.mount_name.clone(),
+            data_path: point.data_mnt_path.clone(),
+            repo_path: format!("{}/repo", point.repo_mnt_path.as_str()),
+            repo_pass: point.repo_password.clone(),
+            acted_by: None,
+            lock_id: Some(lock_id),
+        };
+        match start_fsck(&options, &point, false) {
+            Ok(_) => started += 1,
+            Err(e) => {
+                locks::release_lock(conn, lock_id).await?;
+                println!(
+                    "Failed to start the filesystem check of {}/{}: {}",
+                    point.owned_by, point.mount_name, e
+                );
+            }
+        }
+    }
+    Ok(started)
+}
+
+/// Spawns the background task which starts scheduled filesystem checks.
+pub fn spawn_fsck_scheduler(pool: PgPool) {
+    let interval_secs: u64 = get_env_or!("FSCK_CHECK_INTERVAL", 3600);
+    if interval_secs == 0 {
+        return;
+    }
+    rocket::tokio::spawn(async move {
+        let mut timer =
+            rocket::tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+        loop {
+            timer.tick().await;
+            let Ok(mut conn) = pool.get().await else {
+                continue;
+            };
+            if let Err(e) = run_due_checks(&mut conn).await {
+                println!("Failed to schedule filesystem checks: {}", e);
+            }
+        }
+    });
+}
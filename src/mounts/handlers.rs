@@ -1,6 +1,51 @@
-use super::{actions, dtos::*, rustic_async::NonBlockingRustic};
+use super::archive::{self, ArchiveFormat};
+use super::extract::{self, ConflictPolicy, ExtractReportDto};
+use super::files::{self, FileConditions, FileResponse, UploadDto};
+use super::locks;
+use super::{actions, dtos::*, repo_stats::RepoStatsDto, rustic_async::NonBlockingRustic};
 use crate::prelude::route_prelude::*;
+use rocket::data::{Data, Limits, ToByteUnit};
+use rocket::http::{ContentType, Header};
+use rocket::response::stream::ByteStream;
+use rocket::Responder;
 use rocket::{State, response::content::RawText};
+use std::path::{Path, PathBuf};
+
+#[derive(Responder)]
+pub struct ArchiveResponse<S> {
+    inner: S,
+    content_type: ContentType,
+    disposition: Header<'static>,
+}
+
+async fn stream_archive(
+    conn: &mut Connection<Db>,
+    auth_user: &User,
+    path: String,
+    format: ArchiveFormat,
+    filter: Vec<String>,
+) -> Result<ArchiveResponse<ByteStream![Vec<u8>]>, NeptisError> {
+    let abs_path = actions::stage_archive_async(conn, auth_user, path.as_str()).await?;
+    let f_name = path
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or("archive")
+        .to_string();
+    let mut rx = archive::stream_archive(format, abs_path.into(), filter);
+    Ok(ArchiveResponse {
+        inner: ByteStream! {
+            while let Some(chunk) = rx.recv().await {
+                yield chunk;
+            }
+        },
+        content_type: format.content_type(),
+        disposition: Header::new(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}.{}\"", f_name, format.extension()),
+        ),
+    })
+}
 
 #[get("/")]
 async fn get_all_mounts(
@@ -35,6 +80,54 @@ async fn put_one_mount(
     ))
 }
 
+#[patch("/id/<name>", data = "<dto>")]
+async fn patch_one_mount(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    name: &str,
+    dto: Json<PatchForMountApi>,
+) -> Result<Json<MountDto>, NeptisError> {
+    Ok(Json(
+        actions::rename_mount_async(&mut conn, &auth_user, name, dto.into_inner()).await?,
+    ))
+}
+
+#[post("/id/<name>/unlock", data = "<dto>")]
+async fn unlock_one_mount(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    name: &str,
+    dto: Json<PostForUnlockApi>,
+) -> Result<Json<MountDto>, NeptisError> {
+    Ok(Json(
+        actions::unlock_mount_async(&mut conn, &auth_user, name, dto.into_inner()).await?,
+    ))
+}
+
+#[put("/id/<name>/rpo", data = "<dto>")]
+async fn put_one_rpo(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    name: &str,
+    dto: Json<PutForRpoApi>,
+) -> Result<Json<MountDto>, NeptisError> {
+    Ok(Json(
+        actions::put_rpo_async(&mut conn, &auth_user, name, dto.into_inner()).await?,
+    ))
+}
+
+#[put("/id/<name>/fsck", data = "<dto>")]
+async fn put_one_fsck(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    name: &str,
+    dto: Json<PutForFsckApi>,
+) -> Result<Json<MountDto>, NeptisError> {
+    Ok(Json(
+        actions::put_fsck_async(&mut conn, &auth_user, name, dto.into_inner()).await?,
+    ))
+}
+
 #[post("/id/<name>/backup")]
 async fn post_one_backup(
     mut conn: Connection<Db>,
@@ -58,6 +151,19 @@ async fn post_one_backup(
     ))
 }
 
+#[post("/clone", data = "<dto>")]
+async fn post_clone(
+    mut conn: Connection<Db>,
+    handler: &State<NonBlockingRustic>,
+    auth_user: User,
+    dto: Json<PostForCloneApi>,
+) -> Result<Json<RepoJobDto>, NeptisError> {
+    Ok(Json(
+        actions::clone_mount_async(&mut conn, &auth_user, handler.inner(), dto.into_inner())
+            .await?,
+    ))
+}
+
 #[get("/id/<name>/jobs")]
 async fn get_all_jobs_for_mount(
     mut conn: Connection<Db>,
@@ -69,6 +175,28 @@ async fn get_all_jobs_for_mount(
     ))
 }
 
+#[get("/id/<name>/repo/stats")]
+async fn get_repo_stats(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    name: &str,
+) -> Result<Json<RepoStatsDto>, NeptisError> {
+    Ok(Json(
+        actions::get_repo_stats_async(&mut conn, &auth_user, name).await?,
+    ))
+}
+
+#[get("/id/<name>/hooks")]
+async fn get_all_hooks_for_mount(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    name: &str,
+) -> Result<Json<Vec<MountHookDto>>, NeptisError> {
+    Ok(Json(
+        actions::get_all_hooks_async(&mut conn, &auth_user, name).await?,
+    ))
+}
+
 #[delete("/id/<name>")]
 async fn delete_one_mount(
     mut conn: Connection<Db>,
@@ -101,6 +229,60 @@ async fn dump_file(
     ))
 }
 
+#[get("/archive?<path>&<format>&<filter>")]
+async fn get_archive(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    path: &str,
+    format: ArchiveFormat,
+    filter: Vec<String>,
+) -> Result<ArchiveResponse<ByteStream![Vec<u8>]>, NeptisError> {
+    stream_archive(&mut conn, &auth_user, path.to_string(), format, filter).await
+}
+
+#[get("/id/<name>/snapshots/<snap>/archive?<path>&<format>&<filter>")]
+async fn get_snapshot_archive(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    name: &str,
+    snap: &str,
+    path: Option<&str>,
+    format: ArchiveFormat,
+    filter: Vec<String>,
+) -> Result<ArchiveResponse<ByteStream![Vec<u8>]>, NeptisError> {
+    // `restic mount` exposes every snapshot under `ids/`, by its short id.
+    let s_dir = actions::get_snapshot_dir_name_async(&mut conn, &auth_user, name, snap).await?;
+    let s_path = format!(
+        "/{}/repo/ids/{}/{}",
+        name,
+        s_dir,
+        path.unwrap_or("").trim_start_matches('/')
+    );
+    stream_archive(&mut conn, &auth_user, s_path, format, filter).await
+}
+
+#[post("/extract?<path>&<format>&<conflict>", data = "<body>")]
+async fn post_extract(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    path: &str,
+    format: ArchiveFormat,
+    conflict: Option<ConflictPolicy>,
+    body: Data<'_>,
+) -> Result<Json<ExtractReportDto>, NeptisError> {
+    let target = actions::stage_extract(&mut conn, &auth_user, path).await?;
+    let ret = extract::extract_stream(
+        format,
+        conflict.unwrap_or_default(),
+        path,
+        &target,
+        body.open(target.limit.bytes()),
+    )
+    .await;
+    locks::release_lock(&mut conn, target.lock_id).await?;
+    Ok(Json(ret?))
+}
+
 #[put("/file", data = "<dto>")]
 async fn put_file(
     mut conn: Connection<Db>,
@@ -159,18 +341,90 @@ async fn delete_file(
     ))
 }
 
+// Paths under `/api/files` start at the point, like `<name>/data/...`.
+fn file_path(name: &str, path: &Path) -> String {
+    format!("/{}/{}", name, path.to_string_lossy())
+}
+
+#[get("/<name>/<path..>")]
+async fn get_file_stream(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    name: &str,
+    path: PathBuf,
+    cond: FileConditions,
+) -> Result<FileResponse, NeptisError> {
+    let abs_path =
+        actions::stage_download_async(&mut conn, &auth_user, file_path(name, &path).as_str())
+            .await?;
+    files::open_file(abs_path.as_str(), &cond).await
+}
+
+#[put("/<name>/<path..>?<offset>", data = "<body>")]
+#[allow(clippy::too_many_arguments)]
+async fn put_file_stream(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    limits: &Limits,
+    name: &str,
+    path: PathBuf,
+    offset: Option<u64>,
+    cond: FileConditions,
+    body: Data<'_>,
+) -> Result<Json<UploadDto>, NeptisError> {
+    let len = cond.content_length.ok_or(NeptisError::LengthRequired(
+        "Uploads must state their Content-Length".into(),
+    ))?;
+    let limit = limits
+        .get("upload")
+        .map_or(files::DEFAULT_UPLOAD_LIMIT, |x| x.as_u64());
+    if len > limit {
+        return Err(NeptisError::PayloadTooLarge(format!(
+            "Uploads are limited to {} bytes",
+            limit
+        )));
+    }
+    let target = actions::stage_upload(
+        &mut conn,
+        &auth_user,
+        file_path(name, &path).as_str(),
+        offset,
+        len,
+        &cond,
+    )
+    .await?;
+    let ret = files::write_upload(&target, offset, len, body.open(len.bytes())).await;
+    locks::release_lock(&mut conn, target.lock_id).await?;
+    Ok(Json(ret?))
+}
+
+/// Raw file transfers, mounted under `/api/files`.
+pub fn get_file_routes() -> Vec<Route> {
+    routes![get_file_stream, put_file_stream]
+}
+
 pub fn get_routes() -> Vec<Route> {
     routes![
         get_all_mounts,
         get_one_mount,
         put_one_mount,
+        patch_one_mount,
+        unlock_one_mount,
+        put_one_rpo,
+        put_one_fsck,
         delete_one_mount,
         post_one_backup,
+        post_clone,
         browse_file,
         put_file,
         delete_file,
         get_all_jobs_for_mount,
+        get_all_hooks_for_mount,
+        get_repo_stats,
         dump_file,
+        get_archive,
+        get_snapshot_archive,
+        post_extract,
         post_file,
         get_xattrs,
         put_xattrs,
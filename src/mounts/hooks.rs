@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use super::models::{HookType, MountHook};
+use crate::api::command::Cmd;
+use crate::api::errors::NeptisError;
+
+// Run a single hook, returning whether it succeeded along with a log entry.
+fn run_hook(hook: &MountHook, env: &[(&str, String)]) -> (bool, String) {
+    let label = format!("[{:?}] {}", hook.hook_type, hook.command.join(" "));
+    let Some((program, args)) = hook.command.split_first() else {
+        return (false, format!("{}: no command given", label));
+    };
+
+    let output = Cmd::new(program)
+        .args(args)
+        .envs(env)
+        .timeout(Some(Duration::from_secs(hook.timeout_secs.max(1) as u64)))
+        .output();
+    let output = match output {
+        Ok(x) => x,
+        Err(e) => return (false, format!("{}: {}", label, e)),
+    };
+    let result = match output.timed_out {
+        true => format!("timed out after {}s", hook.timeout_secs),
+        false if output.success() => "exited successfully".to_string(),
+        false => format!("exited with {}", Cmd::describe(&output)),
+    };
+    (
+        output.success(),
+        format!(
+            "{}: {}\nstdout:\n{}\nstderr:\n{}",
+            label,
+            result,
+            output.stdout.trim_end(),
+            output.stderr.trim_end()
+        ),
+    )
+}
+
+/// Runs every enabled hook of the given type in order, appending their output to `logs`.
+/// Stops at the first failure.
+pub fn run_hooks(
+    hooks: &[MountHook],
+    h_type: HookType,
+    env: &[(&str, String)],
+    logs: &mut Vec<String>,
+) -> Result<(), NeptisError> {
+    for hook in hooks.iter().filter(|x| x.enabled && x.hook_type == h_type) {
+        let (ok, log) = run_hook(hook, env);
+        logs.push(log);
+        if !ok {
+            return Err(NeptisError::InternalError(format!(
+                "{:?} hook `{}` failed",
+                h_type,
+                hook.command.join(" ")
+            )));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,173 @@
+use chrono::{Duration, NaiveDateTime};
+use rocket_db_pools::diesel::PgPool;
+
+use super::locks;
+use super::models::*;
+use super::storage;
+use crate::prelude::action_prelude::*;
+
+/// Access times are only written once they are older than this, so a busy point does
+/// not cost a row update on every request.
+const TOUCH_GRANULARITY_SECS: i64 = 60;
+
+/// Records that the data or repository area of `point` was just used.
+pub async fn touch_point(
+    conn: &mut AsyncPgConnection,
+    point: &Mount,
+    is_data: bool,
+) -> Result<(), NeptisError> {
+    use crate::schema::mounts::dsl::*;
+
+    let now = utc_now!();
+    let stale = now - Duration::seconds(TOUCH_GRANULARITY_SECS);
+    let target = mounts.find((point.owned_by.clone(), point.mount_name.clone()));
+    match is_data {
+        true => {
+            diesel::update(target)
+                .filter(data_accessed.lt(stale))
+                .set(data_accessed.eq(now))
+                .execute(conn)
+                .await?
+        }
+        false => {
+            diesel::update(target)
+                .filter(repo_accessed.lt(stale))
+                .set(repo_accessed.eq(now))
+                .execute(conn)
+                .await?
+        }
+    };
+    Ok(())
+}
+
+fn snapshot_mount(point: &Mount) -> String {
+    format!("{}/repo-mnt", point.repo_mnt_path.as_str())
+}
+
+fn is_mountpoint(path: &str) -> bool {
+    Cmd::new("mountpoint")
+        .arg("-q")
+        .arg(path)
+        .output()
+        .is_ok_and(|x| x.success())
+}
+
+// Only images (plain or LUKS) hold a device; the directory backends have nothing to
+// detach besides the snapshot mount.
+fn holds_device(point: &Mount, img_path: &str, mnt_path: &str) -> bool {
+    matches!(point.backend, StorageBackend::LoopExt4 | StorageBackend::LuksExt4)
+        && storage::for_point(point).is_mounted(img_path, mnt_path)
+}
+
+/// Which of the data and repository areas are still attached.
+fn attached_areas(point: &Mount) -> (bool, bool) {
+    (
+        holds_device(point, &point.data_img_path, &point.data_mnt_path),
+        is_mountpoint(snapshot_mount(point).as_str())
+            || holds_device(point, &point.repo_img_path, &point.repo_mnt_path),
+    )
+}
+
+/// Detaches the chosen areas. `ensure_point_mounted` brings them back on the next request.
+pub fn detach_areas(point: &Mount, data: bool, repo: bool) -> Result<(), NeptisError> {
+    let storage = storage::for_point(point);
+    if data {
+        storage.unmount(&point.data_img_path, &point.data_mnt_path)?;
+    }
+    if repo {
+        // Unmounting the snapshots also ends the `restic mount` process serving them.
+        let snapshots = snapshot_mount(point);
+        if is_mountpoint(snapshots.as_str()) {
+            Cmd::privileged("umount").arg(snapshots).run()?;
+        }
+        storage.unmount(&point.repo_img_path, &point.repo_mnt_path)?;
+    }
+    Ok(())
+}
+
+/// Unmounts every area which has not been used since `cutoff`. Points with a lock are
+/// left alone, and each point is locked while its areas are detached. Returns the number
+/// of points touched.
+pub async fn reap_idle(
+    conn: &mut AsyncPgConnection,
+    cutoff: NaiveDateTime,
+) -> Result<usize, NeptisError> {
+    use crate::schema::mounts::dsl::*;
+
+    let points: Vec<Mount> = mounts
+        .filter(
+            locked
+                .eq(false)
+                .and(data_accessed.lt(cutoff).or(repo_accessed.lt(cutoff))),
+        )
+        .get_results(conn)
+        .await?;
+
+    let mut reaped = 0;
+    for point in points {
+        let probe = point.clone();
+        let (d_attached, r_attached) =
+            rocket::tokio::task::spawn_blocking(move || attached_areas(&probe))
+                .await
+                .unwrap_or((false, false));
+        let d_idle = d_attached && point.data_accessed < cutoff;
+        let r_idle = r_attached && point.repo_accessed < cutoff;
+        if !d_idle && !r_idle {
+            continue;
+        }
+
+        // A busy point refuses the lock, which is fine: it is not idle.
+        let Ok(lock_id) = locks::acquire_lock(conn, &point, true, "idle unmount").await else {
+            continue;
+        };
+        // The point may have been used between the query and taking the lock.
+        let ret = match mounts
+            .find((point.owned_by.clone(), point.mount_name.clone()))
+            .get_result::<Mount>(conn)
+            .await
+        {
+            Ok(fresh) => {
+                let data = d_attached && fresh.data_accessed < cutoff;
+                let repo = r_attached && fresh.repo_accessed < cutoff;
+                rocket::tokio::task::spawn_blocking(move || detach_areas(&fresh, data, repo))
+                    .await
+                    .unwrap_or(Err(NeptisError::InternalError(
+                        "The unmount task panicked".into(),
+                    )))
+            }
+            Err(e) => Err(e.into()),
+        };
+        locks::release_lock(conn, lock_id).await?;
+        match ret {
+            Ok(_) => reaped += 1,
+            Err(e) => println!(
+                "Failed to unmount idle point {}/{}: {}",
+                point.owned_by, point.mount_name, e
+            ),
+        }
+    }
+    Ok(reaped)
+}
+
+/// Spawns the background task which unmounts areas idle for longer than
+/// `IDLE_UNMOUNT_SECS`. Zero keeps everything mounted.
+pub fn spawn_idle_reaper(pool: PgPool) {
+    let idle_secs: i64 = get_env_or!("IDLE_UNMOUNT_SECS", 1800);
+    let interval_secs: u64 = get_env_or!("IDLE_CHECK_INTERVAL", 60);
+    if idle_secs <= 0 || interval_secs == 0 {
+        return;
+    }
+    rocket::tokio::spawn(async move {
+        let mut timer =
+            rocket::tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+        loop {
+            timer.tick().await;
+            let Ok(mut conn) = pool.get().await else {
+                continue;
+            };
+            if let Err(e) = reap_idle(&mut conn, utc_now!() - Duration::seconds(idle_secs)).await {
+                println!("Failed to unmount idle points: {}", e);
+            }
+        }
+    });
+}
@@ -0,0 +1,158 @@
+use chrono::Duration;
+use diesel::OptionalExtension;
+use diesel_async::AsyncConnection;
+use diesel_async::scoped_futures::ScopedFutureExt;
+
+use super::models::*;
+use crate::prelude::action_prelude::*;
+
+/// Seconds before a lock is considered stale, such as when the server died mid-job.
+/// Locks held for a job are pushed back while the job keeps reporting progress.
+pub fn lock_ttl() -> Duration {
+    Duration::seconds(get_env_or!("MOUNT_LOCK_TTL", 21600))
+}
+
+/// Takes a lock on a point. Shared locks may be held together, while an exclusive lock
+/// requires the point to have no other live locks.
+pub async fn acquire_lock(
+    conn: &mut AsyncPgConnection,
+    point: &Mount,
+    p_exclusive: bool,
+    p_reason: &str,
+) -> Result<Uuid, NeptisError> {
+    let p_owner = point.owned_by.clone();
+    let p_name = point.mount_name.clone();
+    let p_reason = p_reason.to_string();
+
+    conn.transaction::<_, NeptisError, _>(|conn| {
+        async move {
+            use crate::schema::mount_locks::dsl::*;
+
+            // Hold the mount row for the transaction so lock changes are serialized.
+            {
+                use crate::schema::mounts::dsl as m;
+                m::mounts
+                    .find((p_owner.clone(), p_name.clone()))
+                    .select(m::locked)
+                    .for_update()
+                    .get_result::<bool>(conn)
+                    .await?;
+            }
+
+            let now = utc_now!();
+            diesel::delete(mount_locks)
+                .filter(
+                    owned_by
+                        .eq(p_owner.as_str())
+                        .and(mount_name.eq(p_name.as_str()))
+                        .and(expire_date.le(now)),
+                )
+                .execute(conn)
+                .await?;
+
+            let held: Vec<MountLock> = mount_locks
+                .filter(
+                    owned_by
+                        .eq(p_owner.as_str())
+                        .and(mount_name.eq(p_name.as_str())),
+                )
+                .get_results(conn)
+                .await?;
+            if let Some(c_lock) = held
+                .iter()
+                .find(|x| x.is_exclusive || p_exclusive)
+            {
+                return Err(NeptisError::Locked(format!(
+                    "The point is busy with a {} until {}",
+                    c_lock.reason, c_lock.expire_date
+                )));
+            }
+
+            let n_lock = MountLock {
+                id: Uuid::new_v4(),
+                owned_by: p_owner.clone(),
+                mount_name: p_name.clone(),
+                is_exclusive: p_exclusive,
+                reason: p_reason,
+                acquired_date: now,
+                expire_date: now + lock_ttl(),
+                job_id: None,
+            };
+            diesel::insert_into(mount_locks)
+                .values(&n_lock)
+                .execute(conn)
+                .await?;
+
+            if p_exclusive {
+                use crate::schema::mounts::dsl as m;
+                diesel::update(m::mounts.find((p_owner, p_name)))
+                    .set(m::locked.eq(true))
+                    .execute(conn)
+                    .await?;
+            }
+            Ok(n_lock.id)
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+pub async fn release_lock(conn: &mut AsyncPgConnection, lock_id: Uuid) -> Result<(), NeptisError> {
+    use crate::schema::mount_locks::dsl::*;
+
+    let Some(o_lock) = mount_locks
+        .find(lock_id)
+        .get_result::<MountLock>(conn)
+        .await
+        .optional()?
+    else {
+        return Ok(()); // already expired and swept
+    };
+    diesel::delete(mount_locks.find(lock_id))
+        .execute(conn)
+        .await?;
+    if o_lock.is_exclusive {
+        use crate::schema::mounts::dsl as m;
+        diesel::update(m::mounts.find((o_lock.owned_by, o_lock.mount_name)))
+            .set(m::locked.eq(false))
+            .execute(conn)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Fails with `423 Locked` while a live exclusive lock is held on the point.
+pub async fn ensure_unlocked(conn: &mut AsyncPgConnection, point: &Mount) -> Result<(), NeptisError> {
+    use crate::schema::mount_locks::dsl::*;
+
+    let c_lock: Option<MountLock> = mount_locks
+        .filter(
+            owned_by
+                .eq(point.owned_by.as_str())
+                .and(mount_name.eq(point.mount_name.as_str()))
+                .and(is_exclusive.eq(true))
+                .and(expire_date.gt(utc_now!())),
+        )
+        .first(conn)
+        .await
+        .optional()?;
+    match c_lock {
+        Some(x) => Err(NeptisError::Locked(format!(
+            "The point is busy with a {} until {}",
+            x.reason, x.expire_date
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Drops every lock. Only safe at startup, when no job threads can be holding one.
+pub async fn clear_all_locks(conn: &mut AsyncPgConnection) -> Result<usize, NeptisError> {
+    use crate::schema::mount_locks::dsl::*;
+    use crate::schema::mounts::dsl as m;
+
+    diesel::update(m::mounts)
+        .set(m::locked.eq(false))
+        .execute(conn)
+        .await?;
+    Ok(diesel::delete(mount_locks).execute(conn).await?)
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Mutex, OnceLock};
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use hmac::{Hmac, Mac};
+use rand::{RngCore, rng};
+use rocket::serde::json::serde_json;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::api::util;
+use crate::prelude::action_prelude::*;
+
+/// Type of the LUKS2 token which carries the wrapped key of a container, so the key
+/// travels with the image when it is moved.
+const TOKEN_TYPE: &str = "neptis-wrapped-key";
+
+#[derive(Serialize, Deserialize)]
+struct KeyToken {
+    #[serde(rename = "type")]
+    token_type: String,
+    keyslots: Vec<String>,
+    wrapped_key: String,
+    passphrase: bool,
+}
+
+static PASSPHRASES: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+
+fn passphrases() -> &'static Mutex<HashMap<String, String>> {
+    PASSPHRASES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Keeps the passphrase of the container at `img_path` in memory until the server
+/// stops, so it can be opened again after being unmounted.
+pub fn remember_passphrase(img_path: &str, passphrase: &str) {
+    if let Ok(mut map) = passphrases().lock() {
+        map.insert(img_path.to_string(), passphrase.to_string());
+    }
+}
+
+/// Keeps a remembered passphrase working once the container has moved to `n_img_path`.
+pub fn move_passphrase(img_path: &str, n_img_path: &str) {
+    if let Ok(mut map) = passphrases().lock()
+        && let Some(passphrase) = map.remove(img_path)
+    {
+        map.insert(n_img_path.to_string(), passphrase);
+    }
+}
+
+fn passphrase_for(img_path: &str) -> Option<String> {
+    passphrases().lock().ok()?.get(img_path).cloned()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// The server key which wraps the key of every container, read from
+/// `LUKS_MASTER_KEY_FILE` as 32 raw bytes or 64 hex digits.
+fn master_key() -> Result<Vec<u8>, NeptisError> {
+    let path: String = get_env_or!("LUKS_MASTER_KEY_FILE", String::from("/etc/neptis/master.key"));
+    let raw = fs::read(path.as_str())?;
+    let key = match raw.len() {
+        32 => Some(raw),
+        _ => std::str::from_utf8(&raw).ok().and_then(|x| from_hex(x.trim())),
+    };
+    key.filter(|x| x.len() == 32)
+        .ok_or(NeptisError::InternalError("The LUKS master key is not valid".into()))
+}
+
+/// Name of the device-mapper target the container at `img_path` is opened as.
+pub fn mapper_name(img_path: &str) -> String {
+    format!("neptis-{}", &to_hex(&Sha256::digest(img_path.as_bytes()))[..16])
+}
+
+// With a passphrase, the container only opens with both the unwrapped key and it.
+fn container_key(mount_key: &[u8], passphrase: Option<&str>) -> Vec<u8> {
+    match passphrase {
+        Some(p) => {
+            let mut mac =
+                Hmac::<Sha256>::new_from_slice(mount_key).expect("HMAC accepts any key length");
+            mac.update(p.as_bytes());
+            mac.finalize().into_bytes().to_vec()
+        }
+        None => mount_key.to_vec(),
+    }
+}
+
+/// Generates the key of a new container at `img_path`, along with the token which
+/// stores it wrapped. A passphrase remembered for the path is required from then on.
+pub fn new_key(img_path: &str) -> Result<(Vec<u8>, String), NeptisError> {
+    let mut mount_key = [0u8; 32];
+    rng().fill_bytes(&mut mount_key);
+    let wrapped = util::encrypt(&master_key()?, &mount_key)
+        .ok_or(NeptisError::InternalError("Failed to wrap the key".into()))?;
+    let passphrase = passphrase_for(img_path);
+    let token = serde_json::to_string(&KeyToken {
+        token_type: TOKEN_TYPE.into(),
+        keyslots: vec![],
+        wrapped_key: STANDARD.encode(wrapped),
+        passphrase: passphrase.is_some(),
+    })
+    .map_err(|e| NeptisError::InternalError(e.to_string()))?;
+    Ok((container_key(&mount_key, passphrase.as_deref()), token))
+}
+
+fn key_with(img_path: &str, passphrase: Option<String>) -> Result<Vec<u8>, NeptisError> {
+    let json = Cmd::privileged("cryptsetup")
+        .args(["token", "export", "--token-id", "0"])
+        .arg(img_path)
+        .run()?;
+    let token: KeyToken = serde_json::from_str(json.as_str())
+        .ok()
+        .filter(|x: &KeyToken| x.token_type == TOKEN_TYPE)
+        .ok_or(NeptisError::InternalError("The container has no key token".into()))?;
+    let mount_key = STANDARD
+        .decode(token.wrapped_key.as_str())
+        .ok()
+        .and_then(|x| util::decrypt(&master_key().ok()?, &x))
+        .ok_or(NeptisError::InternalError("Failed to unwrap the key".into()))?;
+    let passphrase = match token.passphrase {
+        true => Some(passphrase.ok_or(NeptisError::Locked(
+            "The point is sealed. Unlock it with its passphrase first".into(),
+        ))?),
+        false => None,
+    };
+    Ok(container_key(&mount_key, passphrase.as_deref()))
+}
+
+/// The key which opens the container at `img_path`.
+pub fn open_key(img_path: &str) -> Result<Vec<u8>, NeptisError> {
+    key_with(img_path, passphrase_for(img_path))
+}
+
+/// Checks `passphrase` against the container at `img_path` and remembers it when it fits.
+pub fn unlock(img_path: &str, passphrase: &str) -> Result<(), NeptisError> {
+    let key = key_with(img_path, Some(passphrase.to_string()))?;
+    let ret = Cmd::privileged("cryptsetup")
+        .args(["open", "--test-passphrase", "--key-file", "-"])
+        .arg(img_path)
+        .stdin(key)
+        .output()?;
+    if !ret.success() {
+        return Err(NeptisError::Unauthorized("The passphrase is wrong!".into()));
+    }
+    remember_passphrase(img_path, passphrase);
+    Ok(())
+}
@@ -2,4 +2,22 @@ pub mod actions;
 pub mod handlers;
 pub mod models;
 pub mod dtos;
-pub mod rustic_async;
\ No newline at end of file
+pub mod rustic_async;
+pub mod retention;
+pub mod hooks;
+pub mod locks;
+pub mod repo_stats;
+pub mod archive;
+pub mod extract;
+pub mod freshness;
+pub mod storage;
+pub mod provisioning;
+pub mod idle;
+pub mod reconcile;
+pub mod relocate;
+pub mod luks;
+pub mod fsck;
+pub mod capacity;
+pub mod usage;
+pub mod pools;
+pub mod files;
\ No newline at end of file
@@ -1,5 +1,6 @@
 use diesel::sql_types::SmallInt;
 use diesel_enum::DbEnum;
+use rocket::FromFormField;
 
 use crate::prelude::model_prelude::*;
 
@@ -18,6 +19,20 @@ pub struct Mount {
     pub data_accessed: NaiveDateTime,
     pub repo_accessed: NaiveDateTime,
     pub locked: bool,
+    /// Hours allowed between successful backups; no objective when unset.
+    pub rpo_hours: Option<i32>,
+    pub is_stale: bool,
+    /// How both areas are stored. Kept per point, so changing `STORAGE_BACKEND` only
+    /// affects points created afterwards.
+    pub backend: StorageBackend,
+    /// Hours between scheduled filesystem checks; none are scheduled when unset.
+    pub fsck_hours: Option<i32>,
+    /// Outcome of the last filesystem check, if one ever ran.
+    pub health: Option<FsHealth>,
+    pub health_checked: Option<NaiveDateTime>,
+    /// Storage pool both areas live in. Only unset for points created before pools
+    /// existed, until the next start assigns them to the default pool.
+    pub pool_name: Option<String>,
 }
 
 #[derive(Insertable, Queryable, Clone, AsChangeset)]
@@ -32,20 +47,78 @@ pub struct RepoJob {
     pub total_bytes: Option<i64>,
     pub errors: Vec<String>,
     pub create_date: NaiveDateTime,
-    pub end_date: Option<NaiveDateTime>
+    pub end_date: Option<NaiveDateTime>,
+    pub acted_by: Option<String>,
+    pub target_path: Option<String>,
+    /// Launch options, kept so the job can be retried as it was started.
+    pub launch_dry_run: bool,
+    pub launch_read_data: bool,
+    pub launch_tags: Vec<String>,
+    pub logs: Vec<String>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, DbEnum, Serialize, Deserialize)]
+#[derive(Insertable, Queryable, Clone)]
+pub struct MountLock {
+    pub id: Uuid,
+    pub owned_by: String,
+    pub mount_name: String,
+    pub is_exclusive: bool,
+    pub reason: String,
+    pub acquired_date: NaiveDateTime,
+    pub expire_date: NaiveDateTime,
+    /// Job the lock is held for, which keeps it alive while the job reports progress.
+    pub job_id: Option<Uuid>,
+}
+
+/// Host space used under the paths of a storage pool at one point in time, kept to
+/// forecast when they fill up.
+#[derive(Insertable, Queryable, Clone)]
+pub struct CapacitySample {
+    pub id: Uuid,
+    pub sample_date: NaiveDateTime,
+    pub data_used_bytes: i64,
+    pub repo_used_bytes: i64,
+    pub pool_name: Option<String>,
+}
+
+/// A place new points may be created in, with a directory for each area. The limits of
+/// the points in a pool may not add up to more than `capacity_bytes`, unless it is zero.
+#[derive(Insertable, Queryable, Clone, AsChangeset)]
+pub struct StoragePool {
+    pub pool_name: String,
+    pub data_path: String,
+    pub repo_path: String,
+    pub capacity_bytes: i64,
+    /// Free-form labels, such as `ssd` or `hdd`, which placement can be restricted to.
+    pub tags: Vec<String>,
+    pub create_date: NaiveDateTime,
+}
+
+#[derive(Insertable, Queryable, Clone, AsChangeset)]
+pub struct MountHook {
+    pub id: Uuid,
+    pub owned_by: String,
+    pub mount_name: String,
+    pub hook_type: HookType,
+    pub command: Vec<String>,
+    pub timeout_secs: i32,
+    pub enabled: bool,
+    pub create_date: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, DbEnum, Serialize, Deserialize, FromFormField)]
 #[diesel(sql_type = SmallInt)]
 #[diesel_enum(error_fn = NeptisError::enum_not_found)]
 #[diesel_enum(error_type = NeptisError)]
 pub enum JobType {
     Backup,
-    Restore
+    Restore,
+    Check,
+    Fsck
 }
 
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, DbEnum, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, DbEnum, Serialize, Deserialize, FromFormField)]
 #[diesel(sql_type = SmallInt)]
 #[diesel_enum(error_fn = NeptisError::enum_not_found)]
 #[diesel_enum(error_type = NeptisError)]
@@ -53,7 +126,42 @@ pub enum JobStatus {
     NotStarted,
     Running,
     Successful,
-    Failed
+    Failed,
+    /// Marked cancelled by an admin. The work itself is not interrupted, and carries on
+    /// until the job's `end_date` is set.
+    Cancelled
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, DbEnum, Serialize, Deserialize)]
+#[diesel(sql_type = SmallInt)]
+#[diesel_enum(error_fn = NeptisError::enum_not_found)]
+#[diesel_enum(error_type = NeptisError)]
+pub enum HookType {
+    PreBackup,
+    PostSuccess,
+    PostFailure
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AsExpression, FromSqlRow, DbEnum, Serialize, Deserialize)]
+#[diesel(sql_type = SmallInt)]
+#[diesel_enum(error_fn = NeptisError::enum_not_found)]
+#[diesel_enum(error_type = NeptisError)]
+pub enum StorageBackend {
+    LoopExt4,
+    Directory,
+    ProjectQuota,
+    Btrfs,
+    LuksExt4
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, AsExpression, FromSqlRow, DbEnum, Serialize, Deserialize)]
+#[diesel(sql_type = SmallInt)]
+#[diesel_enum(error_fn = NeptisError::enum_not_found)]
+#[diesel_enum(error_type = NeptisError)]
+pub enum FsHealth {
+    Clean,
+    Repaired,
+    Corrupt
 }
 
 impl CleanValidate for Mount {
@@ -79,4 +187,42 @@ impl CleanValidate for Mount {
         vmin!(self.repo_max_bytes, 0, "Max repo bytes must be greater than zero!");
         Ok(self)
     }
-}
\ No newline at end of file
+}
+
+impl CleanValidate for StoragePool {
+    fn validate(mut self) -> Result<Self, ValidateError>
+    where
+        Self: Sized,
+    {
+        trim!(self.pool_name, self.data_path, self.repo_path);
+        self.tags = self
+            .tags
+            .into_iter()
+            .map(|x| x.trim().to_lowercase())
+            .filter(|x| !x.is_empty())
+            .collect();
+        vreq!(self.pool_name, "You must enter a name!");
+        vreq!(self.data_path, "You must enter a data path!");
+        vreq!(self.repo_path, "You must enter a repo path!");
+        vmin!(self.capacity_bytes, 0, "The capacity cannot be negative!");
+        Ok(self)
+    }
+}
+
+impl CleanValidate for MountHook {
+    fn validate(mut self) -> Result<Self, ValidateError>
+    where
+        Self: Sized,
+    {
+        self.command = self
+            .command
+            .into_iter()
+            .map(|x| x.trim().to_string())
+            .collect();
+        vreq!(self.command, "You must enter a command!");
+        vreq!(self.command[0], "The command program is blank!");
+        vmin!(self.timeout_secs, 1, "The timeout must be at least one second!");
+        vmax!(self.timeout_secs, 3600, "The timeout must be at most one hour!");
+        Ok(self)
+    }
+}
@@ -0,0 +1,474 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use super::locks;
+use super::luks;
+use super::models::*;
+use super::provisioning;
+use super::relocate;
+use super::storage::{self, MountStats};
+use crate::api::traits::CleanValidate;
+use crate::prelude::action_prelude::*;
+
+/// Name of the pool built from `DATA_PATH` and `REPO_PATH` (`DEFAULT_POOL`).
+pub fn default_pool_name() -> String {
+    get_env_or!("DEFAULT_POOL", String::from("default"))
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StoragePoolDto {
+    pub pool_name: String,
+    pub data_path: String,
+    pub repo_path: String,
+    /// Zero when only the host filesystems bound the pool.
+    pub capacity_bytes: i64,
+    pub tags: Vec<String>,
+    pub is_default: bool,
+    pub mounts: usize,
+    /// Sum of the limits of both areas of every point in the pool.
+    pub allocated_bytes: i64,
+    /// Space left for new points, which placement ranks the pools by. Unset when the
+    /// host filesystems could not be read.
+    pub free_bytes: Option<i64>,
+    pub create_date: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PutForPoolApi {
+    pub data_path: String,
+    pub repo_path: String,
+    pub capacity_bytes: Option<i64>,
+    pub tags: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PostForMigrateApi {
+    pub pool: String,
+}
+
+/// Points stored in `pool`.
+pub fn points_in(pool: &StoragePool, points: &[Mount]) -> Vec<Mount> {
+    points
+        .iter()
+        .filter(|x| x.pool_name.as_deref() == Some(pool.pool_name.as_str()))
+        .cloned()
+        .collect()
+}
+
+fn allocated(points: &[Mount]) -> i64 {
+    (provisioning::allocated_bytes(points, true) + provisioning::allocated_bytes(points, false))
+        as i64
+}
+
+fn has_tags(pool: &StoragePool, tags: &[String]) -> bool {
+    tags.iter()
+        .all(|x| pool.tags.contains(&x.trim().to_lowercase()))
+}
+
+// What may still be handed out under `capacity_bytes`; unbounded when it is zero.
+fn remaining(pool: &StoragePool, points: &[Mount]) -> Option<i64> {
+    (pool.capacity_bytes > 0).then(|| (pool.capacity_bytes - allocated(points)).max(0))
+}
+
+fn host_usage(pool: &StoragePool) -> Result<(MountStats, MountStats), NeptisError> {
+    Ok((
+        storage::disk_usage(pool.data_path.as_str())?,
+        storage::disk_usage(pool.repo_path.as_str())?,
+    ))
+}
+
+// Both areas may live on one filesystem, which must not be counted twice.
+fn free_bytes(pool: &StoragePool, points: &[Mount]) -> Result<i64, NeptisError> {
+    let (d_host, r_host) = host_usage(pool)?;
+    let same_fs = match (fs::metadata(&pool.data_path), fs::metadata(&pool.repo_path)) {
+        (Ok(d), Ok(r)) => d.dev() == r.dev(),
+        _ => false,
+    };
+    let host_free = match same_fs {
+        true => d_host.b_avail as i64,
+        false => (d_host.b_avail + r_host.b_avail) as i64,
+    };
+    Ok(remaining(pool, points).map_or(host_free, |x| x.min(host_free)))
+}
+
+fn to_dto(pool: StoragePool, points: &[Mount]) -> StoragePoolDto {
+    let points = points_in(&pool, points);
+    StoragePoolDto {
+        free_bytes: free_bytes(&pool, &points).ok(),
+        is_default: pool.pool_name == default_pool_name(),
+        mounts: points.len(),
+        allocated_bytes: allocated(&points),
+        pool_name: pool.pool_name,
+        data_path: pool.data_path,
+        repo_path: pool.repo_path,
+        capacity_bytes: pool.capacity_bytes,
+        tags: pool.tags,
+        create_date: pool.create_date,
+    }
+}
+
+/// Refuses to add `bytes` more to `pool` past its capacity. The host filesystems are
+/// checked separately, through `provisioning::ensure_can_allocate`.
+pub fn ensure_room(pool: &StoragePool, points: &[Mount], bytes: i64) -> Result<(), NeptisError> {
+    match remaining(pool, &points_in(pool, points)) {
+        Some(x) if x < bytes => Err(NeptisError::BadRequest(format!(
+            "Not enough free space in the {} pool",
+            pool.pool_name
+        ))),
+        _ => Ok(()),
+    }
+}
+
+pub async fn get_pool(conn: &mut AsyncPgConnection, p_name: &str) -> Result<StoragePool, NeptisError> {
+    use crate::schema::storage_pools::dsl::*;
+    storage_pools
+        .find(p_name)
+        .get_result(conn)
+        .await
+        .ok()
+        .ok_or(NeptisError::BadRequest("The storage pool does not exist!".into()))
+}
+
+/// The pool `point` lives in.
+pub async fn pool_of(conn: &mut AsyncPgConnection, point: &Mount) -> Result<StoragePool, NeptisError> {
+    let p_name = point.pool_name.clone().unwrap_or_else(default_pool_name);
+    get_pool(conn, p_name.as_str()).await
+}
+
+/// Picks the pool a new point of `owner` is created in: the one asked for, else the
+/// owner's default, else whichever has the most space left. With `tags`, only pools
+/// carrying every one of them are considered. The pool must have room for `bytes`.
+pub async fn choose_pool(
+    conn: &mut AsyncPgConnection,
+    owner: &User,
+    requested: Option<&str>,
+    tags: &[String],
+    bytes: i64,
+) -> Result<StoragePool, NeptisError> {
+    let pools: Vec<StoragePool> = {
+        use crate::schema::storage_pools::dsl::*;
+        storage_pools.order(pool_name.asc()).get_results(conn).await?
+    };
+    let points: Vec<Mount> = {
+        use crate::schema::mounts::dsl::*;
+        mounts.get_results(conn).await?
+    };
+
+    if let Some(requested) = requested {
+        let pool = pools
+            .into_iter()
+            .find(|x| x.pool_name == requested)
+            .ok_or(NeptisError::BadRequest("The storage pool does not exist!".into()))?;
+        if !has_tags(&pool, tags) {
+            return Err(NeptisError::BadRequest(
+                "The storage pool does not carry the requested tags!".into(),
+            ));
+        }
+        ensure_room(&pool, &points, bytes)?;
+        return Ok(pool);
+    }
+
+    // A default which is full or lacks the tags is passed over rather than failing.
+    if let Some(pool) = owner
+        .default_pool
+        .as_ref()
+        .and_then(|n| pools.iter().find(|x| x.pool_name == *n))
+        .filter(|x| has_tags(x, tags) && ensure_room(x, &points, bytes).is_ok())
+    {
+        return Ok(pool.clone());
+    }
+
+    pools
+        .into_iter()
+        .filter(|x| has_tags(x, tags) && ensure_room(x, &points, bytes).is_ok())
+        .filter_map(|x| Some((free_bytes(&x, &points_in(&x, &points)).ok()?, x)))
+        .max_by_key(|x| x.0)
+        .map(|x| x.1)
+        .ok_or(NeptisError::BadRequest(
+            "No storage pool has room for the point!".into(),
+        ))
+}
+
+/// Creates the default pool from `DATA_PATH` and `REPO_PATH` if it does not exist yet,
+/// and places every point and capacity sample which has no pool in it. The paths are
+/// only read from the environment the first time.
+pub async fn ensure_default_pool(conn: &mut AsyncPgConnection) -> Result<(), NeptisError> {
+    let d_name = default_pool_name();
+    {
+        use crate::schema::storage_pools::dsl::*;
+        diesel::insert_into(storage_pools)
+            .values(&StoragePool {
+                pool_name: d_name.clone(),
+                data_path: get_env!("DATA_PATH"),
+                repo_path: get_env!("REPO_PATH"),
+                capacity_bytes: 0,
+                tags: vec![],
+                create_date: utc_now!(),
+            })
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await?;
+    }
+    {
+        use crate::schema::capacity_samples::dsl::*;
+        diesel::update(capacity_samples.filter(pool_name.is_null()))
+            .set(pool_name.eq(Some(d_name.clone())))
+            .execute(conn)
+            .await?;
+    }
+    use crate::schema::mounts::dsl::*;
+    diesel::update(mounts.filter(pool_name.is_null()))
+        .set(pool_name.eq(Some(d_name)))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Every pool along with how much of it is in use.
+pub async fn list_pools(conn: &mut AsyncPgConnection) -> Result<Vec<StoragePoolDto>, NeptisError> {
+    let pools: Vec<StoragePool> = {
+        use crate::schema::storage_pools::dsl::*;
+        storage_pools.order(pool_name.asc()).get_results(conn).await?
+    };
+    let points: Vec<Mount> = {
+        use crate::schema::mounts::dsl::*;
+        mounts.get_results(conn).await?
+    };
+    rocket::tokio::task::spawn_blocking(move || {
+        pools.into_iter().map(|x| to_dto(x, &points)).collect()
+    })
+    .await
+    .map_err(|e| NeptisError::InternalError(e.to_string()))
+}
+
+/// Creates the pool `p_name`, or updates it. The paths of a pool cannot change while
+/// points live in it, since each point records its own.
+pub async fn put_pool(
+    conn: &mut AsyncPgConnection,
+    p_name: &str,
+    dto: PutForPoolApi,
+) -> Result<StoragePoolDto, NeptisError> {
+    use crate::schema::storage_pools::dsl::*;
+
+    let points: Vec<Mount> = {
+        use crate::schema::mounts::dsl as m;
+        m::mounts.filter(m::pool_name.eq(p_name)).get_results(conn).await?
+    };
+    let existing: Option<StoragePool> = storage_pools.find(p_name).get_result(conn).await.ok();
+    let n_pool = StoragePool {
+        pool_name: p_name.to_string(),
+        data_path: dto.data_path.trim_end_matches('/').to_string(),
+        repo_path: dto.repo_path.trim_end_matches('/').to_string(),
+        capacity_bytes: dto.capacity_bytes.unwrap_or(0),
+        tags: dto.tags.unwrap_or_default(),
+        create_date: existing.as_ref().map_or(utc_now!(), |x| x.create_date),
+    }
+    .validate()?;
+    for path in [n_pool.data_path.as_str(), n_pool.repo_path.as_str()] {
+        if !Path::new(path).is_dir() {
+            return Err(NeptisError::BadRequest(format!("{} is not a directory!", path)));
+        }
+    }
+
+    let n_pool: StoragePool = match existing {
+        Some(f_pool) => {
+            if !points.is_empty()
+                && (f_pool.data_path != n_pool.data_path || f_pool.repo_path != n_pool.repo_path)
+            {
+                return Err(NeptisError::BadRequest(
+                    "The paths of a pool cannot change while points live in it!".into(),
+                ));
+            }
+            diesel::update(storage_pools.find(p_name))
+                .set(&n_pool)
+                .get_result(conn)
+                .await?
+        }
+        None => {
+            diesel::insert_into(storage_pools)
+                .values(&n_pool)
+                .get_result(conn)
+                .await?
+        }
+    };
+    rocket::tokio::task::spawn_blocking(move || to_dto(n_pool, &points))
+        .await
+        .map_err(|e| NeptisError::InternalError(e.to_string()))
+}
+
+/// Removes an empty pool. The default pool always stays.
+pub async fn delete_pool(conn: &mut AsyncPgConnection, p_name: &str) -> Result<usize, NeptisError> {
+    use crate::schema::storage_pools::dsl::*;
+
+    if p_name == default_pool_name() {
+        return Err(NeptisError::BadRequest("The default pool cannot be removed!".into()));
+    }
+    let points: i64 = {
+        use crate::schema::mounts::dsl as m;
+        m::mounts
+            .filter(m::pool_name.eq(p_name))
+            .count()
+            .get_result(conn)
+            .await?
+    };
+    if points > 0 {
+        return Err(NeptisError::BadRequest(
+            "Points still live in the pool. Migrate them first".into(),
+        ));
+    }
+    match diesel::delete(storage_pools.find(p_name)).execute(conn).await? {
+        0 => Err(NeptisError::BadRequest("The storage pool does not exist!".into())),
+        n => Ok(n),
+    }
+}
+
+// Directory quotas are tied to the filesystem they were set on, so only points whose
+// areas are self-contained can be copied elsewhere.
+fn ensure_movable(point: &Mount) -> Result<(), NeptisError> {
+    match point.backend {
+        StorageBackend::LoopExt4 | StorageBackend::LuksExt4 | StorageBackend::Directory => Ok(()),
+        _ => Err(NeptisError::BadRequest(format!(
+            "Points stored as {:?} cannot be moved between pools!",
+            point.backend
+        ))),
+    }
+}
+
+/// `point` with every path moved into the directories of `pool`.
+pub fn placed(point: &Mount, pool: &StoragePool) -> Mount {
+    let into = |dir: &str, path: &str| {
+        let name = Path::new(path)
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default();
+        format!("{}/{}", dir, name)
+    };
+    Mount {
+        data_img_path: into(&pool.data_path, &point.data_img_path),
+        data_mnt_path: into(&pool.data_path, &point.data_mnt_path),
+        repo_img_path: into(&pool.repo_path, &point.repo_img_path),
+        repo_mnt_path: into(&pool.repo_path, &point.repo_mnt_path),
+        pool_name: Some(pool.pool_name.clone()),
+        ..point.clone()
+    }
+}
+
+fn paths_of(point: &Mount) -> [&str; 4] {
+    [
+        point.data_img_path.as_str(),
+        point.data_mnt_path.as_str(),
+        point.repo_img_path.as_str(),
+        point.repo_mnt_path.as_str(),
+    ]
+}
+
+fn remove_all(paths: &[&str]) {
+    for path in paths {
+        if let Err(e) = Cmd::privileged("rm").args(["-rf", "--"]).arg(path).run() {
+            println!("Failed to remove {}: {}", path, e);
+        }
+    }
+}
+
+// Copies every path of `from` to where it belongs in `to`, which may be on another
+// filesystem. Sparse images stay sparse. Whatever was copied is removed again if one
+// of them fails; `from` is left alone either way.
+fn copy_point(from: &Mount, to: &Mount) -> Result<(), NeptisError> {
+    let (sources, dests) = (paths_of(from), paths_of(to));
+    if let Some(dest) = dests.iter().find(|x| Path::new(x).exists()) {
+        return Err(NeptisError::BadRequest(format!("{} already exists!", dest)));
+    }
+    for (done, (src, dest)) in sources.iter().zip(dests.iter()).enumerate() {
+        let ret = Cmd::privileged("cp")
+            .args(["-a", "--sparse=always", "--"])
+            .arg(src)
+            .arg(dest)
+            .run();
+        if let Err(e) = ret {
+            remove_all(&dests[..=done]);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Moves `point` into `pool`. Both areas are copied while the point is locked, and the
+/// originals are only removed once the database refers to the copies.
+pub async fn migrate_point(
+    conn: &mut AsyncPgConnection,
+    point: &Mount,
+    dto: PostForMigrateApi,
+) -> Result<Mount, NeptisError> {
+    if point.pool_name.as_deref() == Some(dto.pool.as_str()) {
+        return Err(NeptisError::BadRequest("No modification is necessary".into()));
+    }
+    ensure_movable(point)?;
+    let pool = get_pool(conn, dto.pool.as_str()).await?;
+    let points: Vec<Mount> = {
+        use crate::schema::mounts::dsl::*;
+        mounts.get_results(conn).await?
+    };
+    ensure_room(&pool, &points, point.data_max_bytes + point.repo_max_bytes)?;
+    let (d_host, r_host) = host_usage(&pool)?;
+    let in_pool = points_in(&pool, &points);
+    provisioning::ensure_can_allocate(
+        &d_host,
+        provisioning::allocated_bytes(&in_pool, true),
+        point.data_max_bytes.max(0) as u64,
+        "data",
+    )?;
+    provisioning::ensure_can_allocate(
+        &r_host,
+        provisioning::allocated_bytes(&in_pool, false),
+        point.repo_max_bytes.max(0) as u64,
+        "repo",
+    )?;
+
+    let lock_id = locks::acquire_lock(conn, point, true, "migrate").await?;
+    let from = point.clone();
+    let to = placed(point, &pool);
+
+    let ret = async {
+        let (f, t) = (from.clone(), to.clone());
+        rocket::tokio::task::spawn_blocking(move || {
+            relocate::detach(&f).and_then(|_| copy_point(&f, &t))
+        })
+        .await
+        .map_err(|_| NeptisError::InternalError("The migration panicked".into()))??;
+
+        use crate::schema::mounts::dsl::*;
+        let ret: Result<Mount, NeptisError> =
+            diesel::update(mounts.find((from.owned_by.clone(), from.mount_name.clone())))
+                .set((
+                    data_img_path.eq(to.data_img_path.as_str()),
+                    data_mnt_path.eq(to.data_mnt_path.as_str()),
+                    repo_img_path.eq(to.repo_img_path.as_str()),
+                    repo_mnt_path.eq(to.repo_mnt_path.as_str()),
+                    pool_name.eq(to.pool_name.clone()),
+                ))
+                .get_result(conn)
+                .await
+                .map_err(Into::into);
+
+        // Whichever copy the database does not refer to goes.
+        let (f, t) = (from.clone(), to.clone());
+        let done = ret.is_ok();
+        let _ = rocket::tokio::task::spawn_blocking(move || {
+            remove_all(&paths_of(if done { &f } else { &t }));
+        })
+        .await;
+        if done && from.backend == StorageBackend::LuksExt4 {
+            luks::move_passphrase(from.data_img_path.as_str(), to.data_img_path.as_str());
+            luks::move_passphrase(from.repo_img_path.as_str(), to.repo_img_path.as_str());
+        }
+        ret
+    }
+    .await;
+
+    locks::release_lock(conn, lock_id).await?;
+    ret
+}
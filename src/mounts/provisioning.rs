@@ -0,0 +1,176 @@
+use serde::{Deserialize, Serialize};
+
+use super::models::{Mount, StoragePool};
+use super::pools;
+use super::storage::{self, MountStats};
+use crate::prelude::action_prelude::*;
+
+/// Whether new loop images are created sparse (`THIN_PROVISIONING`), so they only take
+/// host space as they fill up.
+pub fn thin_provisioning() -> bool {
+    get_env_or!("THIN_PROVISIONING", false)
+}
+
+/// How far the limits handed out may exceed the size of the host filesystem while thin
+/// provisioning, as a multiple of it (`OVERCOMMIT_RATIO`).
+pub fn overcommit_ratio() -> f64 {
+    get_env_or!("OVERCOMMIT_RATIO", 1.0)
+}
+
+/// Share of the host filesystem, in percent, which must stay free. Once it is reached,
+/// writes to sparse areas are refused (`HOST_RESERVE_PERCENT`).
+pub fn host_reserve_percent() -> f64 {
+    get_env_or!("HOST_RESERVE_PERCENT", 5.0)
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AllocationDto {
+    pub path: String,
+    pub capacity_bytes: i64,
+    pub free_bytes: i64,
+    /// Sum of the limits of every area stored here.
+    pub allocated_bytes: i64,
+    /// Host space the areas actually take up.
+    pub used_bytes: i64,
+    /// Most that may be allocated here under the overcommit ratio.
+    pub allocatable_bytes: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PoolAllocationDto {
+    pub pool_name: String,
+    pub data: AllocationDto,
+    pub repo: AllocationDto,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AllocationReportDto {
+    pub thin_provisioning: bool,
+    pub overcommit_ratio: f64,
+    pub reserve_percent: f64,
+    pub pools: Vec<PoolAllocationDto>,
+}
+
+fn allocatable(host: &MountStats) -> u64 {
+    match thin_provisioning() {
+        true => (host.b_total as f64 * overcommit_ratio()) as u64,
+        false => host.b_total as u64,
+    }
+}
+
+fn ensure_reserve(host: &MountStats) -> Result<(), NeptisError> {
+    let reserve = (host.b_total as f64 * host_reserve_percent() / 100.0) as usize;
+    match host.b_avail > reserve {
+        true => Ok(()),
+        false => Err(NeptisError::InsufficientStorage(
+            "The server is almost out of space. Writes are paused until space is freed".into(),
+        )),
+    }
+}
+
+/// Sum of the limits of one area over `points`.
+pub fn allocated_bytes(points: &[Mount], is_data: bool) -> u64 {
+    points
+        .iter()
+        .map(|x| match is_data {
+            true => x.data_max_bytes.max(0) as u64,
+            false => x.repo_max_bytes.max(0) as u64,
+        })
+        .sum()
+}
+
+/// Checks that `extra` more bytes may be handed out on the filesystem `host`, which
+/// already has `allocated` bytes handed out. Fully allocated images need the space free
+/// right away; thin ones are held to the overcommit ratio and the reserve instead.
+pub fn ensure_can_allocate(
+    host: &MountStats,
+    allocated: u64,
+    extra: u64,
+    area: &str,
+) -> Result<(), NeptisError> {
+    if extra == 0 {
+        return Ok(());
+    }
+    if !thin_provisioning() {
+        return match host.b_avail as u64 >= extra {
+            true => Ok(()),
+            false => Err(NeptisError::BadRequest(format!(
+                "Not enough {} free space on server",
+                area
+            ))),
+        };
+    }
+    if allocated + extra > allocatable(host) {
+        return Err(NeptisError::BadRequest(format!(
+            "The {} storage on the server is fully committed",
+            area
+        )));
+    }
+    ensure_reserve(host)
+}
+
+/// Refuses writes to an area of `point` while its host filesystem is down to the
+/// reserve. Only sparse areas take host space as they are written to.
+pub fn ensure_host_headroom(point: &Mount, is_data: bool) -> Result<(), NeptisError> {
+    let img_path = match is_data {
+        true => point.data_img_path.as_str(),
+        false => point.repo_img_path.as_str(),
+    };
+    if !storage::for_point(point).is_sparse(img_path) {
+        return Ok(());
+    }
+    ensure_reserve(&storage::disk_usage(img_path)?)
+}
+
+pub fn allocation_for(path: &str, points: &[Mount], is_data: bool) -> Result<AllocationDto, NeptisError> {
+    let host = storage::disk_usage(path)?;
+    let used_bytes = points
+        .iter()
+        .filter_map(|x| {
+            let (img_path, mnt_path) = match is_data {
+                true => (x.data_img_path.as_str(), x.data_mnt_path.as_str()),
+                false => (x.repo_img_path.as_str(), x.repo_mnt_path.as_str()),
+            };
+            storage::for_point(x).host_usage(img_path, mnt_path).ok()
+        })
+        .sum::<usize>();
+    Ok(AllocationDto {
+        path: path.to_string(),
+        capacity_bytes: host.b_total as i64,
+        free_bytes: host.b_avail as i64,
+        allocated_bytes: allocated_bytes(points, is_data) as i64,
+        used_bytes: used_bytes as i64,
+        allocatable_bytes: allocatable(&host) as i64,
+    })
+}
+
+/// Compares what has been handed out in each storage pool with what the points
+/// actually use.
+pub async fn allocation_report(
+    conn: &mut AsyncPgConnection,
+) -> Result<AllocationReportDto, NeptisError> {
+    let points: Vec<Mount> = {
+        use crate::schema::mounts::dsl::*;
+        mounts.get_results(conn).await?
+    };
+    let all_pools: Vec<StoragePool> = {
+        use crate::schema::storage_pools::dsl::*;
+        storage_pools.order(pool_name.asc()).get_results(conn).await?
+    };
+    Ok(AllocationReportDto {
+        thin_provisioning: thin_provisioning(),
+        overcommit_ratio: overcommit_ratio(),
+        reserve_percent: host_reserve_percent(),
+        pools: all_pools
+            .iter()
+            .map(|x| {
+                let in_pool = pools::points_in(x, &points);
+                Ok(PoolAllocationDto {
+                    pool_name: x.pool_name.clone(),
+                    data: allocation_for(x.data_path.as_str(), &in_pool, true)?,
+                    repo: allocation_for(x.repo_path.as_str(), &in_pool, false)?,
+                })
+            })
+            .collect::<Result<Vec<_>, NeptisError>>()?,
+    })
+}
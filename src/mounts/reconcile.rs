@@ -0,0 +1,642 @@
+use std::collections::HashSet;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocket::serde::json::serde_json::json;
+use serde::{Deserialize, Serialize};
+
+use super::actions::ensure_point_mounted;
+use super::locks;
+use super::models::*;
+use super::storage;
+use crate::api::traits::CleanValidate;
+use crate::prelude::action_prelude::*;
+use crate::webhooks::dispatch;
+use crate::webhooks::models::WebhookEvent;
+
+/// `ENOTCONN`, which FUSE returns once the process serving a mount has gone.
+const ENOTCONN: i32 = 107;
+
+/// Seconds an image no point refers to has to go unchanged before it is reported. A copy
+/// keeps the modification time of its source, so the change time is what counts.
+fn orphan_min_age() -> i64 {
+    get_env_or!("RECONCILE_ORPHAN_MIN_AGE", 3600)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReconcileIssue {
+    /// A point whose backing image is gone.
+    MissingImage,
+    /// A backing image which no point refers to.
+    OrphanImage,
+    /// An area which is not mounted. Areas are mounted again on their next use, so this
+    /// is only informational.
+    Unmounted,
+    /// A mount left behind by a point which no longer exists, or a snapshot mount whose
+    /// `restic` process died.
+    StaleMount,
+    /// The limit on disk differs from the one recorded for the point.
+    SizeMismatch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RepairAction {
+    /// Records what is on disk: a row for an orphan image, or its size for a mismatch.
+    Adopt,
+    /// Moves an orphan image aside into a `.quarantine` directory next to it.
+    Quarantine,
+    Remount,
+    /// Removes the row, the image or the mount the finding is about.
+    Delete,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReconcileFindingDto {
+    pub id: String,
+    pub issue: ReconcileIssue,
+    pub owned_by: Option<String>,
+    pub mount_name: Option<String>,
+    pub area: Option<String>,
+    pub path: String,
+    pub detail: String,
+    pub actions: Vec<RepairAction>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PostForRepairApi {
+    pub finding: String,
+    pub action: RepairAction,
+    /// Repository password of an orphan image being adopted, which only the lost row knew.
+    pub repo_password: Option<String>,
+}
+
+fn finding(
+    issue: ReconcileIssue,
+    point: Option<(&str, &str)>,
+    area: Option<&str>,
+    path: &str,
+    detail: String,
+    actions: Vec<RepairAction>,
+) -> ReconcileFindingDto {
+    ReconcileFindingDto {
+        id: format!("{:?}:{}", issue, path),
+        issue,
+        owned_by: point.map(|x| x.0.to_string()),
+        mount_name: point.map(|x| x.1.to_string()),
+        area: area.map(|x| x.to_string()),
+        path: path.to_string(),
+        detail,
+        actions,
+    }
+}
+
+// Backing paths are named `{mount}-{owner}-DATA.{suffix}`, and mount names may contain
+// dashes themselves, so the owner is matched against the known users.
+fn parse_backing_name<'a>(
+    file_name: &'a str,
+    user_names: &[String],
+) -> Option<(&'a str, Option<(String, String)>)> {
+    let (stem, suffix) = file_name.rsplit_once('.')?;
+    storage::backend_for_suffix(suffix)?;
+    let (rest, area) = match stem.strip_suffix("-DATA") {
+        Some(x) => (x, "data"),
+        None => (stem.strip_suffix("-REPO")?, "repo"),
+    };
+    let owner = user_names
+        .iter()
+        .filter_map(|u| {
+            let name = rest.strip_suffix(u.as_str())?.strip_suffix('-')?;
+            (!name.is_empty()).then(|| (u.clone(), name.to_string()))
+        })
+        .max_by_key(|x| x.0.len());
+    Some((area, owner))
+}
+
+fn settled(path: &Path) -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() as i64)
+        .unwrap_or_default();
+    path.metadata().is_ok_and(|x| now - x.ctime() >= orphan_min_age())
+}
+
+fn mount_table() -> Vec<String> {
+    Cmd::new("mount")
+        .arg("-l")
+        .run()
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|x| Some(x.split_once(" on ")?.1.split_once(" type ")?.0.to_string()))
+        .collect()
+}
+
+fn check_point(point: &Mount, found: &mut Vec<ReconcileFindingDto>) {
+    let key = Some((point.owned_by.as_str(), point.mount_name.as_str()));
+    let storage = storage::for_point(point);
+    for (area, img_path, mnt_path, max_bytes) in [
+        ("data", &point.data_img_path, &point.data_mnt_path, point.data_max_bytes),
+        ("repo", &point.repo_img_path, &point.repo_mnt_path, point.repo_max_bytes),
+    ] {
+        if !Path::new(img_path).exists() {
+            found.push(finding(
+                ReconcileIssue::MissingImage,
+                key,
+                Some(area),
+                img_path,
+                "The point exists but its backing image does not".into(),
+                vec![RepairAction::Delete],
+            ));
+            continue;
+        }
+        if !storage.is_mounted(img_path, mnt_path) {
+            found.push(finding(
+                ReconcileIssue::Unmounted,
+                key,
+                Some(area),
+                mnt_path,
+                "The area is not mounted".into(),
+                vec![RepairAction::Remount],
+            ));
+        }
+        if let Some(limit) = storage.limit(img_path).filter(|x| *x != max_bytes as u64) {
+            found.push(finding(
+                ReconcileIssue::SizeMismatch,
+                key,
+                Some(area),
+                img_path,
+                format!("The point allows {} bytes but the image holds {}", max_bytes, limit),
+                vec![RepairAction::Adopt],
+            ));
+        }
+    }
+
+    // A dead `restic mount` leaves a mount which fails every access.
+    let snapshots = format!("{}/repo-mnt", point.repo_mnt_path);
+    if fs::read_dir(snapshots.as_str()).is_err_and(|e| e.raw_os_error() == Some(ENOTCONN)) {
+        found.push(finding(
+            ReconcileIssue::StaleMount,
+            key,
+            Some("repo"),
+            snapshots.as_str(),
+            "The snapshot mount is no longer connected".into(),
+            vec![RepairAction::Remount],
+        ));
+    }
+}
+
+fn scan_disk(
+    points: &[Mount],
+    user_names: &[String],
+    busy: &HashSet<(String, String)>,
+    roots: &[String],
+) -> Vec<ReconcileFindingDto> {
+    let mut found = vec![];
+    for point in points {
+        check_point(point, &mut found);
+    }
+
+    let known_images: HashSet<&str> = points
+        .iter()
+        .flat_map(|x| [x.data_img_path.as_str(), x.repo_img_path.as_str()])
+        .collect();
+    let known_mounts: HashSet<String> = points
+        .iter()
+        .flat_map(|x| {
+            [
+                x.data_mnt_path.clone(),
+                x.repo_mnt_path.clone(),
+                format!("{}/repo-mnt", x.repo_mnt_path),
+            ]
+        })
+        .collect();
+
+    for root in roots.iter().collect::<HashSet<_>>() {
+        let Ok(entries) = fs::read_dir(root) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path().to_string_lossy().to_string();
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if !entry.path().is_file() || known_images.contains(path.as_str()) {
+                continue;
+            }
+            let Some((area, owner)) = parse_backing_name(file_name.as_str(), user_names) else {
+                continue;
+            };
+            // Images of a point being created or migrated exist before its row refers to them.
+            if owner.as_ref().is_some_and(|x| busy.contains(x)) || !settled(&entry.path()) {
+                continue;
+            }
+            let mut actions = vec![RepairAction::Quarantine, RepairAction::Delete];
+            if owner.is_some() {
+                actions.insert(0, RepairAction::Adopt);
+            }
+            found.push(finding(
+                ReconcileIssue::OrphanImage,
+                owner.as_ref().map(|x| (x.0.as_str(), x.1.as_str())),
+                Some(area),
+                path.as_str(),
+                "No point refers to this image".into(),
+                actions,
+            ));
+        }
+    }
+
+    for target in mount_table() {
+        if roots.iter().any(|x| target.starts_with(format!("{}/", x).as_str()))
+            && !known_mounts.contains(target.as_str())
+        {
+            found.push(finding(
+                ReconcileIssue::StaleMount,
+                None,
+                None,
+                target.as_str(),
+                "Something is mounted here for a point which does not exist".into(),
+                vec![RepairAction::Delete],
+            ));
+        }
+    }
+    found
+}
+
+/// Compares the points in the database with what is on disk under the paths of every
+/// storage pool. Nothing is changed.
+pub async fn scan(conn: &mut AsyncPgConnection) -> Result<Vec<ReconcileFindingDto>, NeptisError> {
+    let points: Vec<Mount> = {
+        use crate::schema::mounts::dsl::*;
+        mounts.get_results(conn).await?
+    };
+    let user_names: Vec<String> = {
+        use crate::schema::users::dsl::*;
+        users.select(user_name).get_results(conn).await?
+    };
+    let busy = busy_points(conn).await?;
+    let roots: Vec<String> = all_pools(conn)
+        .await?
+        .into_iter()
+        .flat_map(|x| [x.data_path, x.repo_path])
+        .collect();
+    rocket::tokio::task::spawn_blocking(move || scan_disk(&points, &user_names, &busy, &roots))
+        .await
+        .map_err(|_| NeptisError::InternalError("The reconciliation scan panicked".into()))
+}
+
+// Points held by a live quota reservation or lock, as owner and name. Creating, resizing,
+// cloning and migrating all hold one while their images are on disk but not yet recorded.
+async fn busy_points(
+    conn: &mut AsyncPgConnection,
+) -> Result<HashSet<(String, String)>, NeptisError> {
+    let now = utc_now!();
+    let mut busy: HashSet<(String, String)> = {
+        use crate::schema::quota_reservations::dsl::*;
+        quota_reservations
+            .filter(expire_date.gt(now))
+            .select((user_name, mount_name))
+            .get_results::<(String, String)>(conn)
+            .await?
+            .into_iter()
+            .collect()
+    };
+    busy.extend({
+        use crate::schema::mount_locks::dsl::*;
+        mount_locks
+            .filter(expire_date.gt(now))
+            .select((owned_by, mount_name))
+            .get_results::<(String, String)>(conn)
+            .await?
+    });
+    Ok(busy)
+}
+
+// Checks an orphan image again right before it is touched, as a point may have started
+// using it since the scan.
+async fn ensure_orphaned(
+    conn: &mut AsyncPgConnection,
+    item: &ReconcileFindingDto,
+) -> Result<(), NeptisError> {
+    use crate::schema::mounts::dsl::*;
+
+    let claimed: i64 = mounts
+        .filter(
+            data_img_path
+                .eq(item.path.as_str())
+                .or(repo_img_path.eq(item.path.as_str())),
+        )
+        .count()
+        .get_result(conn)
+        .await?;
+    let in_use = match (item.owned_by.clone(), item.mount_name.clone()) {
+        (Some(p_owner), Some(p_name)) => busy_points(conn).await?.contains(&(p_owner, p_name)),
+        _ => false,
+    };
+    if claimed > 0 || in_use {
+        return Err(NeptisError::Locked("The image is in use by a point".into()));
+    }
+    Ok(())
+}
+
+async fn find_point(
+    conn: &mut AsyncPgConnection,
+    item: &ReconcileFindingDto,
+) -> Result<Mount, NeptisError> {
+    use crate::schema::mounts::dsl::*;
+    let (Some(p_owner), Some(p_name)) = (item.owned_by.clone(), item.mount_name.clone()) else {
+        return Err(NeptisError::BadRequest("The finding has no point!".into()));
+    };
+    Ok(mounts.find((p_owner, p_name)).get_result(conn).await?)
+}
+
+async fn all_pools(conn: &mut AsyncPgConnection) -> Result<Vec<StoragePool>, NeptisError> {
+    use crate::schema::storage_pools::dsl::*;
+    Ok(storage_pools.get_results(conn).await?)
+}
+
+// Returns the data and repository backing paths which belong together with `img_path`,
+// in the pool whose directory holds it.
+fn backing_pair<'a>(
+    img_path: &str,
+    pools: &'a [StoragePool],
+) -> Option<(String, String, &'a StoragePool)> {
+    let (stem, suffix) = img_path.rsplit_once('.')?;
+    let dir = Path::new(stem).parent()?.to_str()?;
+    let name = Path::new(stem).file_name()?.to_str()?;
+    let base = name
+        .strip_suffix("-DATA")
+        .or_else(|| name.strip_suffix("-REPO"))?;
+    let pool = pools.iter().find(|x| x.data_path == dir || x.repo_path == dir)?;
+    Some((
+        format!("{}/{}-DATA.{}", pool.data_path, base, suffix),
+        format!("{}/{}-REPO.{}", pool.repo_path, base, suffix),
+        pool,
+    ))
+}
+
+fn mnt_of(img_path: &str) -> &str {
+    img_path.rsplit_once('.').map(|x| x.0).unwrap_or(img_path)
+}
+
+fn backend_of(img_path: &str) -> Result<StorageBackend, NeptisError> {
+    img_path
+        .rsplit_once('.')
+        .and_then(|x| storage::backend_for_suffix(x.1))
+        .ok_or(NeptisError::BadRequest("The image has an unknown type!".into()))
+}
+
+fn quarantine(img_path: &str) -> Result<(), NeptisError> {
+    let storage = storage::provisioner_for(backend_of(img_path)?);
+    let mnt_path = mnt_of(img_path);
+    storage.unmount(img_path, mnt_path)?;
+
+    let path = Path::new(img_path);
+    let dir = path
+        .parent()
+        .ok_or(NeptisError::BadRequest("The image has no parent!".into()))?
+        .join(".quarantine");
+    fs::create_dir_all(&dir)?;
+    for item in [img_path, mnt_path] {
+        let item = Path::new(item);
+        if let (true, Some(name)) = (item.exists(), item.file_name()) {
+            fs::rename(item, dir.join(name))?;
+        }
+    }
+    Ok(())
+}
+
+fn delete_orphan(img_path: &str) -> Result<(), NeptisError> {
+    let storage = storage::provisioner_for(backend_of(img_path)?);
+    let mnt_path = mnt_of(img_path);
+    // Every backend expects the mount directory to be there when destroying.
+    fs::create_dir_all(mnt_path)?;
+    storage.destroy(img_path, mnt_path)
+}
+
+async fn adopt_orphan(
+    conn: &mut AsyncPgConnection,
+    item: &ReconcileFindingDto,
+    password: Option<String>,
+) -> Result<(), NeptisError> {
+    use crate::schema::mounts::dsl::*;
+
+    let (Some(p_owner), Some(p_name)) = (item.owned_by.clone(), item.mount_name.clone()) else {
+        return Err(NeptisError::BadRequest("The owner of the image is unknown!".into()));
+    };
+    let Some(password) = password.filter(|x| !x.is_empty()) else {
+        return Err(NeptisError::BadRequest(
+            "The repository password is required to adopt an image!".into(),
+        ));
+    };
+    let pools = all_pools(conn).await?;
+    let (d_img, r_img, pool) = backing_pair(item.path.as_str(), &pools)
+        .ok_or(NeptisError::BadRequest("The image is not named like a point!".into()))?;
+    if !Path::new(d_img.as_str()).is_file() || !Path::new(r_img.as_str()).is_file() {
+        return Err(NeptisError::BadRequest(
+            "Both the data and repository images are needed to adopt a point!".into(),
+        ));
+    }
+    let p_backend = backend_of(d_img.as_str())?;
+    let storage = storage::provisioner_for(p_backend);
+    let p_mount = Mount {
+        owned_by: p_owner,
+        mount_name: p_name,
+        data_mnt_path: mnt_of(d_img.as_str()).to_string(),
+        repo_mnt_path: mnt_of(r_img.as_str()).to_string(),
+        data_max_bytes: storage.limit(d_img.as_str()).unwrap_or_default() as i64,
+        repo_max_bytes: storage.limit(r_img.as_str()).unwrap_or_default() as i64,
+        data_img_path: d_img,
+        repo_img_path: r_img,
+        repo_password: password,
+        date_created: utc_now!(),
+        data_accessed: utc_now!(),
+        repo_accessed: utc_now!(),
+        locked: false,
+        rpo_hours: None,
+        is_stale: false,
+        backend: p_backend,
+        fsck_hours: None,
+        health: None,
+        health_checked: None,
+        pool_name: Some(pool.pool_name.clone()),
+    };
+    diesel::insert_into(mounts)
+        .values(p_mount.validate()?)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Applies `dto.action` to the finding it names, then scans again.
+pub async fn repair(
+    conn: &mut AsyncPgConnection,
+    dto: PostForRepairApi,
+) -> Result<Vec<ReconcileFindingDto>, NeptisError> {
+    let item = scan(conn)
+        .await?
+        .into_iter()
+        .find(|x| x.id == dto.finding)
+        .ok_or(NeptisError::BadRequest("The finding no longer applies!".into()))?;
+    if !item.actions.contains(&dto.action) {
+        return Err(NeptisError::BadRequest(format!(
+            "{:?} cannot be applied to {:?}!",
+            dto.action, item.issue
+        )));
+    }
+
+    if item.issue == ReconcileIssue::OrphanImage {
+        ensure_orphaned(conn, &item).await?;
+    }
+
+    match (item.issue, dto.action) {
+        (ReconcileIssue::MissingImage, RepairAction::Delete) => {
+            use crate::schema::mounts::dsl::*;
+            let point = find_point(conn, &item).await?;
+            let lock_id = locks::acquire_lock(conn, &point, true, "reconciliation").await?;
+            let target = mounts.find((point.owned_by.clone(), point.mount_name.clone()));
+            // The lock goes away with the point, so it is only released on failure.
+            if let Err(e) = diesel::delete(target).execute(conn).await {
+                locks::release_lock(conn, lock_id).await?;
+                return Err(e.into());
+            }
+            // Whatever is left of the other area goes too.
+            let storage = storage::for_point(&point);
+            for (img_path, mnt_path) in [
+                (point.data_img_path.as_str(), point.data_mnt_path.as_str()),
+                (point.repo_img_path.as_str(), point.repo_mnt_path.as_str()),
+            ] {
+                if Path::new(img_path).exists() {
+                    fs::create_dir_all(mnt_path)?;
+                    storage.destroy(img_path, mnt_path)?;
+                }
+            }
+            dispatch::emit(
+                point.owned_by.as_str(),
+                WebhookEvent::MountDeleted,
+                json!({ "mount": point.mount_name }),
+            );
+        }
+        (ReconcileIssue::Unmounted | ReconcileIssue::StaleMount, RepairAction::Remount) => {
+            let point = find_point(conn, &item).await?;
+            locks::ensure_unlocked(conn, &point).await?;
+            let is_repo = item.area.as_deref() == Some("repo");
+            rocket::tokio::task::spawn_blocking(move || {
+                if item.issue == ReconcileIssue::StaleMount {
+                    // A lazy unmount is the only way to detach a mount whose process is gone.
+                    Cmd::privileged("umount").arg("-l").arg(item.path.as_str()).run()?;
+                }
+                ensure_point_mounted(&point, is_repo)
+            })
+            .await
+            .map_err(|_| NeptisError::InternalError("The remount panicked".into()))??;
+        }
+        (ReconcileIssue::StaleMount, RepairAction::Delete) => {
+            Cmd::privileged("umount").arg(item.path.as_str()).run()?;
+        }
+        (ReconcileIssue::SizeMismatch, RepairAction::Adopt) => {
+            use crate::schema::mounts::dsl::*;
+            let point = find_point(conn, &item).await?;
+            let limit = storage::for_point(&point)
+                .limit(item.path.as_str())
+                .ok_or(NeptisError::InternalError("Failed to read the image size".into()))?
+                as i64;
+            let target = mounts.find((point.owned_by.clone(), point.mount_name.clone()));
+            match item.area.as_deref() {
+                Some("data") => {
+                    diesel::update(target)
+                        .set(data_max_bytes.eq(limit))
+                        .execute(conn)
+                        .await?
+                }
+                _ => {
+                    diesel::update(target)
+                        .set(repo_max_bytes.eq(limit))
+                        .execute(conn)
+                        .await?
+                }
+            };
+        }
+        (ReconcileIssue::OrphanImage, RepairAction::Adopt) => {
+            adopt_orphan(conn, &item, dto.repo_password).await?;
+        }
+        (ReconcileIssue::OrphanImage, RepairAction::Quarantine) => {
+            quarantine(item.path.as_str())?;
+        }
+        (ReconcileIssue::OrphanImage, RepairAction::Delete) => {
+            delete_orphan(item.path.as_str())?;
+        }
+        _ => {
+            return Err(NeptisError::BadRequest(format!(
+                "{:?} cannot be applied to {:?}!",
+                dto.action, item.issue
+            )));
+        }
+    }
+    scan(conn).await
+}
+
+/// Reports every problem found at startup. Unmounted areas are left out, since after a
+/// restart nothing is mounted until it is used.
+pub async fn log_startup_findings(conn: &mut AsyncPgConnection) {
+    match scan(conn).await {
+        Ok(found) => {
+            for item in found
+                .iter()
+                .filter(|x| x.issue != ReconcileIssue::Unmounted)
+            {
+                println!("[reconcile] {:?} at {}: {}", item.issue, item.path, item.detail);
+            }
+        }
+        Err(e) => println!("Failed to reconcile points with the disk: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owned(owner: &str, name: &str) -> Option<(String, String)> {
+        Some((owner.to_string(), name.to_string()))
+    }
+
+    #[test]
+    fn backing_names_split_into_owner_and_mount() {
+        let users = vec!["bob".to_string(), "alice".to_string()];
+        assert_eq!(
+            parse_backing_name("photos-bob-DATA.img", &users),
+            Some(("data", owned("bob", "photos")))
+        );
+        assert_eq!(
+            parse_backing_name("my-photos-alice-REPO.limit", &users),
+            Some(("repo", owned("alice", "my-photos")))
+        );
+        assert_eq!(parse_backing_name("photos-carol-DATA.img", &users), Some(("data", None)));
+    }
+
+    #[test]
+    fn dashed_owners_prefer_the_longest_match() {
+        let users = vec!["bob".to_string(), "jim-bob".to_string()];
+        assert_eq!(
+            parse_backing_name("docs-jim-bob-DATA.prj", &users),
+            Some(("data", owned("jim-bob", "docs")))
+        );
+    }
+
+    #[test]
+    fn foreign_files_are_ignored() {
+        let users = vec!["bob".to_string()];
+        assert_eq!(parse_backing_name("photos-bob-DATA.txt", &users), None);
+        assert_eq!(parse_backing_name("photos-bob.img", &users), None);
+        assert_eq!(parse_backing_name("README", &users), None);
+        // The owner alone is not a backing path.
+        assert_eq!(parse_backing_name("bob-DATA.img", &users), Some(("data", None)));
+    }
+
+    #[test]
+    fn fresh_images_are_not_settled() {
+        let path = std::env::temp_dir().join(format!("neptis-reconcile-{}", Uuid::new_v4()));
+        fs::write(&path, b"image").unwrap();
+        assert!(!settled(&path));
+        assert!(!settled(&path.with_extension("missing")));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,206 @@
+use std::fs;
+use std::path::Path;
+
+use diesel_async::AsyncConnection;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use rustic_backend::BackendOptions;
+use rustic_core::{KeyOptions, Repository, RepositoryOptions};
+
+use super::locks;
+use super::luks;
+use super::models::*;
+use super::storage;
+use crate::prelude::action_prelude::*;
+
+// Backing and mount paths embed both names, as `{dir}/{name}-{owner}-DATA[.{suffix}]`.
+// The directory and suffix of the current path are kept.
+fn rebuild_path(old: &str, n_owner: &str, n_name: &str, area: &str, has_suffix: bool) -> String {
+    let path = Path::new(old);
+    let dir = path
+        .parent()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let base = format!("{}/{}-{}-{}", dir, n_name, n_owner, area);
+    match (has_suffix, old.rsplit_once('.')) {
+        (true, Some((_, suffix))) => format!("{}.{}", base, suffix),
+        _ => base,
+    }
+}
+
+/// `point` as it will look once owned by `n_owner` and called `n_name`.
+pub fn relocated(point: &Mount, n_owner: &str, n_name: &str) -> Mount {
+    Mount {
+        owned_by: n_owner.to_string(),
+        mount_name: n_name.to_string(),
+        data_img_path: rebuild_path(&point.data_img_path, n_owner, n_name, "DATA", true),
+        data_mnt_path: rebuild_path(&point.data_mnt_path, n_owner, n_name, "DATA", false),
+        repo_img_path: rebuild_path(&point.repo_img_path, n_owner, n_name, "REPO", true),
+        repo_mnt_path: rebuild_path(&point.repo_mnt_path, n_owner, n_name, "REPO", false),
+        ..point.clone()
+    }
+}
+
+/// Unmounts both areas, along with the snapshot mount living inside the repository.
+pub fn detach(point: &Mount) -> Result<(), NeptisError> {
+    let snapshots = format!("{}/repo-mnt", point.repo_mnt_path);
+    if Cmd::new("mountpoint")
+        .arg("-q")
+        .arg(snapshots.as_str())
+        .output()
+        .is_ok_and(|x| x.success())
+    {
+        Cmd::privileged("umount").arg(snapshots).run()?;
+    }
+    let storage = storage::for_point(point);
+    storage.unmount(&point.data_img_path, &point.data_mnt_path)?;
+    storage.unmount(&point.repo_img_path, &point.repo_mnt_path)
+}
+
+fn move_pairs<'a>(from: &'a Mount, to: &'a Mount) -> [(&'a str, &'a str); 4] {
+    [
+        (from.data_img_path.as_str(), to.data_img_path.as_str()),
+        (from.data_mnt_path.as_str(), to.data_mnt_path.as_str()),
+        (from.repo_img_path.as_str(), to.repo_img_path.as_str()),
+        (from.repo_mnt_path.as_str(), to.repo_mnt_path.as_str()),
+    ]
+}
+
+// Moves every path of `from` to where it belongs in `to`, putting back whatever was
+// already moved if one of them fails.
+fn move_point(from: &Mount, to: &Mount) -> Result<(), NeptisError> {
+    let pairs = move_pairs(from, to);
+    if let Some((_, dest)) = pairs.iter().find(|(_, dest)| Path::new(dest).exists()) {
+        return Err(NeptisError::BadRequest(format!("{} already exists!", dest)));
+    }
+    for (done, (src, dest)) in pairs.iter().enumerate() {
+        if let Err(e) = fs::rename(src, dest) {
+            for (src, dest) in pairs[..done].iter().rev() {
+                let _ = fs::rename(dest, src);
+            }
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+fn move_back(from: &Mount, to: &Mount) {
+    for (src, dest) in move_pairs(from, to).iter().rev() {
+        if let Err(e) = fs::rename(dest, src) {
+            println!("Failed to move {} back to {}: {}", dest, src, e);
+        }
+    }
+}
+
+// Replaces the keys of the repository so only `n_password` opens it. The repository
+// area of `point` is mounted for the duration.
+fn rotate_password(point: &Mount, n_password: &str) -> Result<(), NeptisError> {
+    let storage = storage::for_point(point);
+    storage.mount(&point.repo_img_path, &point.repo_mnt_path)?;
+    let ret = (|| -> Result<(), NeptisError> {
+        let repo_dir = format!("{}/repo", point.repo_mnt_path);
+        let backends = BackendOptions::default()
+            .repository(repo_dir.as_str())
+            .to_backends()?;
+        let old_opts = RepositoryOptions::default().password(point.repo_password.as_str());
+        let n_key = Repository::new(&old_opts, &backends)?
+            .open()?
+            .add_key(n_password, &KeyOptions::default())?
+            .to_string();
+        // Make sure the new key opens the repository before the old ones go.
+        Repository::new(&RepositoryOptions::default().password(n_password), &backends)?
+            .open()?;
+        // rustic cannot remove keys, but each one is a file named by its ID, so every
+        // other key file is deleted directly.
+        for entry in fs::read_dir(format!("{}/keys", repo_dir))? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy() != n_key {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(())
+    })();
+    let _ = storage.unmount(&point.repo_img_path, &point.repo_mnt_path);
+    ret
+}
+
+// Remembered LUKS passphrases are keyed by backing path, so they follow the move.
+fn move_passphrases(from: &Mount, to: &Mount) {
+    if from.backend == StorageBackend::LuksExt4 {
+        luks::move_passphrase(from.data_img_path.as_str(), to.data_img_path.as_str());
+        luks::move_passphrase(from.repo_img_path.as_str(), to.repo_img_path.as_str());
+    }
+}
+
+/// Renames `point` and moves it to `n_owner`, on disk and in the database. Jobs, hooks
+/// and locks follow through the `ON UPDATE CASCADE` keys. With `n_password`, the
+/// repository is re-keyed last, so a failure there undoes the whole relocation.
+pub async fn relocate_point(
+    conn: &mut AsyncPgConnection,
+    point: &Mount,
+    n_owner: &str,
+    n_name: &str,
+    n_password: Option<String>,
+    reason: &str,
+) -> Result<Mount, NeptisError> {
+    let lock_id = locks::acquire_lock(conn, point, true, reason).await?;
+    let from = point.clone();
+    let to = relocated(point, n_owner, n_name);
+
+    let ret = async {
+        let (f, t) = (from.clone(), to.clone());
+        rocket::tokio::task::spawn_blocking(move || detach(&f).and_then(|_| move_point(&f, &t)))
+            .await
+            .map_err(|_| NeptisError::InternalError("The relocation panicked".into()))??;
+        // Re-keying opens the repository area at its new path, so this comes first.
+        move_passphrases(&from, &to);
+
+        let p_old = (from.owned_by.clone(), from.mount_name.clone());
+        let t = to.clone();
+        let ret = conn
+            .transaction::<_, NeptisError, _>(|conn| {
+                async move {
+                    use crate::schema::mounts::dsl::*;
+                    let n_point: Mount = diesel::update(mounts.find(p_old))
+                        .set((
+                            owned_by.eq(t.owned_by.as_str()),
+                            mount_name.eq(t.mount_name.as_str()),
+                            data_img_path.eq(t.data_img_path.as_str()),
+                            data_mnt_path.eq(t.data_mnt_path.as_str()),
+                            repo_img_path.eq(t.repo_img_path.as_str()),
+                            repo_mnt_path.eq(t.repo_mnt_path.as_str()),
+                            repo_password
+                                .eq(n_password.clone().unwrap_or(t.repo_password.clone())),
+                        ))
+                        .get_result(conn)
+                        .await?;
+                    if let Some(n_password) = n_password {
+                        rocket::tokio::task::spawn_blocking(move || {
+                            rotate_password(&t, n_password.as_str())
+                        })
+                        .await
+                        .map_err(|_| {
+                            NeptisError::InternalError("The relocation panicked".into())
+                        })??;
+                    }
+                    Ok(n_point)
+                }
+                .scope_boxed()
+            })
+            .await;
+        if ret.is_err() {
+            let (f, t) = (from.clone(), to.clone());
+            let _ = rocket::tokio::task::spawn_blocking(move || {
+                let _ = detach(&t);
+                move_back(&f, &t)
+            })
+            .await;
+            move_passphrases(&to, &from);
+        }
+        ret
+    }
+    .await;
+
+    // The lock moved along with the point, so it is found under its id either way.
+    locks::release_lock(conn, lock_id).await?;
+    ret
+}
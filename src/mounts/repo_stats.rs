@@ -0,0 +1,130 @@
+use std::collections::{HashMap, HashSet};
+
+use rustic_backend::BackendOptions;
+use rustic_core::repofile::BlobType;
+use rustic_core::{LsOptions, Repository, RepositoryOptions};
+use serde::{Deserialize, Serialize};
+
+use crate::api::errors::NeptisError;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SnapshotStatsDto {
+    pub id: String,
+    pub time: String,
+    pub tags: String,
+    /// Bytes read from the data area when the snapshot was taken.
+    pub total_bytes: u64,
+    /// Data blobs referenced by no other snapshot.
+    pub unique_blobs: u64,
+    /// Stored bytes that `forget` + `prune` of this snapshot alone would free.
+    pub unique_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RepoStatsDto {
+    pub data_packs: u64,
+    pub tree_packs: u64,
+    pub data_blobs: u64,
+    pub tree_blobs: u64,
+    /// Size of all blobs before compression.
+    pub raw_bytes: u64,
+    /// Size of all blobs as stored in pack files.
+    pub stored_bytes: u64,
+    /// `raw_bytes / stored_bytes`; above 1.0 means compression is saving space.
+    pub compression_ratio: f64,
+    /// Bytes read over every snapshot.
+    pub total_bytes: u64,
+    /// `total_bytes` over the raw size of data blobs; above 1.0 means deduplication is saving space.
+    pub dedup_ratio: f64,
+    pub snapshots: Vec<SnapshotStatsDto>,
+}
+
+fn ratio(a: u64, b: u64) -> f64 {
+    if b == 0 { 0.0 } else { a as f64 / b as f64 }
+}
+
+/// Walks the index and every snapshot of a repository. This reads every tree in the
+/// repository, so it should be run off the async executor.
+pub fn collect_repo_stats(repo_path: &str, repo_pass: &str) -> Result<RepoStatsDto, NeptisError> {
+    let backends = BackendOptions::default()
+        .repository(repo_path)
+        .to_backends()?;
+    let repo_opts = RepositoryOptions::default().password(repo_pass);
+    let repo = Repository::new(&repo_opts, &backends)?.open()?;
+
+    let mut output = RepoStatsDto {
+        data_packs: 0,
+        tree_packs: 0,
+        data_blobs: 0,
+        tree_blobs: 0,
+        raw_bytes: 0,
+        stored_bytes: 0,
+        compression_ratio: 0.0,
+        total_bytes: 0,
+        dedup_ratio: 0.0,
+        snapshots: vec![],
+    };
+
+    let index = repo.infos_index()?;
+    for pack in index.packs {
+        match pack.blob_type {
+            BlobType::Data => output.data_packs += pack.count,
+            BlobType::Tree => output.tree_packs += pack.count,
+        }
+    }
+    let mut data_raw = 0;
+    for blob in index.blobs {
+        match blob.blob_type {
+            BlobType::Data => {
+                output.data_blobs += blob.count;
+                data_raw += blob.data_size;
+            }
+            BlobType::Tree => output.tree_blobs += blob.count,
+        }
+        output.raw_bytes += blob.data_size;
+        output.stored_bytes += blob.size;
+    }
+    output.compression_ratio = ratio(output.raw_bytes, output.stored_bytes);
+
+    // Count how many snapshots reference each data blob to find what is unique to each.
+    let repo = repo.to_indexed()?;
+    let mut refs = HashMap::new();
+    let mut per_snap = vec![];
+    for snap in repo.get_all_snapshots()? {
+        let node = repo.node_from_snapshot_path(snap.id.to_string().as_str(), |_| true)?;
+        let mut blobs = HashSet::new();
+        for item in repo.ls(&node, &LsOptions::default())? {
+            let (_, f_node) = item?;
+            if let Some(content) = f_node.content {
+                blobs.extend(content);
+            }
+        }
+        for blob in blobs.iter() {
+            *refs.entry(*blob).or_insert(0u32) += 1;
+        }
+        per_snap.push((snap, blobs));
+    }
+
+    for (snap, blobs) in per_snap {
+        let mut s_stats = SnapshotStatsDto {
+            id: snap.id.to_string(),
+            time: snap.time.to_string(),
+            tags: snap.tags.to_string(),
+            total_bytes: snap
+                .summary
+                .as_ref()
+                .map(|x| x.total_bytes_processed)
+                .unwrap_or(0),
+            unique_blobs: 0,
+            unique_bytes: 0,
+        };
+        for blob in blobs.iter().filter(|x| refs.get(*x) == Some(&1)) {
+            s_stats.unique_blobs += 1;
+            s_stats.unique_bytes += repo.get_index_entry(blob)?.length as u64;
+        }
+        output.total_bytes += s_stats.total_bytes;
+        output.snapshots.push(s_stats);
+    }
+    output.dedup_ratio = ratio(output.total_bytes, data_raw);
+    Ok(output)
+}
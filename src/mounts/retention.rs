@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+
+use chrono::Duration;
+use diesel::sql_types::BigInt;
+use rocket_db_pools::diesel::PgPool;
+use serde::{Deserialize, Serialize};
+
+use super::models::*;
+use crate::prelude::action_prelude::*;
+use crate::webhooks::dispatch;
+
+/// How long finished rows in `repo_jobs` are kept around. A value of zero
+/// disables the matching rule. Running jobs, cancelled ones included, are never purged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRetention {
+    /// Newest successful jobs to keep per mount.
+    pub keep_per_mount: usize,
+    /// Days to keep successful jobs.
+    pub keep_days: i64,
+    /// Days to keep failed jobs; these do not count towards `keep_per_mount`.
+    pub keep_failed_days: i64,
+    /// Days to keep webhook deliveries, whether they landed or not.
+    pub keep_delivery_days: i64,
+    /// Seconds between cleanup passes.
+    pub interval_secs: u64,
+}
+
+impl JobRetention {
+    pub fn from_env() -> Self {
+        JobRetention {
+            keep_per_mount: get_env_or!("JOB_KEEP_PER_MOUNT", 50),
+            keep_days: get_env_or!("JOB_KEEP_DAYS", 30),
+            keep_failed_days: get_env_or!("JOB_KEEP_FAILED_DAYS", 90),
+            keep_delivery_days: get_env_or!("WEBHOOK_KEEP_DAYS", 30),
+            interval_secs: get_env_or!("JOB_CLEANUP_INTERVAL", 3600),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JobTableDto {
+    pub total_jobs: i64,
+    pub running_jobs: i64,
+    pub failed_jobs: i64,
+    pub table_bytes: i64,
+    pub policy: JobRetention,
+}
+
+#[derive(QueryableByName)]
+struct TableSize {
+    #[diesel(sql_type = BigInt)]
+    bytes: i64,
+}
+
+pub async fn job_table_stats(conn: &mut AsyncPgConnection) -> Result<JobTableDto, NeptisError> {
+    use crate::schema::repo_jobs::dsl::*;
+
+    let size: TableSize = diesel::sql_query("SELECT pg_total_relation_size('repo_jobs') AS bytes")
+        .get_result(conn)
+        .await?;
+    Ok(JobTableDto {
+        total_jobs: repo_jobs.count().get_result(conn).await?,
+        running_jobs: repo_jobs
+            .filter(job_status.eq(JobStatus::Running))
+            .count()
+            .get_result(conn)
+            .await?,
+        failed_jobs: repo_jobs
+            .filter(job_status.eq(JobStatus::Failed))
+            .count()
+            .get_result(conn)
+            .await?,
+        table_bytes: size.bytes,
+        policy: JobRetention::from_env(),
+    })
+}
+
+pub async fn purge_jobs(
+    conn: &mut AsyncPgConnection,
+    policy: &JobRetention,
+) -> Result<usize, NeptisError> {
+    use crate::schema::repo_jobs::dsl::*;
+
+    let now = utc_now!();
+    let mut deleted = 0;
+
+    if policy.keep_days > 0 {
+        deleted += diesel::delete(repo_jobs)
+            .filter(
+                job_status
+                    .ne(JobStatus::Running)
+                    .and(job_status.ne(JobStatus::Failed))
+                    .and(job_status.ne(JobStatus::Cancelled).or(end_date.is_not_null()))
+                    .and(create_date.lt(now - Duration::days(policy.keep_days))),
+            )
+            .execute(conn)
+            .await?;
+    }
+
+    if policy.keep_failed_days > 0 {
+        deleted += diesel::delete(repo_jobs)
+            .filter(
+                job_status
+                    .eq(JobStatus::Failed)
+                    .and(create_date.lt(now - Duration::days(policy.keep_failed_days))),
+            )
+            .execute(conn)
+            .await?;
+    }
+
+    if policy.keep_per_mount > 0 {
+        // Walk the jobs newest first and drop everything past the limit for each mount.
+        let rows: Vec<(Uuid, String, String)> = repo_jobs
+            .filter(
+                job_status
+                    .ne(JobStatus::Running)
+                    .and(job_status.ne(JobStatus::Failed))
+                    .and(job_status.ne(JobStatus::Cancelled).or(end_date.is_not_null())),
+            )
+            .order(create_date.desc())
+            .select((id, point_owned_by, point_name))
+            .get_results(conn)
+            .await?;
+
+        let mut seen: HashMap<(String, String), usize> = HashMap::new();
+        let stale = rows
+            .into_iter()
+            .filter_map(|(j_id, j_owner, j_name)| {
+                let count = seen.entry((j_owner, j_name)).or_insert(0);
+                *count += 1;
+                (*count > policy.keep_per_mount).then_some(j_id)
+            })
+            .collect::<Vec<_>>();
+
+        if !stale.is_empty() {
+            deleted += diesel::delete(repo_jobs)
+                .filter(id.eq_any(stale))
+                .execute(conn)
+                .await?;
+        }
+    }
+    Ok(deleted)
+}
+
+/// Closes cancelled jobs whose work never got to finish, as no job thread survives a
+/// restart. Until then they cannot be retried.
+pub async fn close_interrupted_jobs(conn: &mut AsyncPgConnection) -> Result<usize, NeptisError> {
+    use crate::schema::repo_jobs::dsl::*;
+
+    let stuck: Vec<RepoJob> = repo_jobs
+        .filter(job_status.eq(JobStatus::Cancelled).and(end_date.is_null()))
+        .get_results(conn)
+        .await?;
+    for mut f_job in stuck.iter().cloned() {
+        f_job.end_date = Some(utc_now!());
+        f_job
+            .errors
+            .push("The server stopped before the job finished".into());
+        diesel::update(repo_jobs.find(f_job.id))
+            .set(&f_job)
+            .execute(conn)
+            .await?;
+    }
+    Ok(stuck.len())
+}
+
+/// Spawns the background task which periodically enforces the retention policy.
+pub fn spawn_job_cleanup(pool: PgPool) {
+    let policy = JobRetention::from_env();
+    if policy.interval_secs == 0 {
+        return;
+    }
+    rocket::tokio::spawn(async move {
+        let mut timer =
+            rocket::tokio::time::interval(std::time::Duration::from_secs(policy.interval_secs));
+        loop {
+            timer.tick().await;
+            let Ok(mut conn) = pool.get().await else {
+                continue;
+            };
+            match purge_jobs(&mut conn, &policy).await {
+                Ok(0) => {}
+                Ok(n) => println!("Purged {} job(s) from history", n),
+                Err(e) => println!("Failed to purge job history: {}", e),
+            }
+            match dispatch::purge_deliveries(&mut conn, policy.keep_delivery_days).await {
+                Ok(0) => {}
+                Ok(n) => println!("Purged {} webhook delivery record(s)", n),
+                Err(e) => println!("Failed to purge webhook deliveries: {}", e),
+            }
+        }
+    });
+}
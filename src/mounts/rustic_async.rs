@@ -1,26 +1,33 @@
 use crossbeam_channel::unbounded;
 use crossbeam_channel::{Receiver, Sender};
-use diesel::{Connection, PgConnection, RunQueryDsl};
+use diesel::{Connection, ExpressionMethods, PgConnection, RunQueryDsl};
 use rustic_backend::BackendOptions;
 use rustic_core::repofile::SnapshotFile;
 use rustic_core::{
-    BackupOptions, ConfigOptions, KeyOptions, LocalDestination, LsOptions, NoProgress, PathList,
+    BackupOptions, CheckOptions, ConfigOptions, KeyOptions, LocalDestination, LsOptions, NoProgress, PathList,
     Progress, ProgressBars, Repository, RepositoryOptions, RestoreOptions, RestorePlan,
     RusticResult, SnapshotOptions,
 };
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::thread::JoinHandle;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{env, thread};
 use uuid::Uuid;
 
+use super::hooks;
+use super::locks;
 use super::models::*;
 use crate::api::errors::NeptisError;
 use crate::diesel::QueryDsl;
 use crate::utc_now;
+use crate::webhooks::dispatch;
 
 pub type ProgressType = (Uuid, SendUpdate);
 
+/// How often a job's progress pushes back the expiry of the point lock it holds.
+const LOCK_REFRESH: Duration = Duration::from_secs(60);
+
 pub struct DbProgressBars {
     job_id: Uuid,
     tx: Sender<ProgressType>,
@@ -118,8 +125,12 @@ pub struct NonBlockingRustic {
 pub struct JobLaunchInfo {
     pub point_owned_by: String,
     pub point_name: String,
+    pub data_path: String,
     pub repo_path: String,
     pub repo_pass: String,
+    pub acted_by: Option<String>,
+    /// Point lock held for the job, released once it finishes.
+    pub lock_id: Option<Uuid>,
 }
 
 impl NonBlockingRustic {
@@ -153,23 +164,24 @@ impl NonBlockingRustic {
     }
 
     pub fn handle_progress_update(conn: &mut PgConnection, rx: Receiver<ProgressType>) {
+        let mut refreshed: HashMap<Uuid, Instant> = HashMap::new();
         loop {
             match rx.recv() {
                 Ok((job_id, r_update)) => {
+                    Self::refresh_lock(conn, &mut refreshed, job_id);
                     let _: Result<usize, NeptisError> = (|| {
                         use crate::schema::repo_jobs::dsl::*;
-                        let mut job: RepoJob = repo_jobs
-                            .find(job_id)
-                            .get_result(conn)
-                            .expect("Expected Job ID to be valid in `DbProgressBars`");
-
-                        match r_update {
-                            SendUpdate::Increment(inc) => job.used_bytes += inc as i64,
-                            SendUpdate::LengthSet(len) => job.total_bytes = Some(len as i64),
-                            _ => return Ok(0),
-                        }
-
-                        return Ok(diesel::update(repo_jobs).set(&job).execute(conn)?);
+                        // Only touch the progress columns so a concurrent cancel is not overwritten.
+                        let target = repo_jobs.find(job_id);
+                        Ok(match r_update {
+                            SendUpdate::Increment(inc) => diesel::update(target)
+                                .set(used_bytes.eq(used_bytes + inc as i64))
+                                .execute(conn)?,
+                            SendUpdate::LengthSet(len) => diesel::update(target)
+                                .set(total_bytes.eq(Some(len as i64)))
+                                .execute(conn)?,
+                            _ => 0,
+                        })
                     })();
                 }
                 Err(_) => break, // terminate the loop
@@ -182,7 +194,7 @@ impl NonBlockingRustic {
         &self,
         launch_info: &JobLaunchInfo,
         snap_path: &str,
-        abs_dest_path: &str,
+        rel_dest_path: &str,
         r_opts: RestoreOptions,
         dry_run: bool,
     ) -> Result<Uuid, NeptisError> {
@@ -191,6 +203,7 @@ impl NonBlockingRustic {
             .to_backends()?;
         let repo_opts = RepositoryOptions::default().password(launch_info.repo_pass.as_str());
         let job_id = Uuid::new_v4();
+        let lock_id = launch_info.lock_id;
 
         let p_bar = DbProgressBars::new(job_id, self.tx.clone());
 
@@ -207,6 +220,12 @@ impl NonBlockingRustic {
             errors: vec![],
             create_date: utc_now!(),
             end_date: None,
+            acted_by: launch_info.acted_by.clone(),
+            target_path: Some(rel_dest_path.to_string()),
+            launch_dry_run: dry_run,
+            launch_read_data: false,
+            launch_tags: vec![],
+            logs: vec![],
         };
         {
             use crate::schema::repo_jobs::dsl::*;
@@ -214,13 +233,20 @@ impl NonBlockingRustic {
                 &env::var("DATABASE_URL").expect("DATABASE_URL must be set"),
             )?;
             diesel::insert_into(repo_jobs)
-                .values(s_job)
+                .values(&s_job)
                 .execute(&mut conn)?;
+            Self::attach_lock(&mut conn, lock_id, job_id)?;
+            dispatch::emit_job(&s_job);
             let s_path = snap_path.to_owned();
-            let d_path = abs_dest_path.to_owned();
+            let d_path = format!(
+                "{}/{}",
+                launch_info.data_path,
+                rel_dest_path.trim_start_matches('/')
+            );
             thread::spawn(move || {
-                Self::finish_restore(
+                Self::finish_job(
                     job_id,
+                    lock_id,
                     (|| {
                         let repo = Repository::new_with_progress(&repo_opts, &backends, p_bar)?
                             .open()?
@@ -240,18 +266,105 @@ impl NonBlockingRustic {
         Ok(job_id)
     }
 
+    /// Restores the snapshot `snap_id` of another repository into the data area of a
+    /// freshly created point. With `seed`, the snapshot is also copied into the point's
+    /// own repository once restored. `source_lock_id` is released along with the job.
+    pub fn start_clone(
+        &self,
+        launch_info: &JobLaunchInfo,
+        source_path: &str,
+        source_pass: &str,
+        snap_id: &str,
+        seed: bool,
+        source_lock_id: Option<Uuid>,
+    ) -> Result<Uuid, NeptisError> {
+        let src_backends = BackendOptions::default()
+            .repository(source_path)
+            .to_backends()?;
+        let src_opts = RepositoryOptions::default().password(source_pass);
+        let dest_backends = BackendOptions::default()
+            .repository(launch_info.repo_path.as_str())
+            .to_backends()?;
+        let dest_opts = RepositoryOptions::default().password(launch_info.repo_pass.as_str());
+        let job_id = Uuid::new_v4();
+        let lock_id = launch_info.lock_id;
+        let p_bar = DbProgressBars::new(job_id, self.tx.clone());
+
+        let s_job = RepoJob {
+            id: job_id,
+            snapshot_id: Some(snap_id.to_string()),
+            point_owned_by: launch_info.point_owned_by.clone(),
+            point_name: launch_info.point_name.clone(),
+            job_type: JobType::Restore,
+            job_status: JobStatus::Running,
+            used_bytes: 0,
+            total_bytes: None,
+            errors: vec![],
+            create_date: utc_now!(),
+            end_date: None,
+            acted_by: launch_info.acted_by.clone(),
+            target_path: None,
+            launch_dry_run: false,
+            launch_read_data: false,
+            launch_tags: vec![],
+            logs: vec![],
+        };
+        {
+            use crate::schema::repo_jobs::dsl::*;
+            let mut conn = PgConnection::establish(
+                &env::var("DATABASE_URL").expect("DATABASE_URL must be set"),
+            )?;
+            diesel::insert_into(repo_jobs)
+                .values(&s_job)
+                .execute(&mut conn)?;
+            Self::attach_lock(&mut conn, lock_id, job_id)?;
+            Self::attach_lock(&mut conn, source_lock_id, job_id)?;
+            dispatch::emit_job(&s_job);
+            let s_id = snap_id.to_owned();
+            let d_path = launch_info.data_path.clone();
+            thread::spawn(move || {
+                let ret = (|| -> RusticResult<()> {
+                    let repo = Repository::new_with_progress(&src_opts, &src_backends, p_bar)?
+                        .open()?
+                        .to_indexed()?;
+
+                    let node = repo.node_from_snapshot_path(s_id.as_str(), |_| true)?;
+                    let ls = repo.ls(&node, &LsOptions::default())?;
+                    let dest = LocalDestination::new(d_path.as_str(), true, !node.is_dir())?;
+                    let r_opts = RestoreOptions::default();
+                    let plan = repo.prepare_restore(&r_opts, ls.clone(), &dest, false)?;
+                    repo.restore(plan, &r_opts, ls, &dest)?;
+
+                    if seed {
+                        let snap = repo.get_snapshot_from_str(s_id.as_str(), |_| true)?;
+                        let dest_repo = Repository::new(&dest_opts, &dest_backends)?
+                            .open()?
+                            .to_indexed_ids()?;
+                        repo.copy(&dest_repo, [&snap])?;
+                    }
+                    Ok(())
+                })();
+                Self::release_lock(&mut conn, source_lock_id);
+                Self::finish_job(job_id, lock_id, ret, &mut conn);
+            });
+        }
+        Ok(job_id)
+    }
+
     pub fn start_backup(
         &self,
         launch_info: &JobLaunchInfo,
         source: PathList,
         s_opts: SnapshotOptions,
         b_opts: BackupOptions,
+        m_hooks: Vec<MountHook>,
     ) -> Result<Uuid, NeptisError> {
         let backends = BackendOptions::default()
             .repository(launch_info.repo_path.as_str())
             .to_backends()?;
         let repo_opts = RepositoryOptions::default().password(launch_info.repo_pass.as_str());
         let job_id = Uuid::new_v4();
+        let lock_id = launch_info.lock_id;
         let p_bar = DbProgressBars::new(job_id, self.tx.clone());
 
         let s_job = RepoJob {
@@ -266,6 +379,87 @@ impl NonBlockingRustic {
             errors: vec![],
             create_date: utc_now!(),
             end_date: None,
+            acted_by: launch_info.acted_by.clone(),
+            target_path: None,
+            launch_dry_run: b_opts.dry_run,
+            launch_read_data: false,
+            launch_tags: s_opts.tags.iter().flat_map(|x| x.iter().cloned()).collect(),
+            logs: vec![],
+        };
+        {
+            use crate::schema::repo_jobs::dsl::*;
+            let mut conn = PgConnection::establish(
+                &env::var("DATABASE_URL").expect("DATABASE_URL must be set"),
+            )?;
+            diesel::insert_into(repo_jobs)
+                .values(&s_job)
+                .execute(&mut conn)?;
+            Self::attach_lock(&mut conn, lock_id, job_id)?;
+            dispatch::emit_job(&s_job);
+            let mut h_env = vec![
+                ("NEPTIS_JOB_ID", job_id.to_string()),
+                ("NEPTIS_MOUNT_OWNER", launch_info.point_owned_by.clone()),
+                ("NEPTIS_MOUNT_NAME", launch_info.point_name.clone()),
+                ("NEPTIS_DATA_PATH", launch_info.data_path.clone()),
+            ];
+            thread::spawn(move || {
+                let mut h_logs = vec![];
+                let ret = (|| -> Result<SnapshotFile, NeptisError> {
+                    // A failing pre-backup hook aborts the job before rustic is touched.
+                    hooks::run_hooks(&m_hooks, HookType::PreBackup, &h_env, &mut h_logs)?;
+                    let repo = Repository::new_with_progress(&repo_opts, &backends, p_bar)?
+                        .open()?
+                        .to_indexed()?;
+
+                    // Finally, we need to spawn a new thread to handle everything.
+                    let s_file = s_opts.to_snapshot()?;
+                    Ok(repo.backup(&b_opts, &source, s_file)?)
+                })();
+
+                let post_ret = match ret {
+                    Ok(ref snap) => {
+                        h_env.push(("NEPTIS_SNAPSHOT_ID", snap.id.to_string()));
+                        hooks::run_hooks(&m_hooks, HookType::PostSuccess, &h_env, &mut h_logs)
+                    }
+                    Err(_) => hooks::run_hooks(&m_hooks, HookType::PostFailure, &h_env, &mut h_logs),
+                };
+                Self::finish_backup(job_id, lock_id, ret, post_ret, h_logs, &mut conn);
+            });
+        }
+        Ok(job_id)
+    }
+
+    pub fn start_check(
+        &self,
+        launch_info: &JobLaunchInfo,
+        c_opts: CheckOptions,
+    ) -> Result<Uuid, NeptisError> {
+        let backends = BackendOptions::default()
+            .repository(launch_info.repo_path.as_str())
+            .to_backends()?;
+        let repo_opts = RepositoryOptions::default().password(launch_info.repo_pass.as_str());
+        let job_id = Uuid::new_v4();
+        let lock_id = launch_info.lock_id;
+        let p_bar = DbProgressBars::new(job_id, self.tx.clone());
+
+        let s_job = RepoJob {
+            id: job_id,
+            snapshot_id: None,
+            point_owned_by: launch_info.point_owned_by.clone(),
+            point_name: launch_info.point_name.clone(),
+            job_type: JobType::Check,
+            job_status: JobStatus::Running,
+            used_bytes: 0,
+            total_bytes: None,
+            errors: vec![],
+            create_date: utc_now!(),
+            end_date: None,
+            acted_by: launch_info.acted_by.clone(),
+            target_path: None,
+            launch_dry_run: false,
+            launch_read_data: c_opts.read_data,
+            launch_tags: vec![],
+            logs: vec![],
         };
         {
             use crate::schema::repo_jobs::dsl::*;
@@ -273,19 +467,18 @@ impl NonBlockingRustic {
                 &env::var("DATABASE_URL").expect("DATABASE_URL must be set"),
             )?;
             diesel::insert_into(repo_jobs)
-                .values(s_job)
+                .values(&s_job)
                 .execute(&mut conn)?;
+            Self::attach_lock(&mut conn, lock_id, job_id)?;
+            dispatch::emit_job(&s_job);
             thread::spawn(move || {
-                Self::finish_backup(
+                Self::finish_job(
                     job_id,
+                    lock_id,
                     (|| {
-                        let repo = Repository::new_with_progress(&repo_opts, &backends, p_bar)?
+                        Repository::new_with_progress(&repo_opts, &backends, p_bar)?
                             .open()?
-                            .to_indexed()?;
-
-                        // Finally, we need to spawn a new thread to handle everything.
-                        let s_file = s_opts.to_snapshot()?;
-                        repo.backup(&b_opts, &source, s_file)
+                            .check(c_opts)
                     })(),
                     &mut conn,
                 );
@@ -294,53 +487,139 @@ impl NonBlockingRustic {
         Ok(job_id)
     }
 
-    fn finish_restore(job_id: Uuid, ret: RusticResult<()>, conn: &mut PgConnection) {
+    fn finish_job(
+        job_id: Uuid,
+        lock_id: Option<Uuid>,
+        ret: RusticResult<()>,
+        conn: &mut PgConnection,
+    ) {
         use crate::schema::repo_jobs::dsl::*;
+        Self::release_lock(conn, lock_id);
         let mut f_job: RepoJob = repo_jobs
             .find(job_id)
             .get_result(conn)
             .expect("Job ID is supposed to be valid at this point!".into());
 
+        // A cancelled job keeps its status; the outcome is only noted. Either way the
+        // end date records when the work really stopped.
         f_job.end_date = Some(utc_now!());
-        match ret {
-            Ok(_) => {
-                f_job.job_status = JobStatus::Successful;
-            }
-            Err(e) => {
-                f_job.job_status = JobStatus::Failed;
-                f_job.errors.push(e.to_string());
+        if f_job.job_status == JobStatus::Cancelled {
+            f_job.errors.push(match ret {
+                Ok(_) => "Job completed after it was cancelled".into(),
+                Err(e) => e.to_string(),
+            });
+        } else {
+            match ret {
+                Ok(_) => {
+                    f_job.job_status = JobStatus::Successful;
+                }
+                Err(e) => {
+                    f_job.job_status = JobStatus::Failed;
+                    f_job.errors.push(e.to_string());
+                }
             }
         }
-        let _ = diesel::update(repo_jobs)
+        let _ = diesel::update(repo_jobs.find(job_id))
             .set(&f_job)
             .execute(conn)
             .expect("Failed to access DB!".into());
+        dispatch::emit_job(&f_job);
     }
 
-    fn finish_backup(job_id: Uuid, ret: RusticResult<SnapshotFile>, conn: &mut PgConnection) {
+    fn finish_backup(
+        job_id: Uuid,
+        lock_id: Option<Uuid>,
+        ret: Result<SnapshotFile, NeptisError>,
+        post_ret: Result<(), NeptisError>,
+        h_logs: Vec<String>,
+        conn: &mut PgConnection,
+    ) {
         use crate::schema::repo_jobs::dsl::*;
+        Self::release_lock(conn, lock_id);
         let mut f_job: RepoJob = repo_jobs
             .find(job_id)
             .get_result(conn)
             .expect("Job ID is supposed to be valid at this point!".into());
 
+        f_job.logs.extend(h_logs);
+        if let Err(e) = post_ret {
+            f_job.errors.push(e.to_string());
+        }
+
+        let cancelled = f_job.job_status == JobStatus::Cancelled;
         f_job.end_date = Some(utc_now!());
         match ret {
             Ok(x) => {
-                f_job.job_status = JobStatus::Successful;
-                f_job.snapshot_id = Some(x.id.to_string());
+                if cancelled {
+                    f_job.errors.push("Job completed after it was cancelled".into());
+                } else {
+                    f_job.job_status = JobStatus::Successful;
+                }
+                // A dry run computes an ID but never writes the snapshot.
+                if !f_job.launch_dry_run {
+                    f_job.snapshot_id = Some(x.id.to_string());
+                }
                 if let Some(summary) = x.summary {
                     f_job.used_bytes = summary.total_bytes_processed as i64;
                 }
             }
             Err(e) => {
-                f_job.job_status = JobStatus::Failed;
+                if !cancelled {
+                    f_job.job_status = JobStatus::Failed;
+                }
                 f_job.errors.push(e.to_string());
             }
         }
-        let _ = diesel::update(repo_jobs)
+        let _ = diesel::update(repo_jobs.find(job_id))
             .set(&f_job)
             .execute(conn)
             .expect("Failed to access DB!".into());
+        dispatch::emit_job(&f_job);
+    }
+
+    // Tie the point lock to the job, so the job's progress keeps it from going stale.
+    pub fn attach_lock(
+        conn: &mut PgConnection,
+        lock_id: Option<Uuid>,
+        p_job: Uuid,
+    ) -> Result<(), NeptisError> {
+        use crate::schema::mount_locks::dsl as l;
+        if let Some(lock_id) = lock_id {
+            diesel::update(l::mount_locks.find(lock_id))
+                .set(l::job_id.eq(Some(p_job)))
+                .execute(conn)?;
+        }
+        Ok(())
+    }
+
+    // Push back the expiry of the lock a job holds, at most once per `LOCK_REFRESH`, so
+    // a job running longer than `MOUNT_LOCK_TTL` keeps it for as long as it progresses.
+    fn refresh_lock(conn: &mut PgConnection, refreshed: &mut HashMap<Uuid, Instant>, p_job: Uuid) {
+        use crate::schema::mount_locks::dsl as l;
+        if refreshed.get(&p_job).is_some_and(|x| x.elapsed() < LOCK_REFRESH) {
+            return;
+        }
+        // Jobs that stopped reporting have finished; forget them.
+        refreshed.retain(|_, x| x.elapsed() < LOCK_REFRESH * 10);
+        refreshed.insert(p_job, Instant::now());
+        let _ = diesel::update(l::mount_locks.filter(l::job_id.eq(p_job)))
+            .set(l::expire_date.eq(utc_now!() + locks::lock_ttl()))
+            .execute(conn);
+    }
+
+    // Release the point lock a job was holding, mirroring `locks::release_lock`.
+    pub fn release_lock(conn: &mut PgConnection, lock_id: Option<Uuid>) {
+        use crate::schema::mount_locks::dsl::*;
+        use crate::schema::mounts::dsl as m;
+        let Some(o_lock) = lock_id.and_then(|x| mount_locks.find(x).get_result::<MountLock>(conn).ok())
+        else {
+            return;
+        };
+        let _ = diesel::delete(mount_locks.find(o_lock.id)).execute(conn);
+        if o_lock.is_exclusive {
+            let _ = diesel::update(m::mounts.find((o_lock.owned_by, o_lock.mount_name)))
+                .set(m::locked.eq(false))
+                .execute(conn);
+        }
     }
 }
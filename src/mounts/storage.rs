@@ -0,0 +1,850 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use super::luks;
+use super::models::{Mount, StorageBackend};
+use super::provisioning;
+use crate::api::command::CommandOutput;
+use crate::prelude::action_prelude::*;
+
+pub struct MountStats {
+    pub path: String,
+    pub b_total: usize,
+    pub b_used: usize,
+    pub b_avail: usize,
+}
+
+/// Backing storage for the data and repository areas of a point. Each area is
+/// identified by the pair of its backing path (`img_path`) and the directory it is
+/// used through (`mnt_path`).
+pub trait StorageProvisioner: Send + Sync {
+    /// Extension given to new backing paths.
+    fn image_suffix(&self) -> &'static str;
+
+    /// Allocates a new area limited to `bytes`. It is left unmounted.
+    fn create(&self, img_path: &str, mnt_path: &str, bytes: i64) -> Result<(), NeptisError>;
+
+    fn is_mounted(&self, img_path: &str, mnt_path: &str) -> bool;
+
+    fn mount(&self, img_path: &str, mnt_path: &str) -> Result<(), NeptisError>;
+
+    fn unmount(&self, img_path: &str, mnt_path: &str) -> Result<(), NeptisError>;
+
+    /// Raises the limit of an area to `bytes`, which leaves it unmounted.
+    fn grow(&self, img_path: &str, mnt_path: &str, bytes: i64) -> Result<(), NeptisError>;
+
+    /// Lowers the limit of an area to `bytes`, which leaves it unmounted. The caller
+    /// must make sure the used space fits.
+    fn shrink(&self, img_path: &str, mnt_path: &str, bytes: i64) -> Result<(), NeptisError>;
+
+    /// Unmounts the area and removes it along with everything stored in it.
+    fn destroy(&self, img_path: &str, mnt_path: &str) -> Result<(), NeptisError>;
+
+    /// Space used by a mounted area.
+    fn usage(&self, img_path: &str, mnt_path: &str) -> Result<MountStats, NeptisError>;
+
+    /// Host space the area takes up, which does not require it to be mounted.
+    fn host_usage(&self, img_path: &str, mnt_path: &str) -> Result<usize, NeptisError> {
+        Ok(self.usage(img_path, mnt_path)?.b_used)
+    }
+
+    /// Whether the area draws on host space as it fills up, rather than holding all
+    /// of its limit from the start.
+    fn is_sparse(&self, _img_path: &str) -> bool {
+        true
+    }
+
+    /// The limit recorded on disk, when it can be read without mounting the area.
+    fn limit(&self, _img_path: &str) -> Option<u64> {
+        None
+    }
+
+    /// Runs `e2fsck` over an unmounted area, only reporting problems unless `repair` is
+    /// set. Backends without a filesystem of their own have nothing to check.
+    fn check_fs(
+        &self,
+        _img_path: &str,
+        _mnt_path: &str,
+        _repair: bool,
+    ) -> Result<CommandOutput, NeptisError> {
+        Err(NeptisError::BadRequest(
+            "The point is not stored in a filesystem image of its own!".into(),
+        ))
+    }
+}
+
+/// Reports the usage of the filesystem holding `path` through `df`.
+pub fn disk_usage(path: &str) -> Result<MountStats, NeptisError> {
+    if path.is_empty() {
+        return Err(NeptisError::InternalError("Failed to get system info!".into()));
+    }
+    let res = Cmd::new("df").args(["-B1", "--"]).arg(path).run()?;
+    /*
+       Filesystem        1B-blocks         Used    Available Use% Mounted on
+       /dev/nvme0n1p5 433992540160 255224807424 156646924288  62% /
+    */
+    (|| {
+        let spl = res
+            .split_terminator("\n")
+            .nth(1)?
+            .split_whitespace()
+            .collect::<Vec<_>>();
+
+        Some(MountStats {
+            path: path.to_string(),
+            b_total: spl.get(1)?.parse::<usize>().ok()?,
+            b_used: spl.get(2)?.parse::<usize>().ok()?,
+            b_avail: spl.get(3)?.parse::<usize>().ok()?,
+        })
+    })()
+    .ok_or(NeptisError::InternalError(format!(
+        "Failed to parse `df` output: {}",
+        res
+    )))
+}
+
+/// Each area is an ext4 image attached through a loop device. Requires root.
+pub struct LoopExt4Provisioner;
+
+impl LoopExt4Provisioner {
+    // Sparse images are only extended, so they take host space as blocks are written.
+    fn allocate(img_path: &str, bytes: i64) -> Result<(), NeptisError> {
+        let (program, flag) = match provisioning::thin_provisioning() {
+            true => ("truncate", "-s"),
+            false => ("fallocate", "-l"),
+        };
+        Cmd::privileged(program)
+            .arg(flag)
+            .arg(bytes.to_string())
+            .arg(img_path)
+            .run()
+            .map(|_| ())
+    }
+
+    fn block_size(img_path: &str) -> Result<usize, NeptisError> {
+        let res = Cmd::privileged("tune2fs").arg("-l").arg(img_path).run()?;
+        res.lines()
+            .find_map(|x| x.strip_prefix("Block size:"))
+            .and_then(|x| x.trim().parse::<usize>().ok())
+            .ok_or(NeptisError::InternalError(
+                "Failed to determine block size of FS".into(),
+            ))
+    }
+
+    // e2fsck exits with 1 after fixing errors, which is still a usable filesystem.
+    fn fsck(img_path: &str) -> Result<(), NeptisError> {
+        let cmd = Cmd::privileged("e2fsck").args(["-f", "-y"]).arg(img_path);
+        let output = cmd.output()?;
+        match output.status {
+            Some(0) | Some(1) => Ok(()),
+            _ => Err(NeptisError::Command {
+                command: cmd.display(),
+                status: output.status,
+                stdout: output.stdout.trim().to_string(),
+                stderr: output.stderr.trim().to_string(),
+            }),
+        }
+    }
+}
+
+impl StorageProvisioner for LoopExt4Provisioner {
+    fn image_suffix(&self) -> &'static str {
+        "img"
+    }
+
+    fn create(&self, img_path: &str, mnt_path: &str, bytes: i64) -> Result<(), NeptisError> {
+        fs::create_dir_all(mnt_path)?;
+        Self::allocate(img_path, bytes)?;
+        Cmd::privileged("mkfs.ext4").args(["-q", "-F"]).arg(img_path).run()?;
+        Cmd::privileged("chmod").arg("777").arg(img_path).run()?;
+        Ok(())
+    }
+
+    fn is_mounted(&self, img_path: &str, mnt_path: &str) -> bool {
+        let Ok(table) = Cmd::new("mount").arg("-l").run() else {
+            return false;
+        };
+        let needle = format!("{} on {} type ext4", img_path, mnt_path);
+        table
+            .split_terminator("\n")
+            .any(|line| line.contains(needle.as_str()))
+    }
+
+    fn mount(&self, img_path: &str, mnt_path: &str) -> Result<(), NeptisError> {
+        if !fs::exists(mnt_path)? {
+            fs::create_dir_all(mnt_path)?;
+        }
+        // Discarding freed blocks punches them out of sparse images again.
+        let options = match self.is_sparse(img_path) {
+            true => "rw,sync,loop,discard",
+            false => "rw,sync,loop",
+        };
+        Cmd::privileged("mount")
+            .args(["-o", options, "--"])
+            .args([img_path, mnt_path])
+            .run()?;
+        Cmd::privileged("chmod").args(["777", mnt_path, img_path]).run()?;
+        match self.is_mounted(img_path, mnt_path) {
+            true => Ok(()),
+            false => Err(NeptisError::InternalError("Failed to mount point!".into())),
+        }
+    }
+
+    fn unmount(&self, img_path: &str, mnt_path: &str) -> Result<(), NeptisError> {
+        if !self.is_mounted(img_path, mnt_path) {
+            return Ok(());
+        }
+        Cmd::privileged("umount").arg(mnt_path).run().map(|_| ())
+    }
+
+    fn grow(&self, img_path: &str, mnt_path: &str, bytes: i64) -> Result<(), NeptisError> {
+        self.unmount(img_path, mnt_path)?;
+        Self::fsck(img_path)?;
+        Self::allocate(img_path, bytes)?;
+        Self::fsck(img_path)?;
+        Cmd::privileged("resize2fs").arg(img_path).run()?;
+        Ok(())
+    }
+
+    fn shrink(&self, img_path: &str, mnt_path: &str, bytes: i64) -> Result<(), NeptisError> {
+        self.unmount(img_path, mnt_path)?;
+        let blocks = bytes / Self::block_size(img_path)? as i64;
+        Self::fsck(img_path)?;
+        Cmd::privileged("resize2fs")
+            .arg(img_path)
+            .arg(blocks.to_string())
+            .run()?;
+        Self::fsck(img_path)?;
+        // Hand the space past the end of the filesystem back to the host.
+        Cmd::privileged("truncate")
+            .arg("-s")
+            .arg(bytes.to_string())
+            .arg(img_path)
+            .run()?;
+        Ok(())
+    }
+
+    fn destroy(&self, img_path: &str, mnt_path: &str) -> Result<(), NeptisError> {
+        self.unmount(img_path, mnt_path)?;
+        fs::remove_dir(mnt_path)?;
+        fs::remove_file(img_path)?;
+        Ok(())
+    }
+
+    fn usage(&self, _img_path: &str, mnt_path: &str) -> Result<MountStats, NeptisError> {
+        disk_usage(mnt_path)
+    }
+
+    fn host_usage(&self, img_path: &str, _mnt_path: &str) -> Result<usize, NeptisError> {
+        Ok(fs::metadata(img_path)?.blocks() as usize * 512)
+    }
+
+    fn is_sparse(&self, img_path: &str) -> bool {
+        fs::metadata(img_path).is_ok_and(|x| x.blocks() * 512 < x.len())
+    }
+
+    fn limit(&self, img_path: &str) -> Option<u64> {
+        fs::metadata(img_path).ok().map(|x| x.len())
+    }
+
+    fn check_fs(
+        &self,
+        img_path: &str,
+        mnt_path: &str,
+        repair: bool,
+    ) -> Result<CommandOutput, NeptisError> {
+        self.unmount(img_path, mnt_path)?;
+        Cmd::privileged("e2fsck")
+            .args(["-f", if repair { "-y" } else { "-n" }])
+            .arg(img_path)
+            .output()
+    }
+}
+
+/// Each area is an ext4 filesystem inside a LUKS2 container, attached through a loop
+/// device. The key of each container is kept in its header, wrapped by the server master
+/// key. Requires root.
+pub struct LuksExt4Provisioner;
+
+impl LuksExt4Provisioner {
+    // Space LUKS2 reserves in front of the encrypted data by default.
+    const HEADER_BYTES: i64 = 16 * 1024 * 1024;
+
+    fn device(img_path: &str) -> String {
+        format!("/dev/mapper/{}", luks::mapper_name(img_path))
+    }
+
+    fn open(img_path: &str) -> Result<(), NeptisError> {
+        if Path::new(Self::device(img_path).as_str()).exists() {
+            return Ok(());
+        }
+        Cmd::privileged("cryptsetup")
+            .args(["open", "--type", "luks2", "--key-file", "-"])
+            .args([img_path, luks::mapper_name(img_path).as_str()])
+            .stdin(luks::open_key(img_path)?)
+            .run()
+            .map(|_| ())
+    }
+
+    fn close(img_path: &str) -> Result<(), NeptisError> {
+        if !Path::new(Self::device(img_path).as_str()).exists() {
+            return Ok(());
+        }
+        Cmd::privileged("cryptsetup")
+            .arg("close")
+            .arg(luks::mapper_name(img_path))
+            .run()
+            .map(|_| ())
+    }
+}
+
+impl StorageProvisioner for LuksExt4Provisioner {
+    fn image_suffix(&self) -> &'static str {
+        "luks"
+    }
+
+    fn create(&self, img_path: &str, mnt_path: &str, bytes: i64) -> Result<(), NeptisError> {
+        fs::create_dir_all(mnt_path)?;
+        LoopExt4Provisioner::allocate(img_path, bytes)?;
+        let (key, token) = luks::new_key(img_path)?;
+        Cmd::privileged("cryptsetup")
+            .args(["luksFormat", "--type", "luks2", "--batch-mode", "--key-file", "-"])
+            .arg(img_path)
+            .stdin(key)
+            .run()?;
+        Cmd::privileged("cryptsetup")
+            .args(["token", "import", "--json-file", "-"])
+            .arg(img_path)
+            .stdin(token.into_bytes())
+            .run()?;
+        Self::open(img_path)?;
+        let ret = Cmd::privileged("mkfs.ext4")
+            .args(["-q", "-F"])
+            .arg(Self::device(img_path))
+            .run();
+        Self::close(img_path)?;
+        ret?;
+        Cmd::privileged("chmod").arg("777").arg(img_path).run()?;
+        Ok(())
+    }
+
+    fn is_mounted(&self, img_path: &str, mnt_path: &str) -> bool {
+        let Ok(table) = Cmd::new("mount").arg("-l").run() else {
+            return false;
+        };
+        let needle = format!("{} on {} type ext4", Self::device(img_path), mnt_path);
+        table
+            .split_terminator("\n")
+            .any(|line| line.contains(needle.as_str()))
+    }
+
+    fn mount(&self, img_path: &str, mnt_path: &str) -> Result<(), NeptisError> {
+        if !fs::exists(mnt_path)? {
+            fs::create_dir_all(mnt_path)?;
+        }
+        Self::open(img_path)?;
+        let options = match self.is_sparse(img_path) {
+            true => "rw,sync,discard",
+            false => "rw,sync",
+        };
+        Cmd::privileged("mount")
+            .args(["-o", options, "--"])
+            .args([Self::device(img_path).as_str(), mnt_path])
+            .run()?;
+        Cmd::privileged("chmod").args(["777", mnt_path]).run()?;
+        match self.is_mounted(img_path, mnt_path) {
+            true => Ok(()),
+            false => Err(NeptisError::InternalError("Failed to mount point!".into())),
+        }
+    }
+
+    fn unmount(&self, img_path: &str, mnt_path: &str) -> Result<(), NeptisError> {
+        if self.is_mounted(img_path, mnt_path) {
+            Cmd::privileged("umount").arg(mnt_path).run()?;
+        }
+        Self::close(img_path)
+    }
+
+    fn grow(&self, img_path: &str, mnt_path: &str, bytes: i64) -> Result<(), NeptisError> {
+        self.unmount(img_path, mnt_path)?;
+        LoopExt4Provisioner::allocate(img_path, bytes)?;
+        Self::open(img_path)?;
+        let device = Self::device(img_path);
+        // The container was reopened over the larger image, so only the filesystem grows.
+        let ret = LoopExt4Provisioner::fsck(device.as_str()).and_then(|_| {
+            Cmd::privileged("resize2fs").arg(device.as_str()).run().map(|_| ())
+        });
+        Self::close(img_path)?;
+        ret
+    }
+
+    fn shrink(&self, img_path: &str, mnt_path: &str, bytes: i64) -> Result<(), NeptisError> {
+        self.unmount(img_path, mnt_path)?;
+        Self::open(img_path)?;
+        let device = Self::device(img_path);
+        // The filesystem has to fit behind the header once the image is cut down.
+        let data_bytes = bytes - Self::HEADER_BYTES;
+        let ret = (|| {
+            let blocks = data_bytes / LoopExt4Provisioner::block_size(device.as_str())? as i64;
+            LoopExt4Provisioner::fsck(device.as_str())?;
+            Cmd::privileged("resize2fs")
+                .arg(device.as_str())
+                .arg(blocks.to_string())
+                .run()?;
+            LoopExt4Provisioner::fsck(device.as_str())
+        })();
+        Self::close(img_path)?;
+        ret?;
+        Cmd::privileged("truncate")
+            .arg("-s")
+            .arg(bytes.to_string())
+            .arg(img_path)
+            .run()?;
+        Ok(())
+    }
+
+    fn destroy(&self, img_path: &str, mnt_path: &str) -> Result<(), NeptisError> {
+        self.unmount(img_path, mnt_path)?;
+        fs::remove_dir(mnt_path)?;
+        fs::remove_file(img_path)?;
+        Ok(())
+    }
+
+    fn usage(&self, _img_path: &str, mnt_path: &str) -> Result<MountStats, NeptisError> {
+        disk_usage(mnt_path)
+    }
+
+    fn host_usage(&self, img_path: &str, _mnt_path: &str) -> Result<usize, NeptisError> {
+        Ok(fs::metadata(img_path)?.blocks() as usize * 512)
+    }
+
+    fn is_sparse(&self, img_path: &str) -> bool {
+        fs::metadata(img_path).is_ok_and(|x| x.blocks() * 512 < x.len())
+    }
+
+    fn limit(&self, img_path: &str) -> Option<u64> {
+        fs::metadata(img_path).ok().map(|x| x.len())
+    }
+
+    fn check_fs(
+        &self,
+        img_path: &str,
+        mnt_path: &str,
+        repair: bool,
+    ) -> Result<CommandOutput, NeptisError> {
+        self.unmount(img_path, mnt_path)?;
+        Self::open(img_path)?;
+        let ret = Cmd::privileged("e2fsck")
+            .args(["-f", if repair { "-y" } else { "-n" }])
+            .arg(Self::device(img_path))
+            .output();
+        Self::close(img_path)?;
+        ret
+    }
+}
+
+/// Each area is a plain directory and `img_path` is a small file holding its limit.
+/// Needs no privileges, but the limit is only enforced where the server checks usage,
+/// not by the filesystem.
+pub struct DirectoryProvisioner;
+
+// Reads the number kept in the small file used as `img_path` by the directory backends.
+fn read_sidecar<T: std::str::FromStr>(img_path: &str) -> Result<T, NeptisError> {
+    fs::read_to_string(img_path)?
+        .trim()
+        .parse::<T>()
+        .ok()
+        .ok_or(NeptisError::InternalError("Point is corrupted".into()))
+}
+
+impl DirectoryProvisioner {
+    // Sum file sizes below `path`, staying on one device so the restic FUSE mount
+    // inside the repository area is not walked.
+    fn tree_size(path: &Path, dev: u64) -> std::io::Result<usize> {
+        let mut total = 0;
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let meta = entry.path().symlink_metadata()?;
+            total += match meta.is_dir() {
+                true if meta.dev() == dev => Self::tree_size(entry.path().as_path(), dev)?,
+                true => 0,
+                false => meta.len() as usize,
+            };
+        }
+        Ok(total)
+    }
+}
+
+impl StorageProvisioner for DirectoryProvisioner {
+    fn image_suffix(&self) -> &'static str {
+        "limit"
+    }
+
+    fn create(&self, img_path: &str, mnt_path: &str, bytes: i64) -> Result<(), NeptisError> {
+        fs::create_dir_all(mnt_path)?;
+        Ok(fs::write(img_path, bytes.to_string())?)
+    }
+
+    fn is_mounted(&self, img_path: &str, mnt_path: &str) -> bool {
+        Path::new(img_path).is_file() && Path::new(mnt_path).is_dir()
+    }
+
+    fn mount(&self, _img_path: &str, mnt_path: &str) -> Result<(), NeptisError> {
+        Ok(fs::create_dir_all(mnt_path)?)
+    }
+
+    fn unmount(&self, _img_path: &str, _mnt_path: &str) -> Result<(), NeptisError> {
+        Ok(())
+    }
+
+    fn grow(&self, img_path: &str, _mnt_path: &str, bytes: i64) -> Result<(), NeptisError> {
+        Ok(fs::write(img_path, bytes.to_string())?)
+    }
+
+    fn shrink(&self, img_path: &str, _mnt_path: &str, bytes: i64) -> Result<(), NeptisError> {
+        Ok(fs::write(img_path, bytes.to_string())?)
+    }
+
+    fn destroy(&self, img_path: &str, mnt_path: &str) -> Result<(), NeptisError> {
+        if fs::exists(mnt_path)? {
+            fs::remove_dir_all(mnt_path)?;
+        }
+        Ok(fs::remove_file(img_path)?)
+    }
+
+    fn usage(&self, img_path: &str, mnt_path: &str) -> Result<MountStats, NeptisError> {
+        let b_total: usize = read_sidecar(img_path)?;
+        let b_used = Self::tree_size(Path::new(mnt_path), fs::metadata(mnt_path)?.dev())?;
+        Ok(MountStats {
+            path: mnt_path.to_string(),
+            b_total,
+            b_used,
+            b_avail: b_total.saturating_sub(b_used),
+        })
+    }
+
+    fn limit(&self, img_path: &str) -> Option<u64> {
+        read_sidecar(img_path).ok()
+    }
+}
+
+/// Each area is a directory on a host filesystem mounted with project quotas (ext4 or
+/// XFS with `prjquota`). The directory is its own project, so the filesystem enforces
+/// the limit and resizing is only a quota change. `img_path` is a small file holding
+/// the project ID. Requires root.
+pub struct ProjectQuotaProvisioner;
+
+impl ProjectQuotaProvisioner {
+    // Inode numbers are unique within a filesystem, as are project IDs, so the
+    // directory's inode serves as its project without keeping a registry.
+    fn new_project_id(mnt_path: &str) -> Result<u32, NeptisError> {
+        u32::try_from(fs::metadata(mnt_path)?.ino()).map_err(|_| {
+            NeptisError::InternalError("Failed to derive a project ID for the point".into())
+        })
+    }
+
+    fn set_limit(img_path: &str, mnt_path: &str, bytes: i64) -> Result<(), NeptisError> {
+        let project_id: u32 = read_sidecar(img_path)?;
+        let fs_root = Cmd::new("findmnt")
+            .args(["-n", "-o", "TARGET", "-T"])
+            .arg(mnt_path)
+            .run()?;
+        // Block limits are given in KiB; zero lifts the limit.
+        let kib = (bytes.max(0) as u64).div_ceil(1024).to_string();
+        Cmd::privileged("setquota")
+            .arg("-P")
+            .arg(project_id.to_string())
+            .args(["0", kib.as_str(), "0", "0"])
+            .arg(fs_root)
+            .run()
+            .map(|_| ())
+    }
+}
+
+impl StorageProvisioner for ProjectQuotaProvisioner {
+    fn image_suffix(&self) -> &'static str {
+        "prj"
+    }
+
+    fn create(&self, img_path: &str, mnt_path: &str, bytes: i64) -> Result<(), NeptisError> {
+        fs::create_dir_all(mnt_path)?;
+        let project_id = Self::new_project_id(mnt_path)?;
+        // `+P` makes everything created below the directory inherit its project.
+        Cmd::privileged("chattr")
+            .args(["+P", "-p"])
+            .arg(project_id.to_string())
+            .arg(mnt_path)
+            .run()?;
+        fs::write(img_path, project_id.to_string())?;
+        Self::set_limit(img_path, mnt_path, bytes)
+    }
+
+    fn is_mounted(&self, img_path: &str, mnt_path: &str) -> bool {
+        Path::new(img_path).is_file() && Path::new(mnt_path).is_dir()
+    }
+
+    // A missing directory cannot simply be recreated, since it would lose its project.
+    fn mount(&self, img_path: &str, mnt_path: &str) -> Result<(), NeptisError> {
+        match self.is_mounted(img_path, mnt_path) {
+            true => Ok(()),
+            false => Err(NeptisError::InternalError("Point is corrupted".into())),
+        }
+    }
+
+    fn unmount(&self, _img_path: &str, _mnt_path: &str) -> Result<(), NeptisError> {
+        Ok(())
+    }
+
+    fn grow(&self, img_path: &str, mnt_path: &str, bytes: i64) -> Result<(), NeptisError> {
+        Self::set_limit(img_path, mnt_path, bytes)
+    }
+
+    fn shrink(&self, img_path: &str, mnt_path: &str, bytes: i64) -> Result<(), NeptisError> {
+        Self::set_limit(img_path, mnt_path, bytes)
+    }
+
+    fn destroy(&self, img_path: &str, mnt_path: &str) -> Result<(), NeptisError> {
+        if fs::exists(mnt_path)? {
+            // The inode, and with it the project ID, may be reused by a later point.
+            Self::set_limit(img_path, mnt_path, 0)?;
+            fs::remove_dir_all(mnt_path)?;
+        }
+        Ok(fs::remove_file(img_path)?)
+    }
+
+    // Both ext4 and XFS report the project limit as the size of a project directory.
+    fn usage(&self, _img_path: &str, mnt_path: &str) -> Result<MountStats, NeptisError> {
+        disk_usage(mnt_path)
+    }
+}
+
+/// Each area is a btrfs subvolume limited through its qgroup, so resizing is only a
+/// quota change. Quotas must have been enabled on the filesystem (`btrfs quota enable`).
+/// `img_path` is a small file holding the limit. Requires root.
+pub struct BtrfsProvisioner;
+
+impl BtrfsProvisioner {
+    fn set_limit(img_path: &str, mnt_path: &str, bytes: i64) -> Result<(), NeptisError> {
+        Cmd::privileged("btrfs")
+            .args(["qgroup", "limit"])
+            .arg(bytes.to_string())
+            .arg(mnt_path)
+            .run()?;
+        Ok(fs::write(img_path, bytes.to_string())?)
+    }
+
+    fn referenced(mnt_path: &str) -> Result<usize, NeptisError> {
+        let res = Cmd::privileged("btrfs")
+            .args(["qgroup", "show", "-f", "--raw"])
+            .arg(mnt_path)
+            .run()?;
+        /*
+           Qgroupid    Referenced    Exclusive
+           --------    ----------    ---------
+           0/257       1073741824        16384
+        */
+        res.lines()
+            .last()
+            .and_then(|x| x.split_whitespace().nth(1))
+            .and_then(|x| x.parse::<usize>().ok())
+            .ok_or(NeptisError::InternalError(format!(
+                "Failed to parse `btrfs qgroup show` output: {}",
+                res
+            )))
+    }
+}
+
+impl StorageProvisioner for BtrfsProvisioner {
+    fn image_suffix(&self) -> &'static str {
+        "qgroup"
+    }
+
+    fn create(&self, img_path: &str, mnt_path: &str, bytes: i64) -> Result<(), NeptisError> {
+        if let Some(parent) = Path::new(mnt_path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Cmd::privileged("btrfs")
+            .args(["subvolume", "create"])
+            .arg(mnt_path)
+            .run()?;
+        Cmd::privileged("chmod").arg("777").arg(mnt_path).run()?;
+        Self::set_limit(img_path, mnt_path, bytes)
+    }
+
+    fn is_mounted(&self, img_path: &str, mnt_path: &str) -> bool {
+        Path::new(img_path).is_file() && Path::new(mnt_path).is_dir()
+    }
+
+    fn mount(&self, img_path: &str, mnt_path: &str) -> Result<(), NeptisError> {
+        match self.is_mounted(img_path, mnt_path) {
+            true => Ok(()),
+            false => Err(NeptisError::InternalError("Point is corrupted".into())),
+        }
+    }
+
+    fn unmount(&self, _img_path: &str, _mnt_path: &str) -> Result<(), NeptisError> {
+        Ok(())
+    }
+
+    fn grow(&self, img_path: &str, mnt_path: &str, bytes: i64) -> Result<(), NeptisError> {
+        Self::set_limit(img_path, mnt_path, bytes)
+    }
+
+    fn shrink(&self, img_path: &str, mnt_path: &str, bytes: i64) -> Result<(), NeptisError> {
+        Self::set_limit(img_path, mnt_path, bytes)
+    }
+
+    fn destroy(&self, img_path: &str, mnt_path: &str) -> Result<(), NeptisError> {
+        if fs::exists(mnt_path)? {
+            Cmd::privileged("btrfs")
+                .args(["subvolume", "delete"])
+                .arg(mnt_path)
+                .run()?;
+        }
+        Ok(fs::remove_file(img_path)?)
+    }
+
+    fn usage(&self, img_path: &str, mnt_path: &str) -> Result<MountStats, NeptisError> {
+        let b_total: usize = read_sidecar(img_path)?;
+        let b_used = Self::referenced(mnt_path)?;
+        Ok(MountStats {
+            path: mnt_path.to_string(),
+            b_total,
+            b_used,
+            b_avail: b_total.saturating_sub(b_used),
+        })
+    }
+
+    fn limit(&self, img_path: &str) -> Option<u64> {
+        read_sidecar(img_path).ok()
+    }
+}
+
+static BACKEND: OnceLock<StorageBackend> = OnceLock::new();
+
+fn backend_named(name: &str) -> Result<StorageBackend, NeptisError> {
+    match name {
+        "loop" => Ok(StorageBackend::LoopExt4),
+        "directory" => Ok(StorageBackend::Directory),
+        "prjquota" => Ok(StorageBackend::ProjectQuota),
+        "btrfs" => Ok(StorageBackend::Btrfs),
+        "luks" => Ok(StorageBackend::LuksExt4),
+        other => Err(NeptisError::InternalError(format!(
+            "Unknown STORAGE_BACKEND `{}`, expected `loop`, `directory`, `prjquota`, `btrfs` or `luks`",
+            other
+        ))),
+    }
+}
+
+/// Reads the backend new points are created with from `STORAGE_BACKEND`: `loop` (the
+/// default), `directory`, `prjquota`, `btrfs` or `luks`. Called once at startup so a bad
+/// value stops the server there.
+pub fn init_backend() -> Result<(), NeptisError> {
+    if BACKEND.get().is_none() {
+        let chosen = backend_named(get_env_or!("STORAGE_BACKEND", String::from("loop")).as_str())?;
+        let _ = BACKEND.set(chosen);
+    }
+    Ok(())
+}
+
+/// The backend set up by `init_backend`.
+pub fn configured_backend() -> StorageBackend {
+    *BACKEND
+        .get()
+        .expect("The storage backend is set up when the server starts")
+}
+
+pub fn provisioner_for(backend: StorageBackend) -> &'static dyn StorageProvisioner {
+    match backend {
+        StorageBackend::LoopExt4 => &LoopExt4Provisioner,
+        StorageBackend::Directory => &DirectoryProvisioner,
+        StorageBackend::ProjectQuota => &ProjectQuotaProvisioner,
+        StorageBackend::Btrfs => &BtrfsProvisioner,
+        StorageBackend::LuksExt4 => &LuksExt4Provisioner,
+    }
+}
+
+/// The backend whose backing paths end in `.{suffix}`.
+pub fn backend_for_suffix(suffix: &str) -> Option<StorageBackend> {
+    [
+        StorageBackend::LoopExt4,
+        StorageBackend::Directory,
+        StorageBackend::ProjectQuota,
+        StorageBackend::Btrfs,
+        StorageBackend::LuksExt4,
+    ]
+    .into_iter()
+    .find(|x| provisioner_for(*x).image_suffix() == suffix)
+}
+
+/// The provisioner an existing point was created with.
+pub fn for_point(point: &Mount) -> &'static dyn StorageProvisioner {
+    provisioner_for(point.backend)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn scratch() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("neptis-storage-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn directory_limits_follow_resizes() {
+        let dir = scratch();
+        let img = dir.join("data.limit");
+        let mnt = dir.join("data");
+        let (img, mnt) = (img.to_str().unwrap(), mnt.to_str().unwrap());
+        let storage = DirectoryProvisioner;
+
+        storage.create(img, mnt, 1000).unwrap();
+        assert!(storage.is_mounted(img, mnt));
+        assert_eq!(storage.usage(img, mnt).unwrap().b_total, 1000);
+
+        storage.grow(img, mnt, 5000).unwrap();
+        assert_eq!(storage.usage(img, mnt).unwrap().b_total, 5000);
+
+        storage.shrink(img, mnt, 2000).unwrap();
+        assert_eq!(storage.usage(img, mnt).unwrap().b_total, 2000);
+
+        storage.destroy(img, mnt).unwrap();
+        assert!(!storage.is_mounted(img, mnt));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn directory_usage_counts_nested_files() {
+        let dir = scratch();
+        let img = dir.join("data.limit");
+        let mnt = dir.join("data");
+        let (img, mnt) = (img.to_str().unwrap(), mnt.to_str().unwrap());
+        let storage = DirectoryProvisioner;
+
+        storage.create(img, mnt, 1000).unwrap();
+        fs::write(dir.join("data/a"), [0u8; 100]).unwrap();
+        fs::create_dir_all(dir.join("data/b/c")).unwrap();
+        fs::write(dir.join("data/b/c/d"), [0u8; 300]).unwrap();
+
+        let stats = storage.usage(img, mnt).unwrap();
+        assert_eq!(stats.b_used, 400);
+        assert_eq!(stats.b_avail, 600);
+
+        // Going over the limit reports no space left rather than underflowing.
+        fs::write(dir.join("data/e"), [0u8; 700]).unwrap();
+        assert_eq!(storage.usage(img, mnt).unwrap().b_avail, 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unknown_backends_are_refused() {
+        assert_eq!(backend_named("loop").unwrap(), StorageBackend::LoopExt4);
+        assert_eq!(backend_named("prjquota").unwrap(), StorageBackend::ProjectQuota);
+        assert!(backend_named("zfs").is_err());
+    }
+}
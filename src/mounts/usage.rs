@@ -0,0 +1,349 @@
+use std::collections::BTreeMap;
+use std::fs;
+
+use chrono::{Duration, NaiveDateTime};
+use rocket_db_pools::diesel::PgPool;
+use serde::{Deserialize, Serialize};
+
+use super::models::*;
+use super::pools;
+use super::provisioning::{self, AllocationDto};
+use super::storage;
+use crate::prelude::action_prelude::*;
+
+/// Days of samples the growth rate is fitted over (`CAPACITY_FORECAST_DAYS`).
+fn forecast_days() -> i64 {
+    get_env_or!("CAPACITY_FORECAST_DAYS", 30)
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AreaCapacityDto {
+    pub allocation: AllocationDto,
+    /// Everything used on the host filesystem, points or not.
+    pub host_used_bytes: i64,
+    /// Growth of `host_used_bytes` fitted over the recorded samples.
+    pub growth_bytes_per_day: Option<f64>,
+    /// Days until the host filesystem is full at that rate; unset while it is not growing.
+    pub days_until_full: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PoolCapacityDto {
+    pub pool_name: String,
+    pub data: AreaCapacityDto,
+    pub repo: AreaCapacityDto,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MountCapacityDto {
+    pub owned_by: String,
+    pub mount_name: String,
+    pub pool_name: Option<String>,
+    pub backend: StorageBackend,
+    pub data_max_bytes: i64,
+    pub repo_max_bytes: i64,
+    /// Host space taken by each area; unset when it could not be measured.
+    pub data_used_bytes: Option<i64>,
+    pub repo_used_bytes: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UserCapacityDto {
+    pub user_name: String,
+    pub mounts: usize,
+    pub data_allocated_bytes: i64,
+    pub repo_allocated_bytes: i64,
+    pub data_used_bytes: i64,
+    pub repo_used_bytes: i64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LoopUsageDto {
+    /// Loop device nodes present on the host.
+    pub total: usize,
+    /// Devices with a backing file attached.
+    pub attached: usize,
+    /// Attached devices backing one of the points.
+    pub attached_to_points: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CapacityReportDto {
+    pub pools: Vec<PoolCapacityDto>,
+    pub mounts: Vec<MountCapacityDto>,
+    pub users: Vec<UserCapacityDto>,
+    pub loop_devices: LoopUsageDto,
+    pub samples: usize,
+}
+
+fn loop_usage(points: &[Mount]) -> LoopUsageDto {
+    let total = fs::read_dir("/dev")
+        .map(|x| {
+            x.filter_map(|e| e.ok())
+                .filter(|e| {
+                    let name = e.file_name().to_string_lossy().to_string();
+                    name.strip_prefix("loop")
+                        .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+                })
+                .count()
+        })
+        .unwrap_or(0);
+    /*
+       /dev/loop0 /srv/data/docs-alice-DATA.img
+    */
+    let backing: Vec<String> = Cmd::new("losetup")
+        .args(["--list", "--noheadings", "--output", "NAME,BACK-FILE"])
+        .run()
+        .map(|x| {
+            x.lines()
+                .filter_map(|l| l.split_once(char::is_whitespace))
+                .map(|(_, f)| f.trim().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    let attached_to_points = backing
+        .iter()
+        .filter(|f| {
+            points
+                .iter()
+                .any(|p| p.data_img_path == **f || p.repo_img_path == **f)
+        })
+        .count();
+    LoopUsageDto {
+        total,
+        attached: backing.len(),
+        attached_to_points,
+    }
+}
+
+fn mount_usage(point: &Mount) -> MountCapacityDto {
+    let storage = storage::for_point(point);
+    MountCapacityDto {
+        owned_by: point.owned_by.clone(),
+        mount_name: point.mount_name.clone(),
+        pool_name: point.pool_name.clone(),
+        backend: point.backend,
+        data_max_bytes: point.data_max_bytes,
+        repo_max_bytes: point.repo_max_bytes,
+        data_used_bytes: storage
+            .host_usage(&point.data_img_path, &point.data_mnt_path)
+            .ok()
+            .map(|x| x as i64),
+        repo_used_bytes: storage
+            .host_usage(&point.repo_img_path, &point.repo_mnt_path)
+            .ok()
+            .map(|x| x as i64),
+    }
+}
+
+fn per_user(mounts: &[MountCapacityDto]) -> Vec<UserCapacityDto> {
+    let mut output: BTreeMap<String, UserCapacityDto> = BTreeMap::new();
+    for x in mounts {
+        let item = output
+            .entry(x.owned_by.clone())
+            .or_insert_with(|| UserCapacityDto {
+                user_name: x.owned_by.clone(),
+                mounts: 0,
+                data_allocated_bytes: 0,
+                repo_allocated_bytes: 0,
+                data_used_bytes: 0,
+                repo_used_bytes: 0,
+            });
+        item.mounts += 1;
+        item.data_allocated_bytes += x.data_max_bytes;
+        item.repo_allocated_bytes += x.repo_max_bytes;
+        item.data_used_bytes += x.data_used_bytes.unwrap_or(0);
+        item.repo_used_bytes += x.repo_used_bytes.unwrap_or(0);
+    }
+    output.into_values().collect()
+}
+
+/// Least-squares slope of `points`, in bytes per day. Needs at least two samples
+/// spread over some time.
+fn growth_per_day(points: &[(NaiveDateTime, i64)]) -> Option<f64> {
+    let first = points.first()?.0;
+    let xy: Vec<(f64, f64)> = points
+        .iter()
+        .map(|(t, v)| ((*t - first).num_seconds() as f64 / 86400.0, *v as f64))
+        .collect();
+    let n = xy.len() as f64;
+    let mean_x = xy.iter().map(|x| x.0).sum::<f64>() / n;
+    let mean_y = xy.iter().map(|x| x.1).sum::<f64>() / n;
+    let var_x = xy.iter().map(|x| (x.0 - mean_x).powi(2)).sum::<f64>();
+    if xy.len() < 2 || var_x <= 0.0 {
+        return None;
+    }
+    let cov = xy
+        .iter()
+        .map(|x| (x.0 - mean_x) * (x.1 - mean_y))
+        .sum::<f64>();
+    Some(cov / var_x)
+}
+
+fn area_capacity(
+    path: &str,
+    points: &[Mount],
+    is_data: bool,
+    samples: &[CapacitySample],
+) -> Result<AreaCapacityDto, NeptisError> {
+    let host = storage::disk_usage(path)?;
+    let growth = growth_per_day(
+        &samples
+            .iter()
+            .map(|x| match is_data {
+                true => (x.sample_date, x.data_used_bytes),
+                false => (x.sample_date, x.repo_used_bytes),
+            })
+            .collect::<Vec<_>>(),
+    );
+    Ok(AreaCapacityDto {
+        allocation: provisioning::allocation_for(path, points, is_data)?,
+        host_used_bytes: host.b_used as i64,
+        growth_bytes_per_day: growth,
+        days_until_full: growth
+            .filter(|x| *x > 0.0)
+            .map(|x| host.b_avail as f64 / x),
+    })
+}
+
+/// Records how much of the paths of every storage pool is in use, dropping samples too
+/// old to take part in the forecast.
+pub async fn record_sample(conn: &mut AsyncPgConnection) -> Result<(), NeptisError> {
+    let all_pools: Vec<StoragePool> = {
+        use crate::schema::storage_pools::dsl::*;
+        storage_pools.get_results(conn).await?
+    };
+    use crate::schema::capacity_samples::dsl::*;
+
+    let now = utc_now!();
+    let samples = rocket::tokio::task::spawn_blocking(move || {
+        all_pools
+            .into_iter()
+            .map(|x| {
+                Ok(CapacitySample {
+                    id: Uuid::new_v4(),
+                    sample_date: now,
+                    data_used_bytes: storage::disk_usage(x.data_path.as_str())?.b_used as i64,
+                    repo_used_bytes: storage::disk_usage(x.repo_path.as_str())?.b_used as i64,
+                    pool_name: Some(x.pool_name),
+                })
+            })
+            .collect::<Result<Vec<_>, NeptisError>>()
+    })
+    .await
+    .map_err(|e| NeptisError::InternalError(e.to_string()))??;
+    diesel::insert_into(capacity_samples)
+        .values(&samples)
+        .execute(conn)
+        .await?;
+    diesel::delete(capacity_samples)
+        .filter(sample_date.lt(now - Duration::days(forecast_days())))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Host totals, allocations and usage for the paths of every storage pool, per point and
+/// per user, along with a forecast of when each path fills up.
+pub async fn capacity_report(
+    conn: &mut AsyncPgConnection,
+) -> Result<CapacityReportDto, NeptisError> {
+    let points: Vec<Mount> = {
+        use crate::schema::mounts::dsl::*;
+        mounts.order((owned_by.asc(), mount_name.asc())).get_results(conn).await?
+    };
+    let all_pools: Vec<StoragePool> = {
+        use crate::schema::storage_pools::dsl::*;
+        storage_pools.order(pool_name.asc()).get_results(conn).await?
+    };
+    let samples: Vec<CapacitySample> = {
+        use crate::schema::capacity_samples::dsl::*;
+        capacity_samples
+            .filter(sample_date.ge(utc_now!() - Duration::days(forecast_days())))
+            .order(sample_date.asc())
+            .get_results(conn)
+            .await?
+    };
+
+    rocket::tokio::task::spawn_blocking(move || {
+        let mounts: Vec<MountCapacityDto> = points.iter().map(mount_usage).collect();
+        let pools = all_pools
+            .iter()
+            .map(|x| {
+                let in_pool = pools::points_in(x, &points);
+                let in_samples: Vec<CapacitySample> = samples
+                    .iter()
+                    .filter(|s| s.pool_name.as_deref() == Some(x.pool_name.as_str()))
+                    .cloned()
+                    .collect();
+                Ok(PoolCapacityDto {
+                    pool_name: x.pool_name.clone(),
+                    data: area_capacity(x.data_path.as_str(), &in_pool, true, &in_samples)?,
+                    repo: area_capacity(x.repo_path.as_str(), &in_pool, false, &in_samples)?,
+                })
+            })
+            .collect::<Result<Vec<_>, NeptisError>>()?;
+        Ok(CapacityReportDto {
+            pools,
+            users: per_user(&mounts),
+            mounts,
+            loop_devices: loop_usage(&points),
+            samples: samples.len(),
+        })
+    })
+    .await
+    .map_err(|e| NeptisError::InternalError(e.to_string()))?
+}
+
+/// Spawns the background task which records capacity samples for the forecast.
+pub fn spawn_capacity_sampler(pool: PgPool) {
+    let interval_secs: u64 = get_env_or!("CAPACITY_SAMPLE_INTERVAL", 3600);
+    if interval_secs == 0 {
+        return;
+    }
+    rocket::tokio::spawn(async move {
+        let mut timer =
+            rocket::tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+        loop {
+            timer.tick().await;
+            let Ok(mut conn) = pool.get().await else {
+                continue;
+            };
+            if let Err(e) = record_sample(&mut conn).await {
+                println!("Failed to record a capacity sample: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(day: i64, bytes: i64) -> (NaiveDateTime, i64) {
+        let start = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap().naive_utc();
+        (start + Duration::days(day), bytes)
+    }
+
+    #[test]
+    fn growth_follows_a_steady_trend() {
+        let points = [at(0, 1000), at(1, 1100), at(2, 1200), at(3, 1300)];
+        let growth = growth_per_day(&points).unwrap();
+        assert!((growth - 100.0).abs() < 1e-6, "{}", growth);
+    }
+
+    #[test]
+    fn growth_fits_noisy_and_shrinking_samples() {
+        let growth = growth_per_day(&[at(0, 0), at(1, 150), at(2, 100), at(3, 300)]).unwrap();
+        assert!((growth - 85.0).abs() < 1e-6, "{}", growth);
+        let growth = growth_per_day(&[at(0, 500), at(5, 0)]).unwrap();
+        assert!((growth + 100.0).abs() < 1e-6, "{}", growth);
+    }
+
+    #[test]
+    fn growth_needs_samples_spread_over_time() {
+        assert_eq!(growth_per_day(&[]), None);
+        assert_eq!(growth_per_day(&[at(0, 10)]), None);
+        assert_eq!(growth_per_day(&[at(2, 10), at(2, 50)]), None);
+    }
+}
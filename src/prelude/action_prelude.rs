@@ -1,4 +1,5 @@
-pub use crate::{Db, get_env, utc_now, cmd};
+pub use crate::{Db, get_env, get_env_or, utc_now};
+pub use crate::api::command::Cmd;
 pub use crate::api::errors::*;
 pub use chrono::Utc;
 pub use rocket::Request;
@@ -7,7 +7,8 @@ table! {
         create_date -> Timestamp,
         is_admin -> Bool,
         max_data_bytes -> BigInt,
-        max_snapshot_bytes -> BigInt
+        max_snapshot_bytes -> BigInt,
+        default_pool -> Nullable<Text>
     }
 }
 table! {
@@ -33,7 +34,14 @@ table! {
         date_created -> Timestamp,
         data_accessed -> Timestamp,
         repo_accessed -> Timestamp,
-        locked -> Bool
+        locked -> Bool,
+        rpo_hours -> Nullable<Integer>,
+        is_stale -> Bool,
+        backend -> SmallInt,
+        fsck_hours -> Nullable<Integer>,
+        health -> Nullable<SmallInt>,
+        health_checked -> Nullable<Timestamp>,
+        pool_name -> Nullable<Text>
     }
 }
 table! {
@@ -48,6 +56,95 @@ table! {
         total_bytes -> Nullable<BigInt>,
         errors -> Array<Text>,
         create_date -> Timestamp,
-        end_date -> Nullable<Timestamp>
+        end_date -> Nullable<Timestamp>,
+        acted_by -> Nullable<Text>,
+        target_path -> Nullable<Text>,
+        launch_dry_run -> Bool,
+        launch_read_data -> Bool,
+        launch_tags -> Array<Text>,
+        logs -> Array<Text>
     }
-}
\ No newline at end of file
+}
+table! {
+    mount_hooks(id) {
+        id -> Uuid,
+        owned_by -> Text,
+        mount_name -> Text,
+        hook_type -> SmallInt,
+        command -> Array<Text>,
+        timeout_secs -> Integer,
+        enabled -> Bool,
+        create_date -> Timestamp
+    }
+}
+table! {
+    mount_locks(id) {
+        id -> Uuid,
+        owned_by -> Text,
+        mount_name -> Text,
+        is_exclusive -> Bool,
+        reason -> Text,
+        acquired_date -> Timestamp,
+        expire_date -> Timestamp,
+        job_id -> Nullable<Uuid>
+    }
+}
+table! {
+    webhooks(id) {
+        id -> Uuid,
+        owned_by -> Text,
+        url -> Text,
+        secret -> Text,
+        events -> Array<Text>,
+        is_global -> Bool,
+        enabled -> Bool,
+        create_date -> Timestamp
+    }
+}
+table! {
+    webhook_deliveries(id) {
+        id -> Uuid,
+        webhook_id -> Uuid,
+        event -> Text,
+        payload -> Text,
+        attempts -> Integer,
+        delivered -> Bool,
+        status_code -> Nullable<Integer>,
+        last_error -> Nullable<Text>,
+        create_date -> Timestamp,
+        last_attempt -> Nullable<Timestamp>
+    }
+}
+table! {
+    quota_reservations(id) {
+        id -> Uuid,
+        user_name -> Text,
+        mount_name -> Text,
+        data_bytes -> BigInt,
+        repo_bytes -> BigInt,
+        reason -> Text,
+        create_date -> Timestamp,
+        expire_date -> Timestamp
+    }
+}
+table! {
+    capacity_samples(id) {
+        id -> Uuid,
+        sample_date -> Timestamp,
+        data_used_bytes -> BigInt,
+        repo_used_bytes -> BigInt,
+        pool_name -> Nullable<Text>
+    }
+}
+table! {
+    storage_pools(pool_name) {
+        pool_name -> Text,
+        data_path -> Text,
+        repo_path -> Text,
+        capacity_bytes -> BigInt,
+        tags -> Array<Text>,
+        create_date -> Timestamp
+    }
+}
+joinable!(webhook_deliveries -> webhooks (webhook_id));
+allow_tables_to_appear_in_same_query!(webhooks, webhook_deliveries);
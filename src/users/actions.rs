@@ -5,6 +5,7 @@ use rocket::serde::json::Value;
 
 use super::dtos::*;
 use super::models::*;
+use super::quota::{self, QuotaDto};
 use crate::api::traits::CleanValidate;
 use crate::api::util::{decrypt, encrypt};
 use crate::{api::traits::WebDtoFrom, prelude::action_prelude::*};
@@ -36,6 +37,14 @@ pub async fn get_one_user(name: &str) -> Result<UserDto, NeptisError> {
     Ok(users.find(name).get_result(conn).await?)
 }
 
+#[action]
+pub async fn get_quota(name: &str) -> Result<QuotaDto, NeptisError> {
+    if !auth_user.is_admin && auth_user.user_name != name {
+        return Err(NeptisError::Unauthorized("You do not have access!".into()));
+    }
+    quota::quota_report(conn, name).await
+}
+
 #[action(Vec<User>)]
 pub async fn get_all_users() -> Result<Vec<UserDto>, NeptisError> {
     use crate::schema::users::dsl::*;
@@ -23,6 +23,7 @@ impl WebDtoFrom<User> for UserDto {
             } else {
                 None
             },
+            default_pool: item.default_pool,
         })
     }
 }
@@ -36,7 +37,8 @@ pub struct UserDto {
     pub create_date: NaiveDateTime,
     pub is_admin: bool,
     pub max_data_bytes: Option<i64>, // depends on privledge
-    pub max_snapshot_bytes: Option<i64> // depends on privledge
+    pub max_snapshot_bytes: Option<i64>, // depends on privledge
+    pub default_pool: Option<String>
 }
 
 #[derive(Serialize, Deserialize)]
@@ -48,6 +50,7 @@ pub struct UserForCreateApi {
     pub is_admin: bool,
     pub max_data_bytes: Option<i64>,
     pub max_snapshot_bytes: Option<i64>,
+    pub default_pool: Option<String>,
 }
 
 impl From<UserForCreateApi> for User {
@@ -61,6 +64,7 @@ impl From<UserForCreateApi> for User {
             is_admin: value.is_admin,
             max_data_bytes: value.max_data_bytes.unwrap_or(0),
             max_snapshot_bytes: value.max_snapshot_bytes.unwrap_or(0),
+            default_pool: value.default_pool,
         }
     }
 }
@@ -72,7 +76,9 @@ pub struct UserForUpdateApi {
     pub is_admin: Option<bool>,
     pub max_data_bytes: Option<i64>,
     pub max_snapshot_bytes: Option<i64>,
-    pub password: Option<String>
+    pub password: Option<String>,
+    /// An empty name clears the default pool.
+    pub default_pool: Option<String>
 }
 
 impl UserForUpdateApi {
@@ -84,7 +90,11 @@ impl UserForUpdateApi {
             is_admin: self.is_admin,
             max_data_bytes: self.max_data_bytes.clone(),
             max_snapshot_bytes: self.max_snapshot_bytes.clone(),
-            password_hash: self.password.clone().map(|x|EncodedHash::hash(x))
+            password_hash: self.password.clone().map(|x|EncodedHash::hash(x)),
+            default_pool: self
+                .default_pool
+                .clone()
+                .map(|x| Some(x.trim().to_string()).filter(|x| !x.is_empty()))
         }
     }
 }
@@ -1,6 +1,6 @@
 use rocket::serde::json::Value;
 
-use super::{actions, dtos::*};
+use super::{actions, dtos::*, quota::QuotaDto};
 use crate::prelude::route_prelude::*;
 
 #[get("/")]
@@ -24,6 +24,17 @@ async fn get_one_user(
     ))
 }
 
+#[get("/<name>/quota")]
+async fn get_one_quota(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    name: &str,
+) -> Result<Json<QuotaDto>, NeptisError> {
+    Ok(Json(
+        actions::get_quota_async(&mut conn, &auth_user, name).await?,
+    ))
+}
+
 #[put("/<name>", data = "<dto>")]
 async fn put_one_user(
     mut conn: Connection<Db>,
@@ -71,6 +82,7 @@ pub fn get_routes() -> Vec<Route> {
     routes![
         get_all_users,
         get_one_user,
+        get_one_quota,
         put_one_user,
         create_one_user,
         do_auth,
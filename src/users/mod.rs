@@ -1,4 +1,5 @@
 pub mod actions;
 pub mod handlers;
 pub mod models;
-pub mod dtos;
\ No newline at end of file
+pub mod dtos;
+pub mod quota;
@@ -10,6 +10,8 @@ pub struct User {
     pub is_admin: bool,
     pub max_data_bytes: i64,
     pub max_snapshot_bytes: i64,
+    /// Pool new points are placed in unless another one is asked for.
+    pub default_pool: Option<String>,
 }
 
 impl CleanValidate for User {
@@ -32,7 +34,22 @@ pub struct UserForUpdate {
     pub is_admin: Option<bool>,
     pub max_data_bytes: Option<i64>,
     pub max_snapshot_bytes: Option<i64>,
-    pub password_hash: Option<EncodedHash>
+    pub password_hash: Option<EncodedHash>,
+    pub default_pool: Option<Option<String>>
+}
+
+/// Space set aside for a point being created or grown, counted against the user's
+/// limits until the point itself records it.
+#[derive(Insertable, Queryable, Clone)]
+pub struct QuotaReservation {
+    pub id: Uuid,
+    pub user_name: String,
+    pub mount_name: String,
+    pub data_bytes: i64,
+    pub repo_bytes: i64,
+    pub reason: String,
+    pub create_date: NaiveDateTime,
+    pub expire_date: NaiveDateTime,
 }
 
 #[derive(Insertable, Queryable)]
@@ -0,0 +1,191 @@
+use chrono::Duration;
+use diesel_async::AsyncConnection;
+use diesel_async::scoped_futures::ScopedFutureExt;
+use serde::{Deserialize, Serialize};
+
+use super::models::*;
+use crate::mounts::models::Mount;
+use crate::mounts::storage;
+use crate::prelude::action_prelude::*;
+
+/// Seconds before a reservation is dropped, such as when the server died while
+/// provisioning the point it was taken for.
+fn reservation_ttl() -> Duration {
+    Duration::seconds(get_env_or!("QUOTA_RESERVATION_TTL", 3600))
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct QuotaDto {
+    pub user_name: String,
+    pub data_limit_bytes: i64,
+    pub repo_limit_bytes: i64,
+    /// Sum of the limits of the user's points.
+    pub data_allocated_bytes: i64,
+    pub repo_allocated_bytes: i64,
+    /// Space held for points still being created or grown.
+    pub data_reserved_bytes: i64,
+    pub repo_reserved_bytes: i64,
+    /// Space actually taken inside the points which are currently mounted.
+    pub data_used_bytes: i64,
+    pub repo_used_bytes: i64,
+}
+
+async fn live_reservations(
+    conn: &mut AsyncPgConnection,
+    p_user: &str,
+) -> Result<Vec<QuotaReservation>, NeptisError> {
+    use crate::schema::quota_reservations::dsl::*;
+    Ok(quota_reservations
+        .filter(user_name.eq(p_user).and(expire_date.gt(utc_now!())))
+        .get_results(conn)
+        .await?)
+}
+
+fn allocated(points: &[Mount]) -> (i64, i64) {
+    points.iter().fold((0, 0), |(d, r), x| {
+        (d + x.data_max_bytes.max(0), r + x.repo_max_bytes.max(0))
+    })
+}
+
+fn reserved(items: &[QuotaReservation]) -> (i64, i64) {
+    items
+        .iter()
+        .fold((0, 0), |(d, r), x| (d + x.data_bytes, r + x.repo_bytes))
+}
+
+/// Sets aside `data_bytes` and `repo_bytes` on top of everything `p_user` already holds,
+/// before any disk work starts for `p_name`. The user row is locked while the totals are
+/// read, so concurrent requests cannot both pass on the same free space. The reservation
+/// must be released once the point records the space, or when provisioning fails.
+pub async fn reserve(
+    conn: &mut AsyncPgConnection,
+    p_user: &str,
+    p_name: &str,
+    data_bytes: i64,
+    repo_bytes: i64,
+    p_reason: &str,
+) -> Result<Uuid, NeptisError> {
+    let p_user = p_user.to_string();
+    let p_name = p_name.to_string();
+    let p_reason = p_reason.to_string();
+    // Taken before the dsl import below, whose columns share these names.
+    let (d_bytes, r_bytes) = (data_bytes.max(0), repo_bytes.max(0));
+
+    conn.transaction::<_, NeptisError, _>(|conn| {
+        async move {
+            let owner: User = {
+                use crate::schema::users::dsl::*;
+                users.find(p_user.as_str()).for_update().get_result(conn).await?
+            };
+            let points: Vec<Mount> = {
+                use crate::schema::mounts::dsl::*;
+                mounts
+                    .filter(owned_by.eq(p_user.as_str()))
+                    .get_results(conn)
+                    .await?
+            };
+
+            use crate::schema::quota_reservations::dsl::*;
+            let now = utc_now!();
+            diesel::delete(quota_reservations)
+                .filter(user_name.eq(p_user.as_str()).and(expire_date.le(now)))
+                .execute(conn)
+                .await?;
+            let held: Vec<QuotaReservation> = quota_reservations
+                .filter(user_name.eq(p_user.as_str()))
+                .get_results(conn)
+                .await?;
+
+            let (d_alloc, r_alloc) = allocated(&points);
+            let (d_res, r_res) = reserved(&held);
+            if d_alloc + d_res + d_bytes > owner.max_data_bytes
+                || r_alloc + r_res + r_bytes > owner.max_snapshot_bytes
+            {
+                return Err(NeptisError::BadRequest(
+                    "Not enough space on your account!".into(),
+                ));
+            }
+
+            let n_item = QuotaReservation {
+                id: Uuid::new_v4(),
+                user_name: p_user,
+                mount_name: p_name,
+                data_bytes: d_bytes,
+                repo_bytes: r_bytes,
+                reason: p_reason,
+                create_date: now,
+                expire_date: now + reservation_ttl(),
+            };
+            diesel::insert_into(quota_reservations)
+                .values(&n_item)
+                .execute(conn)
+                .await?;
+            Ok(n_item.id)
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+/// Drops a reservation, whether its point now records the space or provisioning failed.
+pub async fn release(conn: &mut AsyncPgConnection, r_id: Uuid) -> Result<(), NeptisError> {
+    use crate::schema::quota_reservations::dsl::*;
+    diesel::delete(quota_reservations.find(r_id))
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Reports the limits of `p_user` against what is allocated, reserved and used.
+pub async fn quota_report(
+    conn: &mut AsyncPgConnection,
+    p_user: &str,
+) -> Result<QuotaDto, NeptisError> {
+    let owner: User = {
+        use crate::schema::users::dsl::*;
+        users.find(p_user).get_result(conn).await?
+    };
+    let points: Vec<Mount> = {
+        use crate::schema::mounts::dsl::*;
+        mounts.filter(owned_by.eq(p_user)).get_results(conn).await?
+    };
+    let held = live_reservations(conn, p_user).await?;
+
+    // Measuring must not remount the points unmounted for being idle.
+    let (d_used, r_used) = rocket::tokio::task::spawn_blocking({
+        let points = points.clone();
+        move || {
+            let mut output = (0i64, 0i64);
+            for x in points.iter().filter(|x| !x.locked) {
+                let storage = storage::for_point(x);
+                if storage.is_mounted(&x.data_img_path, &x.data_mnt_path)
+                    && let Ok(s) = storage.usage(&x.data_img_path, &x.data_mnt_path)
+                {
+                    output.0 += s.b_used as i64;
+                }
+                if storage.is_mounted(&x.repo_img_path, &x.repo_mnt_path)
+                    && let Ok(s) = storage.usage(&x.repo_img_path, &x.repo_mnt_path)
+                {
+                    output.1 += s.b_used as i64;
+                }
+            }
+            output
+        }
+    })
+    .await
+    .map_err(|e| NeptisError::InternalError(e.to_string()))?;
+
+    let (d_alloc, r_alloc) = allocated(&points);
+    let (d_res, r_res) = reserved(&held);
+    Ok(QuotaDto {
+        user_name: owner.user_name,
+        data_limit_bytes: owner.max_data_bytes,
+        repo_limit_bytes: owner.max_snapshot_bytes,
+        data_allocated_bytes: d_alloc,
+        repo_allocated_bytes: r_alloc,
+        data_reserved_bytes: d_res,
+        repo_reserved_bytes: r_res,
+        data_used_bytes: d_used,
+        repo_used_bytes: r_used,
+    })
+}
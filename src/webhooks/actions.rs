@@ -0,0 +1,116 @@
+use passwords::PasswordGenerator;
+use rocket::serde::json::serde_json::json;
+
+use super::dispatch;
+use super::dtos::*;
+use super::models::*;
+use crate::api::traits::CleanValidate;
+use crate::prelude::action_prelude::*;
+
+async fn find_own_hook(
+    conn: &mut AsyncPgConnection,
+    auth_user: &User,
+    hook_id: Uuid,
+) -> Result<Webhook, NeptisError> {
+    use crate::schema::webhooks::dsl::*;
+    let hook: Webhook = webhooks
+        .find(hook_id)
+        .get_result(conn)
+        .await
+        .ok()
+        .ok_or(NeptisError::BadRequest("The webhook does not exist!".into()))?;
+    if !auth_user.is_admin && hook.owned_by != auth_user.user_name {
+        return Err(NeptisError::Unauthorized("You do not have access!".into()));
+    }
+    Ok(hook)
+}
+
+#[action(Vec<Webhook>)]
+pub async fn get_all_webhooks() -> Result<Vec<WebhookDto>, NeptisError> {
+    use crate::schema::webhooks::dsl::*;
+    Ok(webhooks
+        .filter(owned_by.eq(auth_user.user_name.as_str()))
+        .order(create_date.desc())
+        .get_results(conn)
+        .await?)
+}
+
+#[action(Webhook)]
+pub async fn create_webhook(dto: PostForWebhookApi) -> Result<WebhookDto, NeptisError> {
+    use crate::schema::webhooks::dsl::*;
+
+    let global = dto.global.unwrap_or(false);
+    if global && !auth_user.is_admin {
+        return Err(NeptisError::Unauthorized(
+            "Only admins can create global webhooks!".into(),
+        ));
+    }
+    let hook = Webhook {
+        id: Uuid::new_v4(),
+        owned_by: auth_user.user_name.clone(),
+        url: dto.url,
+        secret: dto.secret.unwrap_or_else(|| {
+            PasswordGenerator::new()
+                .length(32)
+                .numbers(true)
+                .lowercase_letters(true)
+                .uppercase_letters(true)
+                .symbols(false)
+                .spaces(false)
+                .strict(true)
+                .generate_one()
+                .unwrap()
+        }),
+        events: dto
+            .events
+            .unwrap_or_default()
+            .iter()
+            .map(|x| x.as_str().to_string())
+            .collect(),
+        is_global: global,
+        enabled: dto.enabled.unwrap_or(true),
+        create_date: utc_now!(),
+    };
+    Ok(diesel::insert_into(webhooks)
+        .values(hook.validate()?)
+        .get_result(conn)
+        .await?)
+}
+
+#[action]
+pub async fn delete_webhook(hook_id: Uuid) -> Result<usize, NeptisError> {
+    use crate::schema::webhooks::dsl::*;
+    let hook = find_own_hook(conn, auth_user, hook_id).await?;
+    Ok(diesel::delete(webhooks.find(hook.id)).execute(conn).await?)
+}
+
+#[action(Vec<WebhookDelivery>)]
+pub async fn get_deliveries(
+    hook_id: Uuid,
+    d_limit: Option<i64>,
+) -> Result<Vec<WebhookDeliveryDto>, NeptisError> {
+    use crate::schema::webhook_deliveries::dsl::*;
+    let hook = find_own_hook(conn, auth_user, hook_id).await?;
+    Ok(webhook_deliveries
+        .filter(webhook_id.eq(hook.id))
+        .order(create_date.desc())
+        .limit(d_limit.unwrap_or(100))
+        .get_results(conn)
+        .await?)
+}
+
+/// Sends a `ping` to the webhook right away and reports how the receiver answered.
+/// Failed pings are not retried.
+#[action(WebhookDelivery)]
+pub async fn test_webhook(hook_id: Uuid) -> Result<WebhookDeliveryDto, NeptisError> {
+    let hook = find_own_hook(conn, auth_user, hook_id).await?;
+    let delivery = dispatch::create_delivery(
+        conn,
+        &hook,
+        hook.owned_by.as_str(),
+        WebhookEvent::Ping,
+        json!({ "requested_by": auth_user.user_name }),
+    )
+    .await?;
+    dispatch::attempt_delivery(conn, &dispatch::http_client(), &hook, delivery).await
+}
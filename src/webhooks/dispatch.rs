@@ -0,0 +1,420 @@
+use std::sync::{Arc, OnceLock};
+
+use chrono::Duration;
+use hmac::{Hmac, Mac};
+use rocket::serde::json::{Value, serde_json, serde_json::json};
+use rocket::tokio::sync::mpsc;
+use rocket_db_pools::diesel::PgPool;
+use sha2::Sha256;
+
+use super::models::*;
+use super::targets;
+use crate::mounts::models::{JobStatus, RepoJob};
+use crate::prelude::action_prelude::*;
+
+struct Notice {
+    owner: String,
+    event: WebhookEvent,
+    data: Value,
+}
+
+static QUEUE: OnceLock<mpsc::UnboundedSender<Notice>> = OnceLock::new();
+
+/// Delivery attempts before a delivery is given up on.
+fn max_attempts() -> i32 {
+    get_env_or!("WEBHOOK_MAX_ATTEMPTS", 5)
+}
+
+/// Seconds before the first retry; each further retry waits twice as long.
+fn backoff_secs() -> u64 {
+    get_env_or!("WEBHOOK_BACKOFF_SECS", 10)
+}
+
+/// Queues an event for every webhook of `owner`, plus every global webhook. This never
+/// blocks, so it is safe to call from job threads. Events raised before the dispatcher
+/// starts are dropped.
+pub fn emit(owner: &str, event: WebhookEvent, data: Value) {
+    if let Some(tx) = QUEUE.get() {
+        let _ = tx.send(Notice {
+            owner: owner.to_string(),
+            event,
+            data,
+        });
+    }
+}
+
+/// Raises the event matching a job's current status, if any.
+pub fn emit_job(job: &RepoJob) {
+    let event = match job.job_status {
+        JobStatus::Running => WebhookEvent::JobStarted,
+        JobStatus::Successful => WebhookEvent::JobSucceeded,
+        JobStatus::Failed => WebhookEvent::JobFailed,
+        _ => return,
+    };
+    emit(
+        job.point_owned_by.as_str(),
+        event,
+        json!({
+            "job_id": job.id,
+            "job_type": job.job_type,
+            "job_status": job.job_status,
+            "mount": job.point_name,
+            "snapshot_id": job.snapshot_id,
+            "acted_by": job.acted_by,
+            "errors": job.errors,
+        }),
+    );
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body.as_bytes());
+    format!("sha256={}", to_hex(&mac.finalize().into_bytes()))
+}
+
+/// Records a new delivery for a webhook without sending it.
+pub async fn create_delivery(
+    conn: &mut AsyncPgConnection,
+    hook: &Webhook,
+    owner: &str,
+    event: WebhookEvent,
+    data: Value,
+) -> Result<WebhookDelivery, NeptisError> {
+    use crate::schema::webhook_deliveries::dsl as d;
+
+    let d_id = Uuid::new_v4();
+    let now = utc_now!();
+    let body = json!({
+        "id": d_id,
+        "event": event.as_str(),
+        "owner": owner,
+        "timestamp": now,
+        "data": data,
+    });
+    let delivery = WebhookDelivery {
+        id: d_id,
+        webhook_id: hook.id,
+        event: event.as_str().to_string(),
+        payload: serde_json::to_string(&body).unwrap_or_default(),
+        attempts: 0,
+        delivered: false,
+        status_code: None,
+        last_error: None,
+        create_date: now,
+        last_attempt: None,
+    };
+    Ok(diesel::insert_into(d::webhook_deliveries)
+        .values(&delivery)
+        .get_result(conn)
+        .await?)
+}
+
+// Sends a delivery once, noting the outcome on it without storing anything.
+async fn send_delivery(client: &reqwest::Client, hook: &Webhook, delivery: &mut WebhookDelivery) {
+    // Literal addresses never reach the resolver, so they are checked again here.
+    let ret = match targets::check_url(hook.url.as_str()) {
+        Err(e) => Err(e.to_string()),
+        Ok(_) => client
+            .post(hook.url.as_str())
+            .header("Content-Type", "application/json")
+            .header("User-Agent", "neptis-webhooks")
+            .header("X-Neptis-Event", delivery.event.as_str())
+            .header("X-Neptis-Delivery", delivery.id.to_string())
+            .header("X-Neptis-Signature", sign(hook.secret.as_str(), delivery.payload.as_str()))
+            .body(delivery.payload.clone())
+            .send()
+            .await
+            .map_err(|e| e.to_string()),
+    };
+
+    delivery.attempts += 1;
+    delivery.last_attempt = Some(utc_now!());
+    match ret {
+        Ok(res) => {
+            delivery.status_code = Some(res.status().as_u16() as i32);
+            delivery.delivered = res.status().is_success();
+            delivery.last_error = (!delivery.delivered)
+                .then(|| format!("Receiver responded with {}", res.status()));
+        }
+        Err(e) => {
+            delivery.status_code = None;
+            delivery.last_error = Some(e);
+        }
+    }
+}
+
+async fn store_delivery(
+    conn: &mut AsyncPgConnection,
+    delivery: &WebhookDelivery,
+) -> Result<WebhookDelivery, NeptisError> {
+    use crate::schema::webhook_deliveries::dsl::*;
+
+    Ok(diesel::update(webhook_deliveries.find(delivery.id))
+        .set(delivery)
+        .get_result(conn)
+        .await?)
+}
+
+/// Makes a single attempt at a delivery and records the outcome.
+pub async fn attempt_delivery(
+    conn: &mut AsyncPgConnection,
+    client: &reqwest::Client,
+    hook: &Webhook,
+    mut delivery: WebhookDelivery,
+) -> Result<WebhookDelivery, NeptisError> {
+    send_delivery(client, hook, &mut delivery).await;
+    store_delivery(conn, &delivery).await
+}
+
+// Keep attempting a delivery with exponential backoff until it lands or runs out of attempts.
+// Every attempt goes through `record`, which hands back the delivery as stored.
+async fn retry_delivery<F, Fut>(
+    client: &reqwest::Client,
+    hook: &Webhook,
+    mut delivery: WebhookDelivery,
+    backoff: u64,
+    mut record: F,
+) -> Result<WebhookDelivery, NeptisError>
+where
+    F: FnMut(WebhookDelivery) -> Fut,
+    Fut: Future<Output = Result<WebhookDelivery, NeptisError>>,
+{
+    while !delivery.delivered && delivery.attempts < max_attempts() {
+        if delivery.attempts > 0 {
+            let wait = backoff.saturating_mul(1u64 << (delivery.attempts - 1).min(16));
+            rocket::tokio::time::sleep(std::time::Duration::from_secs(wait)).await;
+        }
+        send_delivery(client, hook, &mut delivery).await;
+        delivery = record(delivery).await?;
+    }
+    Ok(delivery)
+}
+
+fn spawn_delivery(pool: PgPool, client: reqwest::Client, hook: Webhook, delivery: WebhookDelivery) {
+    rocket::tokio::spawn(async move {
+        let d_id = delivery.id;
+        let record = |x: WebhookDelivery| {
+            let pool = pool.clone();
+            async move {
+                // Without a connection it is left pending for the next startup.
+                let mut conn = pool
+                    .get()
+                    .await
+                    .map_err(|e| NeptisError::InternalError(e.to_string()))?;
+                store_delivery(&mut conn, &x).await
+            }
+        };
+        if let Err(e) = retry_delivery(&client, &hook, delivery, backoff_secs(), record).await {
+            println!("Failed to record webhook delivery {}: {}", d_id, e);
+        }
+    });
+}
+
+async fn fan_out(
+    conn: &mut AsyncPgConnection,
+    pool: &PgPool,
+    client: &reqwest::Client,
+    notice: Notice,
+) -> Result<(), NeptisError> {
+    use crate::schema::webhooks::dsl::*;
+
+    let hooks: Vec<Webhook> = webhooks
+        .filter(
+            enabled
+                .eq(true)
+                .and(owned_by.eq(notice.owner.as_str()).or(is_global.eq(true))),
+        )
+        .get_results(conn)
+        .await?;
+    for hook in hooks.into_iter().filter(|x| x.wants(notice.event)) {
+        let delivery = create_delivery(
+            conn,
+            &hook,
+            notice.owner.as_str(),
+            notice.event,
+            notice.data.clone(),
+        )
+        .await?;
+        spawn_delivery(pool.clone(), client.clone(), hook, delivery);
+    }
+    Ok(())
+}
+
+/// Deletes deliveries older than `keep_days`; zero keeps them forever. Pending ones that
+/// old are never resumed, so nothing still retrying is lost.
+pub async fn purge_deliveries(
+    conn: &mut AsyncPgConnection,
+    keep_days: i64,
+) -> Result<usize, NeptisError> {
+    use crate::schema::webhook_deliveries::dsl::*;
+
+    if keep_days <= 0 {
+        return Ok(0);
+    }
+    Ok(diesel::delete(webhook_deliveries)
+        .filter(create_date.lt(utc_now!() - Duration::days(keep_days)))
+        .execute(conn)
+        .await?)
+}
+
+// Pick up deliveries that were still retrying when the server went down.
+async fn resume_pending(
+    conn: &mut AsyncPgConnection,
+    pool: &PgPool,
+    client: &reqwest::Client,
+) -> Result<usize, NeptisError> {
+    use crate::schema::webhook_deliveries::dsl as d;
+    use crate::schema::webhooks::dsl as w;
+
+    let pending: Vec<(WebhookDelivery, Webhook)> = d::webhook_deliveries
+        .inner_join(w::webhooks)
+        .filter(
+            d::delivered
+                .eq(false)
+                .and(d::attempts.lt(max_attempts()))
+                .and(d::create_date.gt(utc_now!() - Duration::days(1)))
+                .and(w::enabled.eq(true)),
+        )
+        .get_results(conn)
+        .await?;
+    let count = pending.len();
+    for (delivery, hook) in pending {
+        spawn_delivery(pool.clone(), client.clone(), hook, delivery);
+    }
+    Ok(count)
+}
+
+/// Client used for deliveries. Redirects are not followed, as they could lead to an
+/// address `targets::check_url` would have refused.
+pub fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(get_env_or!("WEBHOOK_TIMEOUT", 10)))
+        .dns_resolver(Arc::new(targets::PublicResolver))
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("Failed to build the webhook HTTP client")
+}
+
+/// Starts the task which turns emitted events into deliveries.
+pub fn spawn_dispatcher(pool: PgPool) {
+    let (tx, mut rx) = mpsc::unbounded_channel::<Notice>();
+    if QUEUE.set(tx).is_err() {
+        return; // already running
+    }
+    rocket::tokio::spawn(async move {
+        let client = http_client();
+        if let Ok(mut conn) = pool.get().await {
+            match resume_pending(&mut conn, &pool, &client).await {
+                Ok(0) => {}
+                Ok(n) => println!("Resumed {} pending webhook deliveries", n),
+                Err(e) => println!("Failed to resume webhook deliveries: {}", e),
+            }
+        }
+        while let Some(notice) = rx.recv().await {
+            let Ok(mut conn) = pool.get().await else {
+                continue;
+            };
+            if let Err(e) = fan_out(&mut conn, &pool, &client, notice).await {
+                println!("Failed to queue webhook deliveries: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    use super::*;
+
+    // Answers each request with the next status, handing back its signature and body.
+    fn receiver(statuses: Vec<u16>) -> (u16, std::sync::mpsc::Receiver<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let (mut signature, mut length) = (String::new(), 0);
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((k, v)) = line.split_once(": ") {
+                        match k.to_lowercase().as_str() {
+                            "x-neptis-signature" => signature = v.to_string(),
+                            "content-length" => length = v.parse().unwrap(),
+                            _ => {}
+                        }
+                    }
+                }
+                let mut body = vec![0u8; length];
+                reader.read_exact(&mut body).unwrap();
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {} Whatever\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+                tx.send((signature, String::from_utf8(body).unwrap())).unwrap();
+            }
+        });
+        (port, rx)
+    }
+
+    #[rocket::async_test]
+    async fn signed_delivery_is_retried_after_server_error() {
+        // Loopback is refused unless allowed; `localhost` keeps other tests' literals refused.
+        unsafe { std::env::set_var("WEBHOOK_ALLOWED_HOSTS", "localhost") };
+        let (port, rx) = receiver(vec![503, 200]);
+        let hook = Webhook {
+            id: Uuid::new_v4(),
+            owned_by: "alice".into(),
+            url: format!("http://localhost:{}/hook", port),
+            secret: "s3cret".into(),
+            events: vec![],
+            is_global: false,
+            enabled: true,
+            create_date: utc_now!(),
+        };
+        let delivery = WebhookDelivery {
+            id: Uuid::new_v4(),
+            webhook_id: hook.id,
+            event: "ping".into(),
+            payload: r#"{"event":"ping"}"#.into(),
+            attempts: 0,
+            delivered: false,
+            status_code: None,
+            last_error: None,
+            create_date: utc_now!(),
+            last_attempt: None,
+        };
+
+        let mut recorded = vec![];
+        let done = retry_delivery(&http_client(), &hook, delivery, 0, |x| {
+            recorded.push(x.status_code);
+            async move { Ok(x) }
+        })
+        .await
+        .unwrap();
+
+        assert!(done.delivered);
+        assert_eq!(done.attempts, 2);
+        assert_eq!(recorded, [Some(503), Some(200)]);
+        for _ in 0..2 {
+            let (signature, body) = rx.recv().unwrap();
+            assert_eq!(body, r#"{"event":"ping"}"#);
+            assert_eq!(signature, sign("s3cret", body.as_str()));
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use crate::prelude::model_prelude::*;
+use super::models::{Webhook, WebhookDelivery, WebhookEvent};
+
+#[derive(Serialize, Deserialize)]
+pub struct WebhookDto {
+    pub id: Uuid,
+    pub owned_by: String,
+    pub url: String,
+    /// Key used for the `X-Neptis-Signature` HMAC-SHA256 of each payload.
+    pub secret: String,
+    pub events: Vec<String>,
+    pub is_global: bool,
+    pub enabled: bool,
+    pub create_date: NaiveDateTime
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WebhookDeliveryDto {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    pub event: String,
+    pub payload: String,
+    pub attempts: i32,
+    pub delivered: bool,
+    pub status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub create_date: NaiveDateTime,
+    pub last_attempt: Option<NaiveDateTime>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PostForWebhookApi {
+    pub url: String,
+    /// Events to deliver; every event when left empty.
+    pub events: Option<Vec<WebhookEvent>>,
+    /// Generated when not given.
+    pub secret: Option<String>,
+    /// Receive events for every user's mounts. Admin only.
+    pub global: Option<bool>,
+    pub enabled: Option<bool>
+}
+
+impl WebDtoFrom<Webhook> for WebhookDto {
+    fn try_to_dto(auth_user: &crate::users::models::User, item: Webhook) -> Result<Self, NeptisError>
+    where
+        Self: Serialize + Sized,
+    {
+        if !auth_user.is_admin && auth_user.user_name != item.owned_by {
+            return Err(NeptisError::Unauthorized("You do not have access!".into()));
+        }
+        Ok(Self {
+            id: item.id,
+            owned_by: item.owned_by,
+            url: item.url,
+            secret: item.secret,
+            events: item.events,
+            is_global: item.is_global,
+            enabled: item.enabled,
+            create_date: item.create_date,
+        })
+    }
+}
+
+impl WebDtoFrom<WebhookDelivery> for WebhookDeliveryDto {
+    fn try_to_dto(_auth_user: &crate::users::models::User, item: WebhookDelivery) -> Result<Self, NeptisError>
+    where
+        Self: Serialize + Sized,
+    {
+        // Access is checked against the owning webhook before deliveries are loaded.
+        Ok(Self {
+            id: item.id,
+            webhook_id: item.webhook_id,
+            event: item.event,
+            payload: item.payload,
+            attempts: item.attempts,
+            delivered: item.delivered,
+            status_code: item.status_code,
+            last_error: item.last_error,
+            create_date: item.create_date,
+            last_attempt: item.last_attempt,
+        })
+    }
+}
+
+bind_dto!(Webhook, WebhookDto);
+bind_dto!(WebhookDelivery, WebhookDeliveryDto);
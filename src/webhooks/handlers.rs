@@ -0,0 +1,68 @@
+use super::{actions, dtos::*};
+use crate::prelude::route_prelude::*;
+use uuid::Uuid;
+
+#[get("/")]
+async fn get_all_webhooks(
+    mut conn: Connection<Db>,
+    auth_user: User,
+) -> Result<Json<Vec<WebhookDto>>, NeptisError> {
+    Ok(Json(
+        actions::get_all_webhooks_async(&mut conn, &auth_user).await?,
+    ))
+}
+
+#[post("/", data = "<dto>")]
+async fn create_webhook(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    dto: Json<PostForWebhookApi>,
+) -> Result<Json<WebhookDto>, NeptisError> {
+    Ok(Json(
+        actions::create_webhook_async(&mut conn, &auth_user, dto.into_inner()).await?,
+    ))
+}
+
+#[delete("/<id>")]
+async fn delete_webhook(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    id: Uuid,
+) -> Result<Json<usize>, NeptisError> {
+    Ok(Json(
+        actions::delete_webhook_async(&mut conn, &auth_user, id).await?,
+    ))
+}
+
+#[get("/<id>/deliveries?<limit>")]
+async fn get_deliveries(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    id: Uuid,
+    limit: Option<i64>,
+) -> Result<Json<Vec<WebhookDeliveryDto>>, NeptisError> {
+    Ok(Json(
+        actions::get_deliveries_async(&mut conn, &auth_user, id, limit).await?,
+    ))
+}
+
+#[post("/<id>/test")]
+async fn test_webhook(
+    mut conn: Connection<Db>,
+    auth_user: User,
+    id: Uuid,
+) -> Result<Json<WebhookDeliveryDto>, NeptisError> {
+    Ok(Json(
+        actions::test_webhook_async(&mut conn, &auth_user, id).await?,
+    ))
+}
+
+pub fn get_routes() -> Vec<Route> {
+    routes![
+        get_all_webhooks,
+        create_webhook,
+        delete_webhook,
+        get_deliveries,
+        test_webhook
+    ]
+}
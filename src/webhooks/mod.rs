@@ -0,0 +1,7 @@
+pub mod actions;
+pub mod handlers;
+pub mod models;
+pub mod dtos;
+pub mod dispatch;
+pub mod thresholds;
+pub mod targets;
\ No newline at end of file
@@ -0,0 +1,90 @@
+use super::targets;
+use crate::prelude::model_prelude::*;
+
+#[derive(Insertable, Queryable, Clone, AsChangeset)]
+pub struct Webhook {
+    pub id: Uuid,
+    pub owned_by: String,
+    pub url: String,
+    pub secret: String,
+    pub events: Vec<String>,
+    pub is_global: bool,
+    pub enabled: bool,
+    pub create_date: NaiveDateTime,
+}
+
+#[derive(Insertable, Queryable, Clone, AsChangeset)]
+#[diesel(table_name = webhook_deliveries)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub webhook_id: Uuid,
+    pub event: String,
+    pub payload: String,
+    pub attempts: i32,
+    pub delivered: bool,
+    pub status_code: Option<i32>,
+    pub last_error: Option<String>,
+    pub create_date: NaiveDateTime,
+    pub last_attempt: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WebhookEvent {
+    #[serde(rename = "ping")]
+    Ping,
+    #[serde(rename = "job.started")]
+    JobStarted,
+    #[serde(rename = "job.succeeded")]
+    JobSucceeded,
+    #[serde(rename = "job.failed")]
+    JobFailed,
+    #[serde(rename = "mount.created")]
+    MountCreated,
+    #[serde(rename = "mount.resized")]
+    MountResized,
+    #[serde(rename = "mount.deleted")]
+    MountDeleted,
+    #[serde(rename = "quota.threshold")]
+    QuotaThreshold,
+    #[serde(rename = "mount.stale")]
+    MountStale,
+    #[serde(rename = "mount.recovered")]
+    MountRecovered,
+}
+
+impl WebhookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::Ping => "ping",
+            WebhookEvent::JobStarted => "job.started",
+            WebhookEvent::JobSucceeded => "job.succeeded",
+            WebhookEvent::JobFailed => "job.failed",
+            WebhookEvent::MountCreated => "mount.created",
+            WebhookEvent::MountResized => "mount.resized",
+            WebhookEvent::MountDeleted => "mount.deleted",
+            WebhookEvent::QuotaThreshold => "quota.threshold",
+            WebhookEvent::MountStale => "mount.stale",
+            WebhookEvent::MountRecovered => "mount.recovered",
+        }
+    }
+}
+
+impl Webhook {
+    /// Whether the hook subscribes to an event; an empty list means every event.
+    pub fn wants(&self, event: WebhookEvent) -> bool {
+        self.events.is_empty() || self.events.iter().any(|x| x == event.as_str())
+    }
+}
+
+impl CleanValidate for Webhook {
+    fn validate(mut self) -> Result<Self, ValidateError>
+    where
+        Self: Sized,
+    {
+        trim!(self.url, self.secret);
+        vreq!(self.url, "You must enter a URL!");
+        vcheck!(|| targets::check_url(self.url.as_str()));
+        vmin!(self.secret.len(), 16, "The secret must be at least 16 characters!");
+        Ok(self)
+    }
+}
@@ -0,0 +1,129 @@
+use std::net::{IpAddr, SocketAddr};
+
+use reqwest::Url;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+
+/// Hosts which may be delivered to even though they are private or local, such as an
+/// internal chat relay (`WEBHOOK_ALLOWED_HOSTS`, comma separated).
+fn allowed_hosts() -> Vec<String> {
+    std::env::var("WEBHOOK_ALLOWED_HOSTS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|x| x.trim().trim_matches(['[', ']']).to_lowercase())
+        .filter(|x| !x.is_empty())
+        .collect()
+}
+
+fn is_allowed(host: &str) -> bool {
+    let host = host.trim_matches(['[', ']']).to_lowercase();
+    allowed_hosts().contains(&host)
+}
+
+/// Whether an address is reachable on the public internet, as opposed to loopback,
+/// private, link-local and other reserved ranges.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(x) => {
+            let [a, b, ..] = x.octets();
+            !(x.is_loopback()
+                || x.is_private()
+                || x.is_link_local()
+                || x.is_unspecified()
+                || x.is_broadcast()
+                || x.is_multicast()
+                || x.is_documentation()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)) // carrier-grade NAT
+                || (a == 198 && (b == 18 || b == 19))) // benchmarking
+        }
+        IpAddr::V6(x) => match x.to_ipv4_mapped() {
+            Some(v4) => is_public(IpAddr::V4(v4)),
+            None => {
+                !(x.is_loopback()
+                    || x.is_unspecified()
+                    || x.is_multicast()
+                    || x.is_unique_local()
+                    || x.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+/// Refuses URLs which are not http(s), or whose host is a literal non-public address.
+/// Host names are checked when they are resolved, through `PublicResolver`.
+pub fn check_url(url: &str) -> Result<(), &'static str> {
+    let url = Url::parse(url).map_err(|_| "The URL is not valid!")?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err("The URL must use http or https!");
+    }
+    let host = url.host_str().ok_or("The URL must have a host!")?;
+    if is_allowed(host) {
+        return Ok(());
+    }
+    match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(ip) if !is_public(ip) => Err("The URL must not point at a private or local address!"),
+        _ => Ok(()),
+    }
+}
+
+/// Resolves webhook hosts, dropping every address which is not public so deliveries
+/// cannot be aimed at the server's own network.
+pub struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let found: Vec<SocketAddr> = rocket::tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .collect();
+            let allowed = is_allowed(host.as_str());
+            let addrs: Vec<SocketAddr> = found
+                .into_iter()
+                .filter(|x| allowed || is_public(x.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} does not resolve to a public address", host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_addresses() {
+        for x in ["1.1.1.1", "93.184.216.34", "2606:4700:4700::1111"] {
+            assert!(is_public(x.parse().unwrap()), "{}", x);
+        }
+        for x in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(x.parse().unwrap()), "{}", x);
+        }
+    }
+
+    #[test]
+    fn literal_hosts_are_checked() {
+        assert!(check_url("https://example.com/hook").is_ok());
+        assert!(check_url("http://8.8.8.8:8080/x").is_ok());
+        assert!(check_url("http://127.0.0.1/").is_err());
+        assert!(check_url("http://[::1]:80/").is_err());
+        assert!(check_url("http://169.254.169.254/latest/meta-data").is_err());
+        assert!(check_url("ftp://example.com/").is_err());
+        assert!(check_url("not a url").is_err());
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::HashSet;
+
+use rocket::serde::json::serde_json::json;
+use rocket_db_pools::diesel::PgPool;
+
+use super::dispatch;
+use super::models::WebhookEvent;
+use crate::mounts::models::Mount;
+use crate::mounts::storage;
+use crate::prelude::action_prelude::*;
+
+/// Percentage of a mount's quota which raises `quota.threshold` when crossed.
+fn threshold_percent() -> f64 {
+    get_env_or!("WEBHOOK_QUOTA_PERCENT", 90.0)
+}
+
+// Raise an event for every area that has gone over the threshold since the last pass.
+// Areas are re-armed once they fall back below it.
+fn check_point(point: &Mount, over: &mut HashSet<(String, String, &'static str)>) {
+    for (area, img_path, mnt_path, max_bytes) in [
+        ("data", &point.data_img_path, &point.data_mnt_path, point.data_max_bytes),
+        ("repo", &point.repo_img_path, &point.repo_mnt_path, point.repo_max_bytes),
+    ] {
+        // Points which are not mounted are left alone rather than mounted just to be measured.
+        let storage = storage::for_point(point);
+        if max_bytes <= 0 || !storage.is_mounted(img_path, mnt_path) {
+            continue;
+        }
+        let Ok(stats) = storage.usage(img_path, mnt_path) else {
+            continue;
+        };
+        let percent = stats.b_used as f64 * 100.0 / max_bytes as f64;
+        let key = (point.owned_by.clone(), point.mount_name.clone(), area);
+        if percent < threshold_percent() {
+            over.remove(&key);
+        } else if over.insert(key) {
+            dispatch::emit(
+                point.owned_by.as_str(),
+                WebhookEvent::QuotaThreshold,
+                json!({
+                    "mount": point.mount_name,
+                    "area": area,
+                    "used_bytes": stats.b_used,
+                    "max_bytes": max_bytes,
+                    "percent": percent,
+                }),
+            );
+        }
+    }
+}
+
+/// Spawns the background task which periodically compares mount usage to the threshold.
+pub fn spawn_quota_watch(pool: PgPool) {
+    let interval_secs: u64 = get_env_or!("WEBHOOK_QUOTA_INTERVAL", 600);
+    if interval_secs == 0 {
+        return;
+    }
+    rocket::tokio::spawn(async move {
+        let mut over = HashSet::new();
+        let mut timer = rocket::tokio::time::interval(std::time::Duration::from_secs(interval_secs));
+        loop {
+            timer.tick().await;
+            let points: Vec<Mount> = {
+                use crate::schema::mounts::dsl::*;
+                let Ok(mut conn) = pool.get().await else {
+                    continue;
+                };
+                match mounts.filter(locked.eq(false)).get_results(&mut conn).await {
+                    Ok(x) => x,
+                    Err(_) => continue,
+                }
+            };
+            // `df` and `mount` are shelled out to, so keep them off the executor.
+            over = rocket::tokio::task::spawn_blocking(move || {
+                for point in points.iter() {
+                    check_point(point, &mut over);
+                }
+                over
+            })
+            .await
+            .unwrap_or_default();
+        }
+    });
+}